@@ -0,0 +1,7 @@
+fn main() {
+    // `self update` picks the release artifact built for the same target.
+    println!(
+        "cargo:rustc-env=BEVY_PATCH_TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
+}
@@ -0,0 +1,619 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::{Range, RangeInclusive};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::{atomic, is_bevy_crate};
+
+// Everything between the markers belongs to bevy-patch and is rewritten as a whole.
+pub const BEGIN: &str = "# bevy-patch-begin";
+pub const END: &str = "# bevy-patch-end";
+
+const HEADER: &str = "[patch.crates-io]";
+
+// Prefix of the block's lines while `toggle` has it off.
+const OFF: &str = "#~ ";
+
+// Lines keep their own terminator, so a file with mixed line endings is written back as it was.
+fn split_lines(content: &str) -> Vec<String> {
+    content.split_inclusive('\n').map(str::to_owned).collect()
+}
+
+fn body(line: &str) -> &str {
+    line.trim_end_matches(['\r', '\n'])
+}
+
+fn eol(line: &str) -> &str {
+    &line[body(line).len()..]
+}
+
+// Line indices of the begin and end markers.
+fn marked_block(lines: &[String]) -> Result<Option<(usize, usize)>> {
+    let begin = lines.iter().position(|line| line.trim() == BEGIN);
+    let end = lines.iter().position(|line| line.trim() == END);
+    match (begin, end) {
+        (None, None) => Ok(None),
+        (Some(begin), Some(end)) if begin < end => Ok(Some((begin, end))),
+        _ => anyhow::bail!("{BEGIN} and {END} don't enclose a block"),
+    }
+}
+
+// The line ending new lines get when `line` has none to copy.
+fn newline(content: &str) -> &'static str {
+    if content.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+// Puts back the final newline, or its absence, after lines were added or dropped at the end.
+fn keep_final_newline(lines: &mut [String], had_final_newline: bool, nl: &str) {
+    if let Some(last) = lines.last_mut() {
+        let len = body(last).len();
+        if !had_final_newline {
+            last.truncate(len);
+        } else if len == last.len() {
+            last.push_str(nl);
+        }
+    }
+}
+
+// A `[patch.crates-io]` entry as it's written in the manifest.
+struct Entry {
+    name: String,
+    // The lines from the key to the end of the value.
+    lines: RangeInclusive<usize>,
+    // The comment lines right above the key.
+    comments: Range<usize>,
+    // What follows the value on its last line, ` # pinned` say.
+    trailing: Option<String>,
+}
+
+fn entries(content: &str, lines: &[String]) -> Result<Vec<Entry>> {
+    let manifest = toml_edit::Document::parse(content)?;
+    let Some(patches) = manifest
+        .get("patch")
+        .and_then(|patch| patch.get("crates-io"))
+        .and_then(|patches| patches.as_table_like())
+    else {
+        return Ok(Vec::new());
+    };
+    let starts: Vec<usize> = lines
+        .iter()
+        .scan(0, |offset, line| {
+            let start = *offset;
+            *offset += line.len();
+            Some(start)
+        })
+        .collect();
+    let line_of = |offset: usize| starts.partition_point(|&start| start <= offset) - 1;
+
+    let mut entries = Vec::new();
+    for (name, _) in patches.iter() {
+        let (key, item) = patches.get_key_value(name).expect("iterated key");
+        let (Some(key), Some(value)) = (key.span(), item.as_value().and_then(|value| value.span()))
+        else {
+            anyhow::bail!(
+                "[patch.crates-io.{name}] is a table, write it as `{name} = {{ ... }}` instead"
+            );
+        };
+        let first = line_of(key.start);
+        let last = line_of(value.end);
+        let rest = &body(&lines[last])[value.end - starts[last]..];
+        let mut above = first;
+        while above > 0 && {
+            let line = lines[above - 1].trim();
+            line.starts_with('#') && line != BEGIN && line != END
+        } {
+            above -= 1;
+        }
+        entries.push(Entry {
+            name: name.to_owned(),
+            lines: first..=last,
+            comments: above..first,
+            trailing: rest.trim().starts_with('#').then(|| rest.to_owned()),
+        });
+    }
+    Ok(entries)
+}
+
+// The names of the entries between the markers.
+pub fn block_entries(content: &str) -> Result<Vec<String>> {
+    let lines = split_lines(content);
+    let Some((begin, end)) = marked_block(&lines)? else {
+        return Ok(Vec::new());
+    };
+    Ok(entries(content, &lines)?
+        .into_iter()
+        .filter(|entry| begin < *entry.lines.start() && *entry.lines.end() < end)
+        .map(|entry| entry.name)
+        .collect())
+}
+
+// The marked block as `apply` takes it, to put it back later. Trailing comments are left
+// out, `apply` carries them over from the block it replaces.
+pub fn marked_patch(content: &str) -> Result<Option<String>> {
+    let mut lines = split_lines(content);
+    let Some((begin, end)) = marked_block(&lines)? else {
+        return Ok(None);
+    };
+    for entry in entries(content, &lines)? {
+        if let Some(trailing) = entry.trailing {
+            let last = &mut lines[*entry.lines.end()];
+            let len = body(last).len() - trailing.len();
+            last.truncate(len);
+        }
+    }
+    let block: String = lines[begin + 1..end]
+        .iter()
+        .map(|line| format!("{}\n", body(line)))
+        .collect();
+    Ok(Some(format!("{HEADER}\n{block}")))
+}
+
+// The block for `generated`, each entry keeping the trailing comment it had.
+fn block(generated: &str, trailing: &HashMap<String, String>, nl: &str) -> Vec<String> {
+    let mut block = vec![format!("{BEGIN}{nl}")];
+    for line in generated.lines().filter(|line| line.trim() != HEADER) {
+        let name = line
+            .split_once('=')
+            .map(|(name, _)| name.trim().trim_matches('"'));
+        match name.and_then(|name| trailing.get(name)) {
+            Some(comment) if !line.starts_with('#') => block.push(format!("{line}{comment}{nl}")),
+            _ => block.push(format!("{line}{nl}")),
+        }
+    }
+    block.push(format!("{END}{nl}"));
+    block
+}
+
+// Replaces the marked block with `generated`, the `[patch.crates-io]` output of bevy-patch.
+// Without markers, the block goes right under the `[patch.crates-io]` header and takes over
+// the bevy crates, as with `--frozen`, and whatever else it defines. Only the lines of the
+// block and of the entries it takes over change: their trailing comments follow them into
+// the block and the comments above them go right above it.
+pub fn apply_block(content: &str, generated: &str) -> Result<String> {
+    let generated_entries: toml::Table = toml::from_str(generated)
+        .ok()
+        .and_then(|generated: toml::Table| {
+            generated
+                .get("patch")?
+                .get("crates-io")?
+                .as_table()
+                .cloned()
+        })
+        .ok_or_else(|| anyhow::anyhow!("the generated entries aren't a [patch.crates-io] table"))?;
+    let had_final_newline = content.is_empty() || content.ends_with('\n');
+    let mut lines = split_lines(content);
+
+    match marked_block(&lines)? {
+        Some((begin, end)) => {
+            let trailing = entries(content, &lines)?
+                .into_iter()
+                .filter(|entry| begin < *entry.lines.start() && *entry.lines.end() < end)
+                .filter_map(|entry| Some((entry.name, entry.trailing?)))
+                .collect();
+            let nl = match eol(&lines[begin]) {
+                "" => newline(content),
+                nl => nl,
+            };
+            let block = block(generated, &trailing, nl);
+            lines.splice(begin..=end, block);
+        }
+        None => {
+            let owned: Vec<Entry> = entries(content, &lines)?
+                .into_iter()
+                .filter(|entry| {
+                    is_bevy_crate(&entry.name) || generated_entries.contains_key(&entry.name)
+                })
+                .collect();
+            let mut dropped = HashSet::new();
+            let mut moved = Vec::new();
+            let mut trailing = HashMap::new();
+            for entry in &owned {
+                moved.extend(entry.comments.clone());
+                dropped.extend(entry.comments.clone());
+                dropped.extend(entry.lines.clone());
+                if let Some(comment) = &entry.trailing {
+                    trailing.insert(entry.name.clone(), comment.clone());
+                }
+            }
+            moved.sort();
+            let moved: Vec<String> = moved.into_iter().map(|line| lines[line].clone()).collect();
+            lines = lines
+                .into_iter()
+                .enumerate()
+                .filter(|(line, _)| !dropped.contains(line))
+                .map(|(_, line)| line)
+                .collect();
+
+            let header = lines.iter().position(|line| {
+                line.trim()
+                    .strip_prefix(HEADER)
+                    .is_some_and(|rest| rest.trim().is_empty() || rest.trim().starts_with('#'))
+            });
+            let at = match header {
+                Some(header) => header + 1,
+                None => {
+                    let nl = newline(content);
+                    if let Some(last) = lines.last_mut()
+                        && eol(last).is_empty()
+                    {
+                        last.push_str(nl);
+                    }
+                    if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                        lines.push(nl.to_owned());
+                    }
+                    lines.push(format!("{HEADER}{nl}"));
+                    lines.len()
+                }
+            };
+            let nl = match eol(&lines[at - 1]) {
+                "" => newline(content),
+                nl => nl,
+            }
+            .to_owned();
+            if eol(&lines[at - 1]).is_empty() {
+                lines[at - 1].push_str(&nl);
+            }
+            let block = block(generated, &trailing, &nl);
+            lines.splice(at..at, moved.into_iter().chain(block));
+        }
+    }
+    keep_final_newline(&mut lines, had_final_newline, newline(content));
+
+    let result = lines.concat();
+    toml::from_str::<toml::Table>(&result)
+        .context("The manifest wouldn't parse with the new entries")?;
+    Ok(result)
+}
+
+// Drops the marked block, markers included. Every other byte is kept.
+pub fn remove_block(content: &str) -> Result<Option<String>> {
+    let mut lines = split_lines(content);
+    let Some((begin, end)) = marked_block(&lines)? else {
+        return Ok(None);
+    };
+    lines.drain(begin..=end);
+    keep_final_newline(&mut lines, content.ends_with('\n'), newline(content));
+    Ok(Some(lines.concat()))
+}
+
+// Whether the marked block is commented out by `toggle`, `None` without a block.
+pub fn block_is_on(content: &str) -> Result<Option<bool>> {
+    let lines = split_lines(content);
+    let Some((begin, end)) = marked_block(&lines)? else {
+        return Ok(None);
+    };
+    Ok(Some(
+        !lines[begin + 1..end]
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .all(|line| line.starts_with(OFF)),
+    ))
+}
+
+// Comments out the lines between the markers, or uncomments them. Blank lines are kept as they
+// are, so are the markers and everything outside them.
+pub fn toggle_block(content: &str, on: bool) -> Result<Option<String>> {
+    let mut lines = split_lines(content);
+    let Some((begin, end)) = marked_block(&lines)? else {
+        return Ok(None);
+    };
+    for line in &mut lines[begin + 1..end] {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if on {
+            if let Some(rest) = line.strip_prefix(OFF) {
+                *line = rest.to_owned();
+            }
+        } else if !line.starts_with(OFF) {
+            line.insert_str(0, OFF);
+        }
+    }
+    Ok(Some(lines.concat()))
+}
+
+fn state(on: bool) -> &'static str {
+    if on { "on" } else { "off" }
+}
+
+// Turns the block on or off, `None` flips it.
+pub fn toggle(cargo_toml: &Path, on: Option<bool>) -> Result<()> {
+    let mut turned = None;
+    atomic::edit(cargo_toml, |content| {
+        let Some(was_on) = block_is_on(content)? else {
+            anyhow::bail!("{} has no {BEGIN} block", cargo_toml.display());
+        };
+        let on = on.unwrap_or(!was_on);
+        turned = Some((on, was_on != on));
+        Ok(toggle_block(content, on)?.unwrap_or_else(|| content.to_owned()))
+    })?;
+    match turned {
+        Some((on, true)) => eprintln!(
+            "note: the patch in {} is now {}",
+            cargo_toml.display(),
+            state(on)
+        ),
+        Some((on, false)) => eprintln!(
+            "note: the patch in {} is already {}",
+            cargo_toml.display(),
+            state(on)
+        ),
+        None => {}
+    }
+    Ok(())
+}
+
+// The state of the block and the entries in it.
+pub fn status(cargo_toml: &Path) -> Result<String> {
+    let content = std::fs::read_to_string(cargo_toml)
+        .with_context(|| format!("Failed to read {}", cargo_toml.display()))?;
+    let Some(on) = block_is_on(&content)? else {
+        return Ok(format!("{}: no {BEGIN} block", cargo_toml.display()));
+    };
+    let active = toggle_block(&content, true)?.unwrap_or_default();
+    Ok(format!(
+        "{}: patch {}, {} entries",
+        cargo_toml.display(),
+        state(on),
+        block_entries(&active)?.len()
+    ))
+}
+
+pub fn apply(cargo_toml: &Path, generated: &str) -> Result<()> {
+    if atomic::edit(cargo_toml, |content| apply_block(content, generated))? {
+        eprintln!("note: wrote the patch entries to {}", cargo_toml.display());
+    } else {
+        eprintln!("note: {} is up to date", cargo_toml.display());
+    }
+    Ok(())
+}
+
+pub fn remove(cargo_toml: &Path) -> Result<()> {
+    let removed = atomic::edit(cargo_toml, |content| {
+        Ok(remove_block(content)?.unwrap_or_else(|| content.to_owned()))
+    })?;
+    if removed {
+        eprintln!(
+            "note: removed the patch entries from {}",
+            cargo_toml.display()
+        );
+    } else {
+        eprintln!("note: {} has no {BEGIN} block", cargo_toml.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"[package]
+name = "game"
+
+[patch.crates-io]
+bevy = { path = "../old" }
+other = { path = "../other" } # kept
+"#;
+
+    const GENERATED: &str = r#"[patch.crates-io]
+# Bevy Patch
+bevy = { git = "https://github.com/bevyengine/bevy", branch = "main" }
+bevy_ecs = { git = "https://github.com/bevyengine/bevy", branch = "main" }"#;
+
+    #[test]
+    fn applying_twice_changes_nothing() {
+        let once = apply_block(MANIFEST, GENERATED).unwrap();
+        assert_eq!(
+            once,
+            r#"[package]
+name = "game"
+
+[patch.crates-io]
+# bevy-patch-begin
+# Bevy Patch
+bevy = { git = "https://github.com/bevyengine/bevy", branch = "main" }
+bevy_ecs = { git = "https://github.com/bevyengine/bevy", branch = "main" }
+# bevy-patch-end
+other = { path = "../other" } # kept
+"#
+        );
+        assert_eq!(apply_block(&once, GENERATED).unwrap(), once);
+    }
+
+    #[test]
+    fn toggling_comments_out_the_block_only() {
+        let on = apply_block(MANIFEST, GENERATED).unwrap();
+        assert_eq!(block_is_on(&on).unwrap(), Some(true));
+
+        let off = toggle_block(&on, false).unwrap().unwrap();
+        assert!(
+            off.contains("# bevy-patch-begin\n#~ # Bevy Patch\n#~ bevy = "),
+            "{off}"
+        );
+        assert!(off.contains("# bevy-patch-end\nother = { path = \"../other\" } # kept\n"));
+        assert_eq!(block_is_on(&off).unwrap(), Some(false));
+        assert_eq!(toggle_block(&off, false).unwrap().unwrap(), off);
+        let patches: toml::Table = toml::from_str(&off).unwrap();
+        assert!(patches["patch"]["crates-io"].get("bevy").is_none());
+
+        assert_eq!(toggle_block(&off, true).unwrap().unwrap(), on);
+        assert_eq!(block_is_on(MANIFEST).unwrap(), None);
+    }
+
+    #[test]
+    fn virtual_manifests_get_a_patch_table() {
+        let manifest = "[workspace]\nmembers = [\"crates/*\"]\n";
+        let once = apply_block(manifest, GENERATED).unwrap();
+        assert!(
+            once.starts_with(
+                "[workspace]\nmembers = [\"crates/*\"]\n\n[patch.crates-io]\n# bevy-patch-begin\n"
+            ),
+            "{once}"
+        );
+        assert_eq!(
+            remove_block(&once).unwrap().unwrap(),
+            format!("{manifest}\n[patch.crates-io]\n")
+        );
+    }
+
+    #[test]
+    fn renamed_crates_dont_linger() {
+        let once = apply_block(MANIFEST, GENERATED).unwrap();
+        let renamed = GENERATED.replace("bevy_ecs =", "bevy_ecs_renamed =");
+        let twice = apply_block(&once, &renamed).unwrap();
+        assert!(!twice.contains("bevy_ecs ="));
+        assert!(twice.contains("bevy_ecs_renamed ="));
+    }
+
+    #[test]
+    fn markers_survive_toml_edit() {
+        let applied = apply_block(MANIFEST, GENERATED).unwrap();
+        let mut manifest: toml_edit::DocumentMut = applied.parse().unwrap();
+        manifest["patch"]["crates-io"]["other"]["path"] = toml_edit::value("../moved");
+        let edited = manifest.to_string();
+        assert!(edited.contains(BEGIN) && edited.contains(END));
+
+        let removed = remove_block(&edited).unwrap().unwrap();
+        assert_eq!(
+            removed,
+            r#"[package]
+name = "game"
+
+[patch.crates-io]
+other = { path = "../moved" } # kept
+"#
+        );
+    }
+
+    #[test]
+    fn comments_are_kept() {
+        let manifest = r#"[patch.crates-io]
+other = { path = "../other" }
+# TODO drop once 0.15 is out
+bevy = { path = "../old" } # pinned
+"#;
+        let once = apply_block(manifest, GENERATED).unwrap();
+        assert_eq!(
+            once,
+            r#"[patch.crates-io]
+# TODO drop once 0.15 is out
+# bevy-patch-begin
+# Bevy Patch
+bevy = { git = "https://github.com/bevyengine/bevy", branch = "main" } # pinned
+bevy_ecs = { git = "https://github.com/bevyengine/bevy", branch = "main" }
+# bevy-patch-end
+other = { path = "../other" }
+"#
+        );
+        assert_eq!(apply_block(&once, GENERATED).unwrap(), once);
+    }
+
+    #[test]
+    fn mixed_line_endings_are_kept() {
+        let manifest = "[package]\r\nname = \"game\"\n\n[patch.crates-io]\n\
+            bevy = { path = \"../old\" }\r\nother = { path = \"../other\" }\r\n";
+        let applied = apply_block(manifest, GENERATED).unwrap();
+        assert_eq!(
+            applied,
+            "[package]\r\nname = \"game\"\n\n[patch.crates-io]\n\
+             # bevy-patch-begin\n# Bevy Patch\n\
+             bevy = { git = \"https://github.com/bevyengine/bevy\", branch = \"main\" }\n\
+             bevy_ecs = { git = \"https://github.com/bevyengine/bevy\", branch = \"main\" }\n\
+             # bevy-patch-end\nother = { path = \"../other\" }\r\n"
+        );
+        assert_eq!(
+            remove_block(&applied).unwrap().unwrap(),
+            "[package]\r\nname = \"game\"\n\n[patch.crates-io]\nother = { path = \"../other\" }\r\n"
+        );
+    }
+
+    #[test]
+    fn a_missing_final_newline_stays_missing() {
+        let manifest = "[patch.crates-io]\nbevy = { path = \"../old\" }";
+        let applied = apply_block(manifest, GENERATED).unwrap();
+        assert!(applied.ends_with(END), "{applied:?}");
+        assert_eq!(apply_block(&applied, GENERATED).unwrap(), applied);
+        assert_eq!(
+            remove_block(&applied).unwrap().unwrap(),
+            "[patch.crates-io]"
+        );
+
+        let applied = apply_block("[package]\nname = \"game\"", GENERATED).unwrap();
+        assert!(applied.starts_with("[package]\nname = \"game\"\n\n[patch.crates-io]\n"));
+        assert!(applied.ends_with(END), "{applied:?}");
+    }
+
+    #[test]
+    fn comments_outside_the_block_survive_apply_and_remove() {
+        let manifest = r#"[package]
+name = "game" # the game
+
+# Local forks
+[patch.crates-io] # keep sorted
+# our fork
+other = { path = "../other" } # kept
+# TODO drop once 0.15 is out
+bevy_ecs = { path = "../old/crates/bevy_ecs" } # pinned
+
+# trailer
+"#;
+        let applied = apply_block(manifest, GENERATED).unwrap();
+        assert!(applied.contains(
+            "bevy_ecs = { git = \"https://github.com/bevyengine/bevy\", branch = \"main\" } # pinned\n"
+        ));
+        // Changed entries keep their trailing comment on the next apply.
+        let moved = GENERATED.replace("branch = \"main\"", "tag = \"v0.15.0\"");
+        let reapplied = apply_block(&applied, &moved).unwrap();
+        assert!(reapplied.contains(
+            "bevy_ecs = { git = \"https://github.com/bevyengine/bevy\", tag = \"v0.15.0\" } # pinned\n"
+        ));
+
+        assert_eq!(
+            remove_block(&reapplied).unwrap().unwrap(),
+            r#"[package]
+name = "game" # the game
+
+# Local forks
+[patch.crates-io] # keep sorted
+# TODO drop once 0.15 is out
+# our fork
+other = { path = "../other" } # kept
+
+# trailer
+"#
+        );
+    }
+
+    #[test]
+    fn the_manifest_is_written_back_byte_for_byte() {
+        let dir = std::env::temp_dir().join(format!("bevy-patch-apply-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cargo_toml = dir.join("Cargo.toml");
+        let manifest = "[package]\r\nname = \"game\"\r\n\r\n[patch.crates-io]\r\n\
+            bevy = { path = \"../old\" } # pinned\r\nother = { path = \"../other\" }";
+        std::fs::write(&cargo_toml, manifest).unwrap();
+
+        apply(&cargo_toml, GENERATED).unwrap();
+        let applied = std::fs::read_to_string(&cargo_toml).unwrap();
+        assert_eq!(
+            applied,
+            "[package]\r\nname = \"game\"\r\n\r\n[patch.crates-io]\r\n\
+             # bevy-patch-begin\r\n# Bevy Patch\r\n\
+             bevy = { git = \"https://github.com/bevyengine/bevy\", branch = \"main\" } # pinned\r\n\
+             bevy_ecs = { git = \"https://github.com/bevyengine/bevy\", branch = \"main\" }\r\n\
+             # bevy-patch-end\r\nother = { path = \"../other\" }"
+        );
+
+        remove(&cargo_toml).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&cargo_toml).unwrap(),
+            "[package]\r\nname = \"game\"\r\n\r\n[patch.crates-io]\r\nother = { path = \"../other\" }"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
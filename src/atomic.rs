@@ -0,0 +1,107 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+// How long to wait for another bevy-patch run editing the same directory.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+const LOCK_FILE: &str = ".bevy-patch.lock";
+
+// Held for a read-modify-write cycle, the advisory lock is released when the file closes.
+pub struct DirLock {
+    _file: File,
+}
+
+fn dir_of(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+// Locks the sentinel next to `path`, concurrent runs editing files there take turns.
+pub fn lock(path: &Path) -> Result<DirLock> {
+    let dir = dir_of(path);
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let sentinel = dir.join(LOCK_FILE);
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&sentinel)
+        .with_context(|| format!("Failed to open {}", sentinel.display()))?;
+
+    let started = Instant::now();
+    let mut waiting = false;
+    loop {
+        match fs4::FileExt::try_lock(&file) {
+            Ok(()) => return Ok(DirLock { _file: file }),
+            Err(fs4::TryLockError::WouldBlock) => {}
+            Err(fs4::TryLockError::Error(err)) => {
+                return Err(err).with_context(|| format!("Failed to lock {}", sentinel.display()));
+            }
+        }
+        if !waiting {
+            eprintln!(
+                "note: waiting for another bevy-patch process to finish with {}",
+                dir.display()
+            );
+            waiting = true;
+        }
+        if started.elapsed() > LOCK_TIMEOUT {
+            anyhow::bail!(
+                "Another bevy-patch process held {} for over {}s",
+                sentinel.display(),
+                LOCK_TIMEOUT.as_secs()
+            );
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+// The read-modify-write cycle of the manifest edits: `edit` gets the file as it is and returns
+// it as it should be, the file is only written when that differs. Returns whether it did.
+pub fn edit(path: &Path, edit: impl FnOnce(&str) -> Result<String>) -> Result<bool> {
+    let _lock = lock(path)?;
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let updated = edit(&content).with_context(|| format!("Failed to edit {}", path.display()))?;
+    if updated == content {
+        return Ok(false);
+    }
+    write(path, updated)?;
+    Ok(true)
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    dir_of(path).join(format!(".{name}.{}.tmp", std::process::id()))
+}
+
+// Writes a temp file in the same directory, syncs it and renames it over `path`, so a crash
+// leaves either the old or the new file. The original's permissions are kept.
+pub fn write(path: &Path, content: impl AsRef<[u8]>) -> Result<()> {
+    let temp = temp_path(path);
+    let result = (|| {
+        let mut file = File::create(&temp)?;
+        file.write_all(content.as_ref())?;
+        if let Ok(metadata) = std::fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        std::fs::rename(&temp, path)
+    })();
+    if let Err(err) = result {
+        let _ = std::fs::remove_file(&temp);
+        return Err(err).with_context(|| format!("Failed to write {}", path.display()));
+    }
+
+    // The rename itself only survives a crash once the directory is synced.
+    #[cfg(unix)]
+    if let Ok(dir) = File::open(dir_of(path)) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
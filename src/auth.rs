@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use serde::Deserialize;
+
+const DEVICE_CODE_URL: &str = "https://github.com/login/device/code";
+const ACCESS_TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
+const KEYRING_SERVICE: &str = "bevy-patch";
+const KEYRING_USER: &str = "github";
+
+// Builds can bake in the client id of their own OAuth app.
+const BUILTIN_CLIENT_ID: Option<&str> = option_env!("BEVY_PATCH_GITHUB_CLIENT_ID");
+
+const OAUTH_APP_HELP: &str = "\
+No OAuth app client id, set BEVY_PATCH_GITHUB_CLIENT_ID or pass --client-id.
+To create an OAuth app:
+  1. Open https://github.com/settings/applications/new
+  2. Use any homepage and callback URL, e.g. http://localhost
+  3. Tick \"Enable Device Flow\" and register the application
+  4. Copy its client id, device flow doesn't need the client secret";
+
+#[derive(Subcommand)]
+pub enum AuthCommand {
+    /// Log in through GitHub's device flow and keep the token in the OS keychain
+    DeviceLogin {
+        #[arg(long, env = "BEVY_PATCH_GITHUB_CLIENT_ID")]
+        client_id: Option<String>,
+    },
+    /// Remove the token stored by device-login
+    Logout,
+}
+
+#[derive(Deserialize)]
+struct DeviceCode {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    expires_in: u64,
+    interval: u64,
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+fn keyring_entry() -> Result<keyring::Entry> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER).context("Failed to open the OS keychain")
+}
+
+// The token saved by `auth device-login`, if any.
+pub fn stored_token() -> Option<String> {
+    keyring_entry().ok()?.get_password().ok()
+}
+
+fn post<T: serde::de::DeserializeOwned>(url: &str, form: &[(&str, &str)]) -> Result<T> {
+    crate::http::client()
+        .post(url)
+        .timeout(Duration::from_secs(10))
+        .header("User-Agent", "bevy-patch")
+        .header("Accept", "application/json")
+        .form(form)
+        .send()
+        .with_context(|| format!("Failed to reach {url}"))?
+        .json()
+        .with_context(|| format!("Failed to parse the response of {url}"))
+}
+
+fn device_login(client_id: &str) -> Result<()> {
+    let code: DeviceCode = post(
+        DEVICE_CODE_URL,
+        &[("client_id", client_id), ("scope", "public_repo")],
+    )?;
+    eprintln!(
+        "Open {} and enter the code {}",
+        code.verification_uri, code.user_code
+    );
+
+    let mut interval = Duration::from_secs(code.interval);
+    let mut waited = Duration::ZERO;
+    while waited < Duration::from_secs(code.expires_in) {
+        std::thread::sleep(interval);
+        waited += interval;
+
+        let token: AccessToken = post(
+            ACCESS_TOKEN_URL,
+            &[
+                ("client_id", client_id),
+                ("device_code", &code.device_code),
+                ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+            ],
+        )?;
+        match (token.access_token, token.error.as_deref()) {
+            (Some(token), _) => {
+                keyring_entry()?
+                    .set_password(&token)
+                    .context("Failed to store the token in the OS keychain")?;
+                eprintln!("Logged in, the token is stored in the OS keychain");
+                return Ok(());
+            }
+            (None, Some("authorization_pending")) => {}
+            // GitHub asks to poll 5 seconds slower every time this comes back.
+            (None, Some("slow_down")) => interval += Duration::from_secs(5),
+            (None, error) => anyhow::bail!(
+                "Device login failed: {}",
+                token
+                    .error_description
+                    .as_deref()
+                    .or(error)
+                    .unwrap_or("no token in the response")
+            ),
+        }
+    }
+
+    anyhow::bail!("The code expired before the login was completed")
+}
+
+pub fn run(command: &AuthCommand) -> Result<()> {
+    match command {
+        AuthCommand::DeviceLogin { client_id } => {
+            let client_id = client_id
+                .as_deref()
+                .or(BUILTIN_CLIENT_ID)
+                .filter(|id| !id.is_empty())
+                .ok_or_else(|| anyhow::anyhow!(OAUTH_APP_HELP))?;
+            device_login(client_id)
+        }
+        AuthCommand::Logout => match keyring_entry()?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(err) => Err(err).context("Failed to remove the token from the OS keychain"),
+        },
+    }
+}
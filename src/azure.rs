@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::{GitReference, encode_query};
+
+// Azure DevOps takes a personal access token as the password of basic auth, the user is ignored.
+pub struct Azure {
+    pub client: reqwest::blocking::Client,
+    pub token: Option<String>,
+    pub timeout: Duration,
+}
+
+#[derive(Deserialize)]
+struct AzureItems {
+    value: Vec<AzureEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AzureEntry {
+    git_object_type: String,
+    path: String,
+}
+
+pub fn is_azure(repo: &str) -> bool {
+    repo.contains("dev.azure.com/")
+}
+
+// https://dev.azure.com/<org>/<project>/_git/<repo> -> (org, project, repo)
+pub fn parse_azure_repo(repo: &str) -> Option<(&str, &str, &str)> {
+    let path = repo.split_once("dev.azure.com/")?.1;
+    let (org, rest) = path.split_once('/')?;
+    let (project, name) = rest.split_once("/_git/")?;
+    Some((org, project, name.trim_end_matches('/')))
+}
+
+// <org>.visualstudio.com/<project>/_git/<repo> -> dev.azure.com/<org>/<project>/_git/<repo>
+pub fn modern_azure_url(repo: &str) -> Option<String> {
+    let (scheme, path) = repo.split_once("://").unwrap_or(("", repo));
+    let (host, rest) = path.split_once('/')?;
+    let org = host.strip_suffix(".visualstudio.com")?;
+    let scheme = if scheme.is_empty() {
+        String::new()
+    } else {
+        format!("{scheme}://")
+    };
+    Some(format!("{scheme}dev.azure.com/{org}/{rest}"))
+}
+
+fn version_type(reference: &GitReference) -> &'static str {
+    match reference {
+        GitReference::Branch(_) => "branch",
+        GitReference::Tag(_) => "tag",
+        GitReference::Rev(_) => "commit",
+    }
+}
+
+pub fn fetch_crates_from_azure(
+    org: &str,
+    project: &str,
+    repo: &str,
+    crates_root: &str,
+    reference: &GitReference,
+    azure: &Azure,
+) -> Result<Vec<String>> {
+    let url = format!(
+        "https://dev.azure.com/{org}/{project}/_apis/git/repositories/{repo}/items?scopePath=/{}&recursionLevel=OneLevel&versionDescriptor.version={}&versionDescriptor.versionType={}&api-version=7.0",
+        encode_query(crates_root),
+        encode_query(reference.as_str()),
+        version_type(reference)
+    );
+    let mut request = azure
+        .client
+        .get(&url)
+        .timeout(azure.timeout)
+        .header("User-Agent", "bevy-patch");
+    if let Some(token) = &azure.token {
+        request = request.basic_auth("", Some(token));
+    }
+    let response = request
+        .send()
+        .context("Failed to fetch from Azure DevOps")?;
+
+    let status = response.status();
+    if status != 200 {
+        let body = response.text().unwrap_or_default();
+        anyhow::bail!("{}: {}", status.as_u16(), body.trim());
+    }
+
+    let items: AzureItems = response
+        .json()
+        .context("Failed to parse Azure DevOps response")?;
+    let mut crates: Vec<String> = items
+        .value
+        .into_iter()
+        // The scope path itself is listed alongside its children.
+        .filter(|entry| {
+            entry.git_object_type == "tree" && entry.path.trim_matches('/') != crates_root
+        })
+        .filter_map(|entry| entry.path.rsplit('/').next().map(str::to_owned))
+        .collect();
+
+    crates.sort();
+    Ok(crates)
+}
@@ -0,0 +1,261 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use anyhow::Result;
+use semver::{Version, VersionReq};
+
+use crate::explain::{cargo_metadata, str_field};
+
+fn is_bevy_crate(name: &str) -> bool {
+    name == "bevy" || name.starts_with("bevy_")
+}
+
+// Releases cargo treats as compatible, `0.14.x` is one series and `0.15.x` another.
+fn series(version: &Version) -> String {
+    if version.major == 0 {
+        format!("0.{}", version.minor)
+    } else {
+        version.major.to_string()
+    }
+}
+
+#[derive(Default)]
+struct Series {
+    versions: BTreeSet<Version>,
+    // E.g. `game (bevy ^0.14)`.
+    requirers: BTreeSet<String>,
+}
+
+// The series of every bevy crate resolved from crates.io, with the packages asking for it.
+// Bevy crates requiring their own series are left out, `bevy_render 0.14.2` needing
+// `bevy_ecs 0.14` says nothing about the project.
+fn series_in(metadata: &serde_json::Value) -> BTreeMap<String, Series> {
+    let packages = metadata["packages"].as_array().cloned().unwrap_or_default();
+    let members: BTreeSet<&str> = metadata["workspace_members"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|id| id.as_str())
+        .collect();
+    let version = |package: &serde_json::Value| {
+        str_field(package, "version").and_then(|version| Version::parse(version).ok())
+    };
+    let resolved: Vec<(&str, Version)> = packages
+        .iter()
+        .filter(|package| {
+            str_field(package, "source").is_some_and(|source| source.starts_with("registry+"))
+        })
+        .filter_map(|package| Some((str_field(package, "name")?, version(package)?)))
+        .filter(|(name, _)| is_bevy_crate(name))
+        .collect();
+
+    let mut series_of: BTreeMap<String, Series> = BTreeMap::new();
+    for (_, version) in &resolved {
+        series_of
+            .entry(series(version))
+            .or_default()
+            .versions
+            .insert(version.clone());
+    }
+    for package in &packages {
+        let (Some(name), Some(id)) = (str_field(package, "name"), str_field(package, "id")) else {
+            continue;
+        };
+        let own_series = version(package).map(|version| series(&version));
+        let requirer = match version(package) {
+            Some(_) if members.contains(id) => name.to_owned(),
+            Some(version) => format!("{name} {version}"),
+            None => continue,
+        };
+        for dependency in package["dependencies"].as_array().into_iter().flatten() {
+            let (Some(dependency_name), Some(req)) =
+                (str_field(dependency, "name"), str_field(dependency, "req"))
+            else {
+                continue;
+            };
+            let Ok(parsed) = VersionReq::parse(req) else {
+                continue;
+            };
+            let matched = resolved
+                .iter()
+                .filter(|(resolved_name, version)| {
+                    *resolved_name == dependency_name && parsed.matches(version)
+                })
+                .map(|(_, version)| series(version))
+                .max();
+            let Some(matched) = matched else {
+                continue;
+            };
+            if is_bevy_crate(name) && own_series.as_ref() == Some(&matched) {
+                continue;
+            }
+            series_of
+                .entry(matched)
+                .or_default()
+                .requirers
+                .insert(format!("{requirer} ({dependency_name} {req})"));
+        }
+    }
+    series_of
+}
+
+fn report(series: &BTreeMap<String, Series>) -> String {
+    series
+        .iter()
+        .map(|(series, Series { requirers, .. })| {
+            let requirers = if requirers.is_empty() {
+                "a transitive dependency".to_owned()
+            } else {
+                requirers.iter().cloned().collect::<Vec<_>>().join(", ")
+            };
+            format!("\n  bevy {series}: {requirers}")
+        })
+        .collect()
+}
+
+// Refuses to write a patch into a graph holding several bevy series: the single
+// `[patch.crates-io]` table only replaces the one its version matches, the others keep coming
+// from crates.io and every bevy crate ends up in the graph twice.
+pub fn check(
+    cargo_toml: &Path,
+    allow_multiple: bool,
+    target_version: Option<&VersionReq>,
+    verbose: bool,
+) -> Result<()> {
+    if !cargo_toml.is_file() {
+        return Ok(());
+    }
+    let metadata = match cargo_metadata(cargo_toml) {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            if verbose {
+                eprintln!("debug: skipped the bevy versions check: {err:#}");
+            }
+            return Ok(());
+        }
+    };
+    check_metadata(&metadata, allow_multiple, target_version)
+}
+
+fn check_metadata(
+    metadata: &serde_json::Value,
+    allow_multiple: bool,
+    target_version: Option<&VersionReq>,
+) -> Result<()> {
+    let series = series_in(metadata);
+    if series.len() < 2 {
+        return Ok(());
+    }
+
+    if !allow_multiple {
+        anyhow::bail!(
+            "The project depends on {} bevy versions:{}\n\
+             One [patch.crates-io] table only replaces the version its source matches, the \
+             others keep coming from crates.io and the graph ends up with two copies of every \
+             bevy crate. Move the members to one bevy version, or pass --allow-multiple with \
+             --target-version <REQ> to patch only one of them",
+            series.len(),
+            report(&series)
+        );
+    }
+    let Some(target_version) = target_version else {
+        warning!(
+            "the project depends on {} bevy versions, only the one the patch matches is replaced:{}",
+            series.len(),
+            report(&series)
+        );
+        return Ok(());
+    };
+
+    let target = series
+        .iter()
+        .find(|(_, series)| series.versions.iter().any(|v| target_version.matches(v)))
+        .map(|(series, _)| series)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "--target-version {target_version} matches none of the project's bevy versions:{}",
+                report(&series)
+            )
+        })?;
+    let others: Vec<&str> = series
+        .keys()
+        .filter(|series| *series != target)
+        .map(String::as_str)
+        .collect();
+    eprintln!(
+        "note: patching bevy {target}, bevy {} keeps coming from crates.io",
+        others.join(", ")
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REGISTRY: &str = "registry+https://github.com/rust-lang/crates.io-index";
+
+    fn package(
+        id: &str,
+        name: &str,
+        version: &str,
+        source: Option<&str>,
+        deps: &[(&str, &str)],
+    ) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "name": name,
+            "version": version,
+            "source": source,
+            "dependencies": deps
+                .iter()
+                .map(|(name, req)| serde_json::json!({ "name": name, "req": req }))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    fn metadata() -> serde_json::Value {
+        serde_json::json!({
+            "workspace_members": ["path+file:///ws/game#0.1.0", "path+file:///ws/lab#0.1.0"],
+            "packages": [
+                package("path+file:///ws/game#0.1.0", "game", "0.1.0", None, &[("bevy", "^0.14")]),
+                package("path+file:///ws/lab#0.1.0", "lab", "0.1.0", None, &[("bevy", "^0.15.1")]),
+                package("bevy 0.14.2", "bevy", "0.14.2", Some(REGISTRY), &[("bevy_ecs", "^0.14.2")]),
+                package("bevy_ecs 0.14.2", "bevy_ecs", "0.14.2", Some(REGISTRY), &[]),
+                package("bevy 0.15.1", "bevy", "0.15.1", Some(REGISTRY), &[("bevy_ecs", "^0.15.1")]),
+                package("bevy_ecs 0.15.1", "bevy_ecs", "0.15.1", Some(REGISTRY), &[]),
+            ],
+        })
+    }
+
+    #[test]
+    fn several_versions_are_refused_with_the_members_needing_each() {
+        let err = check_metadata(&metadata(), false, None)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("bevy 0.14: game (bevy ^0.14)"), "{err}");
+        assert!(err.contains("bevy 0.15: lab (bevy ^0.15.1)"), "{err}");
+        assert!(!err.contains("bevy_ecs"), "{err}");
+    }
+
+    #[test]
+    fn target_version_picks_one() {
+        let target = VersionReq::parse("0.15").unwrap();
+        check_metadata(&metadata(), true, Some(&target)).unwrap();
+        check_metadata(&metadata(), true, None).unwrap();
+
+        let target = VersionReq::parse("0.13").unwrap();
+        let err = check_metadata(&metadata(), true, Some(&target))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("matches none"), "{err}");
+    }
+
+    #[test]
+    fn one_version_passes() {
+        let mut metadata = metadata();
+        let packages = metadata["packages"].as_array_mut().unwrap();
+        packages.retain(|package| package["name"] != "lab" && package["version"] != "0.15.1");
+        check_metadata(&metadata, false, None).unwrap();
+    }
+}
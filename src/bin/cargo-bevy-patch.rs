@@ -0,0 +1,23 @@
+use std::process::ExitCode;
+
+// `cargo bevy-patch <args>` runs `cargo-bevy-patch bevy-patch <args>`, which is handed as is to
+// the bevy-patch installed alongside, it recognizes the extra argument.
+fn main() -> ExitCode {
+    let exe = std::env::current_exe()
+        .map(|exe| exe.with_file_name(format!("bevy-patch{}", std::env::consts::EXE_SUFFIX)));
+    let status = exe.and_then(|exe| {
+        std::process::Command::new(exe)
+            .args(std::env::args_os().skip(1))
+            .status()
+    });
+
+    match status {
+        Ok(status) => ExitCode::from(status.code().unwrap_or(1) as u8),
+        Err(err) => {
+            eprintln!(
+                "Error: failed to run bevy-patch, is it installed next to cargo-bevy-patch? {err}"
+            );
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::path::Path;
+use std::process::ExitCode;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::format::Shell;
+use crate::{Github, api_repo_url, apply, atomic, encode_path, parse_args, prompt};
+
+#[derive(Deserialize)]
+struct GithubCompare {
+    total_commits: usize,
+    commits: Vec<CompareCommit>,
+}
+
+#[derive(Deserialize)]
+struct CompareCommit {
+    sha: String,
+    commit: CommitMessage,
+}
+
+#[derive(Deserialize)]
+struct CommitMessage {
+    message: String,
+}
+
+struct Candidate {
+    sha: String,
+    summary: String,
+}
+
+// The commits after `good` up to `bad`, oldest first, `bad` itself last.
+fn commit_range(repo: &str, good: &str, bad: &str, github: &Github) -> Result<Vec<Candidate>> {
+    let compare = format!(
+        "{}/compare/{}...{}",
+        api_repo_url(repo)?,
+        encode_path(good),
+        encode_path(bad)
+    );
+    let mut candidates = Vec::new();
+    for page in 1.. {
+        let range: GithubCompare = github
+            .get(&format!("{compare}?per_page=100&page={page}"))
+            .with_context(|| format!("Failed to compare {good} with {bad} on {repo}"))?;
+        let last_page = range.commits.len() < 100;
+        candidates.extend(range.commits.into_iter().map(|commit| {
+            Candidate {
+                summary: commit
+                    .commit
+                    .message
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .to_owned(),
+                sha: commit.sha,
+            }
+        }));
+        if last_page || candidates.len() >= range.total_commits {
+            break;
+        }
+    }
+    if candidates.is_empty() {
+        anyhow::bail!("{bad} has no commits that {good} doesn't, is it really newer?");
+    }
+    Ok(candidates)
+}
+
+// Puts the manifest back as it was: the block it had, or none. Entries `apply` took over from
+// outside a block can't be recreated from the block, the original file is written back then.
+fn restore(cargo_toml: &Path, original: &str) -> Result<()> {
+    if let Some(block) = apply::marked_patch(original)? {
+        return apply::apply(cargo_toml, &block);
+    }
+    let current = std::fs::read_to_string(cargo_toml)
+        .with_context(|| format!("Failed to read {}", cargo_toml.display()))?;
+    if apply::remove_block(&current)?.as_deref() == Some(original) {
+        return apply::remove(cargo_toml);
+    }
+    atomic::write(cargo_toml, original)?;
+    eprintln!("note: restored {}", cargo_toml.display());
+    Ok(())
+}
+
+// Patches --cargo-toml to `sha`, discovering the crates the commit has.
+fn apply_commit(cargo_toml: &Path, repo: &str, sha: &str, args: &[String]) -> Result<()> {
+    let step = [
+        "--cargo-toml",
+        &cargo_toml.display().to_string(),
+        "apply",
+        "git",
+        "--repo",
+        repo,
+        "--rev",
+        sha,
+    ]
+    .map(str::to_owned);
+    let cli = parse_args(step.into_iter().chain(args.iter().cloned()))?;
+    crate::run(&cli)?;
+    Ok(())
+}
+
+// Halves the range until one commit is left, asking after each step whether the project works.
+fn step(
+    cargo_toml: &Path,
+    repo: &str,
+    mut candidates: Vec<Candidate>,
+    args: &[String],
+) -> Result<Option<Candidate>> {
+    let answers = ["good", "bad", "skip", "quit"].map(str::to_owned);
+    // `candidates[..bad]` is untested, `candidates[bad]` is known to be bad.
+    let mut bad = candidates.len() - 1;
+    let mut low = 0;
+    while low < bad {
+        let mid = low + (bad - low) / 2;
+        let Candidate { sha, summary } = &candidates[mid];
+        apply_commit(cargo_toml, repo, sha, args)?;
+        let left = bad - low;
+        eprintln!(
+            "note: patched to {sha} {summary}, {left} commits left (about {} steps)",
+            usize::BITS - left.leading_zeros()
+        );
+        let answer = prompt::choose(
+            &format!(
+                "Build and test the project, is {} good or bad?",
+                &sha[..12.min(sha.len())]
+            ),
+            &answers,
+            "Pass --script for a `git bisect run` script instead",
+        )?;
+        match answer {
+            0 => low = mid + 1,
+            1 => bad = mid,
+            2 => {
+                candidates.remove(mid);
+                bad -= 1;
+            }
+            _ => return Ok(None),
+        }
+    }
+    Ok(Some(candidates.swap_remove(bad)))
+}
+
+pub fn run(
+    cargo_toml: &Path,
+    repo: &str,
+    good: &str,
+    bad: &str,
+    args: &[String],
+    github: &Github,
+) -> Result<ExitCode> {
+    let candidates = commit_range(repo, good, bad, github)?;
+    eprintln!(
+        "note: {} commits between {good} and {bad}",
+        candidates.len()
+    );
+    let original = std::fs::read_to_string(cargo_toml)
+        .with_context(|| format!("Failed to read {}", cargo_toml.display()))?;
+
+    let found = step(cargo_toml, repo, candidates, args);
+    restore(cargo_toml, &original)?;
+    match found? {
+        Some(Candidate { sha, summary }) => {
+            println!("{sha} is the first bad commit\n{summary}");
+            Ok(ExitCode::SUCCESS)
+        }
+        None => {
+            eprintln!("note: stopped bisecting");
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+// A script for `git bisect run` in a bevy checkout: patches the project to the checked out
+// commit, runs `test_command` and puts the manifest back.
+pub fn script(
+    cargo_toml: &Path,
+    repo: &str,
+    good: &str,
+    bad: &str,
+    test_command: &str,
+    args: &[String],
+) -> Result<String> {
+    let cargo_toml = std::path::absolute(cargo_toml)
+        .with_context(|| format!("Failed to resolve {}", cargo_toml.display()))?;
+    let quote = |arg: &str| Shell::Sh.quote(arg);
+    let args: String = args.iter().map(|arg| format!(" {}", quote(arg))).collect();
+    Ok(format!(
+        r#"#!/bin/sh
+# In a checkout of {repo}:
+#   git bisect start {bad} {good}
+#   git bisect run path/to/this/script
+manifest={manifest}
+backup="$manifest.bevy-patch-bisect"
+cp "$manifest" "$backup" || exit 128
+trap 'mv "$backup" "$manifest"' EXIT
+# A commit bevy-patch can't patch to is skipped.
+bevy-patch --cargo-toml "$manifest" apply git --repo {repo_arg} --rev "$(git rev-parse HEAD)"{args} || exit 125
+cd "$(dirname "$manifest")" || exit 128
+{test_command} || exit 1
+"#,
+        manifest = quote(&cargo_toml.display().to_string()),
+        repo_arg = quote(repo),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_quotes_its_arguments() {
+        let script = script(
+            Path::new("/work/my game/Cargo.toml"),
+            "https://github.com/bevyengine/bevy",
+            "v0.14.0",
+            "abc123",
+            "cargo test",
+            &["--skip-root".to_owned(), "it's".to_owned()],
+        )
+        .unwrap();
+        assert!(
+            script.contains("manifest='/work/my game/Cargo.toml'\n"),
+            "{script}"
+        );
+        assert!(
+            script.contains(
+                "apply git --repo 'https://github.com/bevyengine/bevy' \
+                 --rev \"$(git rev-parse HEAD)\" '--skip-root' 'it'\\''s' || exit 125"
+            ),
+            "{script}"
+        );
+        assert!(
+            script.contains("#   git bisect start abc123 v0.14.0\n"),
+            "{script}"
+        );
+    }
+
+    #[test]
+    fn restoring_puts_back_the_block_or_the_file() {
+        let dir =
+            Path::new("target/bevy-patch-tests").join(format!("bisect-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cargo_toml = dir.join("Cargo.toml");
+        let step = "[patch.crates-io]\nbevy = { git = \"https://github.com/bevyengine/bevy\", rev = \"abc\" }\n";
+
+        for original in [
+            "[package]\nname = \"game\"\n\n[patch.crates-io]\n# bevy-patch-begin\nbevy = { path = \"../bevy\" } # local\n# bevy-patch-end\nother = { path = \"../other\" }\n",
+            "[package]\nname = \"game\"\n",
+            "[package]\nname = \"game\"\n\n[patch.crates-io]\nbevy = { path = \"../bevy\" }\n",
+        ] {
+            std::fs::write(&cargo_toml, original).unwrap();
+            apply::apply(&cargo_toml, step).unwrap();
+            assert_ne!(std::fs::read_to_string(&cargo_toml).unwrap(), original);
+            restore(&cargo_toml, original).unwrap();
+            assert_eq!(std::fs::read_to_string(&cargo_toml).unwrap(), original);
+        }
+    }
+}
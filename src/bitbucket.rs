@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::encode_path;
+
+const API_URL: &str = "https://api.bitbucket.org/2.0/repositories";
+
+// Bitbucket authenticates with a username and an app password, or a repository/workspace
+// access token, which wins when both are given.
+pub struct Bitbucket {
+    pub client: reqwest::blocking::Client,
+    pub user: Option<String>,
+    pub app_password: Option<String>,
+    pub token: Option<String>,
+    pub timeout: Duration,
+}
+
+// `{"type": "error", "error": {"message": "...", "detail": "..."}}`
+#[derive(Deserialize)]
+struct BitbucketError {
+    error: BitbucketErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct BitbucketErrorDetail {
+    message: String,
+    detail: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BitbucketPage {
+    values: Vec<BitbucketEntry>,
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BitbucketEntry {
+    #[serde(rename = "type")]
+    entry_type: String,
+    path: String,
+}
+
+pub fn is_bitbucket(repo: &str) -> bool {
+    repo.contains("bitbucket.org/")
+}
+
+// https://bitbucket.org/<workspace>/<repo> -> (workspace, repo)
+pub fn parse_bitbucket_repo(repo: &str) -> Option<(&str, &str)> {
+    let path = repo.split_once("bitbucket.org/")?.1;
+    let (workspace, name) = path.trim_end_matches('/').split_once('/')?;
+    Some((workspace, name.trim_end_matches(".git")))
+}
+
+impl Bitbucket {
+    fn get<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let mut request = self
+            .client
+            .get(url)
+            .timeout(self.timeout)
+            .header("User-Agent", "bevy-patch");
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        } else if let Some(user) = &self.user {
+            request = request.basic_auth(user, self.app_password.as_ref());
+        }
+        let response = request.send().context("Failed to fetch from Bitbucket")?;
+
+        let status = response.status();
+        if status == 200 {
+            response
+                .json()
+                .context("Failed to parse Bitbucket response")
+        } else {
+            let body = response.text().unwrap_or_default();
+            let message = match serde_json::from_str::<BitbucketError>(&body) {
+                Ok(BitbucketError {
+                    error:
+                        BitbucketErrorDetail {
+                            message,
+                            detail: Some(detail),
+                        },
+                }) => format!("{message} ({detail})"),
+                Ok(err) => err.error.message,
+                Err(_) => body.trim().to_owned(),
+            };
+            Err(anyhow::anyhow!("{}: {message}", status.as_u16()))
+        }
+    }
+}
+
+pub fn fetch_crates_from_bitbucket(
+    workspace: &str,
+    repo: &str,
+    crates_root: &str,
+    git_ref: &str,
+    bitbucket: &Bitbucket,
+) -> Result<Vec<String>> {
+    let mut crates = Vec::new();
+    let mut url = Some(format!(
+        "{API_URL}/{workspace}/{repo}/src/{}/{}/?pagelen=100",
+        encode_path(git_ref),
+        encode_path(crates_root)
+    ));
+    while let Some(page_url) = url {
+        let page: BitbucketPage = bitbucket.get(&page_url)?;
+        crates.extend(
+            page.values
+                .into_iter()
+                .filter(|entry| entry.entry_type == "commit_directory")
+                .filter_map(|entry| entry.path.rsplit('/').next().map(str::to_owned)),
+        );
+        url = page.next;
+    }
+
+    crates.sort();
+    Ok(crates)
+}
@@ -0,0 +1,280 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+
+// GitHub responses are reused for this long before being fetched again.
+pub const CACHE_TTL: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Clone, Copy)]
+pub struct CachePolicy {
+    pub read: bool,
+    pub write: bool,
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// Print the cache directory
+    Path,
+    /// Count cached responses and their size per repository
+    Stats,
+    /// Delete every cached response
+    Clear,
+    /// Delete expired responses, then the oldest ones until the cache fits `--max-size`
+    Gc {
+        /// e.g. `50MB`, `512KB` or a number of bytes
+        #[arg(long, value_parser = parse_size)]
+        max_size: Option<u64>,
+    },
+    /// Show cached responses with their age
+    List,
+}
+
+static CACHE_DIR: OnceLock<PathBuf> = OnceLock::new();
+// Set once the cache directory turned out unwritable, responses then only live for the run.
+static MEMORY_ONLY: AtomicBool = AtomicBool::new(false);
+static MEMORY: LazyLock<Mutex<HashMap<String, CacheEntry>>> = LazyLock::new(Default::default);
+
+pub fn set_cache_dir(dir: Option<PathBuf>) {
+    if let Some(dir) = dir {
+        let _ = CACHE_DIR.set(dir);
+    }
+}
+
+fn parse_size(size: &str) -> Result<u64, String> {
+    let upper = size.trim().to_ascii_uppercase();
+    let (number, unit) = upper.split_at(
+        upper
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(upper.len()),
+    );
+    let multiplier = match unit.trim() {
+        "" | "B" => 1,
+        "KB" | "K" => 1 << 10,
+        "MB" | "M" => 1 << 20,
+        "GB" | "G" => 1 << 30,
+        _ => return Err(format!("{size:?} should look like 50MB, 512KB or 1GB")),
+    };
+    number
+        .parse::<u64>()
+        .map(|number| number * multiplier)
+        .map_err(|_| format!("{size:?} should look like 50MB, 512KB or 1GB"))
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    url: String,
+    fetched_at: u64,
+    body: String,
+}
+
+impl CacheEntry {
+    fn age(&self) -> Duration {
+        Duration::from_secs(now().saturating_sub(self.fetched_at))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn cache_dir() -> Option<PathBuf> {
+    if let Some(dir) = CACHE_DIR.get() {
+        return Some(dir.clone());
+    }
+    if let Some(dir) = std::env::var_os("XDG_CACHE_HOME") {
+        return Some(PathBuf::from(dir).join("bevy-patch"));
+    }
+    if cfg!(windows)
+        && let Some(dir) = std::env::var_os("LOCALAPPDATA")
+    {
+        return Some(PathBuf::from(dir).join("bevy-patch"));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache").join("bevy-patch"))
+}
+
+// FNV-1a, file names have to stay stable across builds which `DefaultHasher` doesn't promise.
+fn entry_path(url: &str) -> Option<PathBuf> {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in url.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    cache_dir().map(|dir| dir.join(format!("{hash:016x}.json")))
+}
+
+// `https://api.github.com/repos/<owner>/<repo>/...` -> `<owner>/<repo>`, other URLs by host.
+fn repo_of(url: &str) -> String {
+    let path = url.split_once("://").map_or(url, |(_, path)| path);
+    match path.strip_prefix("api.github.com/repos/") {
+        Some(repo) => repo.split('/').take(2).collect::<Vec<_>>().join("/"),
+        None => path.split('/').next().unwrap_or_default().to_owned(),
+    }
+}
+
+fn file_size(path: &PathBuf) -> u64 {
+    std::fs::metadata(path).map(|m| m.len()).unwrap_or_default()
+}
+
+fn remove(path: &PathBuf) -> Result<()> {
+    std::fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))
+}
+
+fn entries() -> Result<Vec<(PathBuf, CacheEntry)>> {
+    let Some(dir) = cache_dir() else {
+        return Ok(Vec::new());
+    };
+    let Ok(read_dir) = std::fs::read_dir(&dir) else {
+        return Ok(Vec::new());
+    };
+
+    let mut entries = Vec::new();
+    for file in read_dir {
+        let path = file?.path();
+        let entry = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<CacheEntry>(&content).ok());
+        if let Some(entry) = entry {
+            entries.push((path, entry));
+        }
+    }
+
+    entries.sort_by(|(_, a), (_, b)| a.url.cmp(&b.url));
+    Ok(entries)
+}
+
+pub fn read(url: &str) -> Option<String> {
+    if let Some(entry) = MEMORY.lock().unwrap().get(url) {
+        return (entry.age() < CACHE_TTL).then(|| entry.body.clone());
+    }
+    let content = std::fs::read_to_string(entry_path(url)?).ok()?;
+    let entry: CacheEntry = serde_json::from_str(&content).ok()?;
+    (entry.url == url && entry.age() < CACHE_TTL).then_some(entry.body)
+}
+
+fn write_file(url: &str, entry: &CacheEntry) -> std::io::Result<()> {
+    let path = entry_path(url).ok_or_else(|| std::io::Error::other("no cache directory"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Written aside and renamed into place, so concurrent runs never see half an entry.
+    let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+    std::fs::write(&temp, serde_json::to_string(entry)?)?;
+    std::fs::rename(&temp, &path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temp);
+    })
+}
+
+// Caching is best effort, when the directory can't be written the run keeps responses in memory.
+pub fn write(url: &str, body: &str) {
+    let entry = CacheEntry {
+        url: url.to_owned(),
+        fetched_at: now(),
+        body: body.to_owned(),
+    };
+
+    if !MEMORY_ONLY.load(Ordering::Relaxed) {
+        match write_file(url, &entry) {
+            Ok(()) => return,
+            Err(err) => {
+                if let Some(dir) = cache_dir() {
+                    warning!(
+                        "can't write the cache in {} ({err}), caching in memory for this run",
+                        dir.display()
+                    );
+                }
+                MEMORY_ONLY.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+    MEMORY.lock().unwrap().insert(url.to_owned(), entry);
+}
+
+// Drops the cached responses of a repository that mention `needle`, usually a ref.
+pub fn remove_matching(api_repo_url: &str, needle: &str) -> Result<()> {
+    for (path, entry) in entries()? {
+        if entry.url.starts_with(api_repo_url) && entry.url.contains(needle) {
+            remove(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn run(command: &CacheCommand) -> Result<()> {
+    match command {
+        CacheCommand::Path => match cache_dir() {
+            Some(dir) => println!("{}", dir.display()),
+            None => anyhow::bail!("No cache directory, set --cache-dir or HOME"),
+        },
+        CacheCommand::Stats => {
+            let mut repos: BTreeMap<String, (usize, u64)> = BTreeMap::new();
+            for (path, entry) in entries()? {
+                let stats = repos.entry(repo_of(&entry.url)).or_default();
+                stats.0 += 1;
+                stats.1 += file_size(&path);
+            }
+            for (repo, (count, size)) in &repos {
+                println!(
+                    "{count:>6} responses  {:>8} KB  {repo}",
+                    size.div_ceil(1024)
+                );
+            }
+            let count: usize = repos.values().map(|(count, _)| count).sum();
+            let size: u64 = repos.values().map(|(_, size)| size).sum();
+            println!("{count:>6} responses  {:>8} KB  total", size.div_ceil(1024));
+        }
+        CacheCommand::Clear => {
+            let entries = entries()?;
+            for (path, _) in &entries {
+                remove(path)?;
+            }
+            println!("Removed {} cached responses", entries.len());
+        }
+        CacheCommand::Gc { max_size } => {
+            let mut kept = Vec::new();
+            let mut removed = 0;
+            for (path, entry) in entries()? {
+                if entry.age() >= CACHE_TTL {
+                    remove(&path)?;
+                    removed += 1;
+                } else {
+                    kept.push((file_size(&path), entry.fetched_at, path));
+                }
+            }
+
+            if let Some(max_size) = max_size {
+                // Oldest first.
+                kept.sort_by_key(|(_, fetched_at, _)| *fetched_at);
+                let mut size: u64 = kept.iter().map(|(size, _, _)| size).sum();
+                for (entry_size, _, path) in &kept {
+                    if size <= *max_size {
+                        break;
+                    }
+                    remove(path)?;
+                    removed += 1;
+                    size -= entry_size;
+                }
+            }
+            println!("Removed {removed} cached responses");
+        }
+        CacheCommand::List => {
+            let ttl = CACHE_TTL.as_secs();
+            for (_, entry) in entries()? {
+                let age = entry.age().as_secs();
+                let state = if age < ttl { "fresh" } else { "expired" };
+                println!("{age:>6}s / {ttl}s  {state:<7}  {}", entry.url);
+            }
+        }
+    }
+
+    Ok(())
+}
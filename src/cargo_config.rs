@@ -0,0 +1,77 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::atomic;
+
+// Cargo still reads the extensionless legacy name, prefer it when that's what the project has.
+fn config_path(project_dir: &Path) -> PathBuf {
+    let dir = project_dir.join(".cargo");
+    let legacy = dir.join("config");
+    if legacy.is_file() && !dir.join("config.toml").exists() {
+        legacy
+    } else {
+        dir.join("config.toml")
+    }
+}
+
+pub fn cargo_home() -> Option<PathBuf> {
+    std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cargo")))
+}
+
+// The config files cargo merges when run in `dir`, the ones winning first: `dir` and each of
+// its parents, then the one in CARGO_HOME.
+pub fn config_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = dir
+        .ancestors()
+        .map(config_path)
+        .filter(|path| path.is_file())
+        .collect();
+    if let Some(home) = cargo_home() {
+        let path = ["config.toml", "config"]
+            .into_iter()
+            .map(|name| home.join(name))
+            .find(|path| path.is_file());
+        if let Some(path) = path
+            && !files.contains(&path)
+        {
+            files.push(path);
+        }
+    }
+    files
+}
+
+// Sets `net.git-fetch-with-cli = true` so cargo fetches git sources with the git CLI,
+// which knows about SSH agents and credential helpers. Everything else in the file is kept.
+pub fn enable_git_fetch_with_cli(project_dir: &Path) -> Result<PathBuf> {
+    let path = config_path(project_dir);
+    let _lock = atomic::lock(&path)?;
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    let mut config: toml_edit::DocumentMut = content
+        .parse()
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    let net = config
+        .entry("net")
+        .or_insert_with(toml_edit::table)
+        .as_table_like_mut()
+        .ok_or_else(|| anyhow::anyhow!("`net` in {} isn't a table", path.display()))?;
+    if net
+        .get("git-fetch-with-cli")
+        .and_then(|item| item.as_bool())
+        == Some(true)
+    {
+        return Ok(path);
+    }
+    net.insert("git-fetch-with-cli", toml_edit::value(true));
+
+    atomic::write(&path, config.to_string())?;
+    Ok(path)
+}
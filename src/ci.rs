@@ -0,0 +1,93 @@
+use std::sync::{Mutex, OnceLock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CiEnvironment {
+    GithubActions,
+    GitlabCi,
+    AzurePipelines,
+    CircleCi,
+    Buildkite,
+    Jenkins,
+    // `CI` is set but the system isn't one of the above.
+    Other,
+}
+
+static CI: OnceLock<Option<CiEnvironment>> = OnceLock::new();
+// Every warning of the run, for the `--gha` step summary.
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn is_set(var: &str) -> bool {
+    std::env::var(var).is_ok_and(|value| !value.is_empty() && value != "false" && value != "0")
+}
+
+impl CiEnvironment {
+    pub fn detect() -> Option<Self> {
+        let detected = [
+            ("GITHUB_ACTIONS", CiEnvironment::GithubActions),
+            ("GITLAB_CI", CiEnvironment::GitlabCi),
+            ("TF_BUILD", CiEnvironment::AzurePipelines),
+            ("CIRCLECI", CiEnvironment::CircleCi),
+            ("BUILDKITE", CiEnvironment::Buildkite),
+            ("JENKINS_URL", CiEnvironment::Jenkins),
+        ]
+        .into_iter()
+        .find(|(var, _)| is_set(var))
+        .map(|(_, ci)| ci);
+        detected.or_else(|| is_set("CI").then_some(CiEnvironment::Other))
+    }
+}
+
+pub fn set_ci(ci: Option<CiEnvironment>) {
+    let _ = CI.set(ci);
+}
+
+fn github_actions() -> bool {
+    CI.get() == Some(&Some(CiEnvironment::GithubActions))
+}
+
+// Workflow commands end at a newline, so line breaks are escaped to stay one annotation.
+fn annotation(level: &str, message: &str) -> String {
+    let escaped = message
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A");
+    format!("::{level}::{escaped}")
+}
+
+// Shown inline on the pull request in GitHub Actions.
+pub fn warning(message: &str) {
+    WARNINGS.lock().unwrap().push(message.to_owned());
+    if crate::report::is_json() {
+        crate::report::warning(message);
+    } else if github_actions() {
+        eprintln!("{}", annotation("warning", message));
+    } else {
+        eprintln!("warning: {message}");
+    }
+}
+
+pub fn warnings() -> Vec<String> {
+    WARNINGS.lock().unwrap().clone()
+}
+
+pub fn error(message: &str) {
+    if github_actions() {
+        eprintln!("{}", annotation("error", message));
+    } else {
+        eprintln!("Error: {message}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn annotations_stay_on_one_line() {
+        assert_eq!(
+            annotation("warning", "100% of\r\nthe crates\nfailed"),
+            "::warning::100%25 of%0D%0Athe crates%0Afailed"
+        );
+        assert_eq!(annotation("error", "%0A"), "::error::%250A");
+    }
+}
@@ -0,0 +1,44 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::PatchGroup;
+
+// `--fail-on-change` when the crate set differs from `--compare-to`.
+pub const CHANGED_EXIT_CODE: u8 = 2;
+
+// The crates patched in an earlier output, from every `[patch.*]` table.
+fn previous_crates(path: &Path) -> Result<BTreeSet<String>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let previous: toml::Table =
+        toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))?;
+    let registries = previous
+        .get("patch")
+        .and_then(|patch| patch.as_table())
+        .ok_or_else(|| anyhow::anyhow!("{} has no [patch] section", path.display()))?;
+    Ok(registries
+        .values()
+        .filter_map(|patches| patches.as_table())
+        .flat_map(|patches| patches.keys().cloned())
+        .collect())
+}
+
+// Prints the crates added and removed since `path` to stderr, returns whether there were any.
+pub fn report(path: &Path, groups: &[PatchGroup]) -> Result<bool> {
+    let previous = previous_crates(path)?;
+    let current: BTreeSet<String> = groups
+        .iter()
+        .flat_map(|group| &group.entries)
+        .map(|entry| entry.name.clone())
+        .collect();
+
+    for added in current.difference(&previous) {
+        eprintln!("+ {added} (added)");
+    }
+    for removed in previous.difference(&current) {
+        eprintln!("- {removed} (removed)");
+    }
+    Ok(current != previous)
+}
@@ -0,0 +1,519 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use bevy_patch::is_bevy_crate;
+use clap::{Subcommand, ValueEnum};
+
+use crate::{
+    GitReference, Github, PatchEntry, PatchGroup, PatchSource, apply, atomic, fetch_crate_metadata,
+    fetch_crates_from_local, git_output, user_friendly_repo,
+};
+
+#[derive(Subcommand)]
+pub enum ConvertCommand {
+    /// Turn `path` patch entries into `git` entries pinned to the local clone's HEAD
+    PathToGit,
+    /// Clone the repository of `git` patch entries and turn them into `path` entries
+    GitToPath {
+        #[arg(long, value_name = "DIR")]
+        clone_to: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConvertTo {
+    /// `git` entries pinned to the checkouts' HEAD
+    Git,
+    /// `path` entries into the checkout given with --path
+    Path,
+}
+
+pub fn run(
+    command: &ConvertCommand,
+    cargo_toml: &Path,
+    crates_root: &str,
+    github: &Github,
+) -> Result<Vec<PatchGroup>> {
+    match command {
+        ConvertCommand::PathToGit => path_to_git(cargo_toml),
+        ConvertCommand::GitToPath { clone_to } => {
+            git_to_path(cargo_toml, clone_to, crates_root, github)
+        }
+    }
+}
+
+// The patches bevy-patch manages: bevy crates, as with `--frozen`, and the entries between the
+// `apply` markers. Other patches of the manifest are left alone.
+fn read_patches(cargo_toml: &Path) -> Result<toml::Table> {
+    let content = std::fs::read_to_string(cargo_toml)
+        .with_context(|| format!("Failed to read {}", cargo_toml.display()))?;
+    let manifest: toml::Table = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", cargo_toml.display()))?;
+    let marked = apply::block_entries(&content)
+        .with_context(|| format!("Failed to parse {}", cargo_toml.display()))?;
+
+    let mut patches = manifest
+        .get("patch")
+        .and_then(|patch| patch.get("crates-io"))
+        .and_then(|patches| patches.as_table())
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!("{} has no [patch.crates-io]", cargo_toml.display()))?;
+    patches.retain(|name, _| is_bevy_crate(name) || marked.iter().any(|marked| marked == name));
+    Ok(patches)
+}
+
+// The directory `path` and the paths in the manifest are relative to.
+fn manifest_dir(cargo_toml: &Path) -> &Path {
+    cargo_toml
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+// `path` as written in the manifest of `manifest_dir`.
+fn relative_to(path: &Path, manifest_dir: &Path) -> Result<String> {
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve {}", path.display()))?;
+    let base = manifest_dir
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve {}", manifest_dir.display()))?;
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = PathBuf::new();
+    for _ in base.components().skip(common) {
+        relative.push("..");
+    }
+    relative.extend(path.components().skip(common));
+    if relative.as_os_str().is_empty() {
+        return Ok(".".to_owned());
+    }
+    Ok(relative.to_string_lossy().replace('\\', "/"))
+}
+
+// The clone's remote at its current commit.
+fn git_source(root: &str) -> Result<PatchSource> {
+    let remote = git_output(root, &["remote", "get-url", "origin"])
+        .with_context(|| format!("{root} has no origin remote to point at"))?;
+    let sha = git_output(root, &["rev-parse", "HEAD"])?;
+
+    if !git_output(root, &["status", "--porcelain"])?
+        .trim()
+        .is_empty()
+    {
+        warning!(
+            "{root} has uncommitted changes, {} won't include them",
+            sha.trim()
+        );
+    }
+
+    Ok(PatchSource::Git {
+        repo: user_friendly_repo(remote.trim()),
+        reference: GitReference::Rev(sha.trim().to_owned()),
+    })
+}
+
+fn path_to_git(cargo_toml: &Path) -> Result<Vec<PatchGroup>> {
+    // Patch paths are relative to the manifest.
+    let manifest_dir = manifest_dir(cargo_toml);
+
+    let mut roots: Vec<(String, usize)> = Vec::new();
+    let mut groups: Vec<PatchGroup> = Vec::new();
+    for (name, patch) in read_patches(cargo_toml)? {
+        let Some(path) = patch.get("path").and_then(|path| path.as_str()) else {
+            continue;
+        };
+        let path = manifest_dir.join(path).to_string_lossy().into_owned();
+        let root = git_output(&path, &["rev-parse", "--show-toplevel"])
+            .with_context(|| format!("{name} isn't patched to a git checkout"))?
+            .trim()
+            .to_owned();
+        let subpath = git_output(&path, &["rev-parse", "--show-prefix"])?
+            .trim()
+            .trim_end_matches('/')
+            .to_owned();
+
+        let group = match roots.iter().find(|(known, _)| *known == root) {
+            Some((_, group)) => *group,
+            None => {
+                groups.push(PatchGroup {
+                    source: git_source(&root)?,
+                    entries: Vec::new(),
+                });
+                roots.push((root, groups.len() - 1));
+                groups.len() - 1
+            }
+        };
+
+        let mut entry = PatchEntry::new(name, subpath);
+        entry.version = patch
+            .get("version")
+            .and_then(|version| version.as_str())
+            .map(str::to_owned);
+        groups[group].entries.push(entry);
+    }
+
+    if groups.is_empty() {
+        anyhow::bail!("{} has no path patches to convert", cargo_toml.display());
+    }
+    Ok(groups)
+}
+
+// Points path sources, given relative to the working directory, at the same directories from
+// the manifest, which is where cargo resolves them. Left as given when both are the same.
+pub fn paths_relative_to_manifest(groups: &mut [PatchGroup], cargo_toml: &Path) -> Result<()> {
+    let manifest_dir = manifest_dir(cargo_toml);
+    if manifest_dir.canonicalize().ok() == std::env::current_dir()?.canonicalize().ok() {
+        return Ok(());
+    }
+    for group in groups {
+        if let PatchSource::Path { path } = &mut group.source {
+            *path = relative_to(Path::new(path), manifest_dir)?;
+        }
+    }
+    Ok(())
+}
+
+fn git_reference(patch: &toml::Value) -> Option<GitReference> {
+    let get = |key| {
+        patch
+            .get(key)
+            .and_then(|value| value.as_str())
+            .map(str::to_owned)
+    };
+    get("branch")
+        .map(GitReference::Branch)
+        .or_else(|| get("tag").map(GitReference::Tag))
+        .or_else(|| get("rev").map(GitReference::Rev))
+}
+
+fn git(args: &[&str]) -> Result<()> {
+    let status = std::process::Command::new("git")
+        .args(args)
+        .status()
+        .context("Failed to run git")?;
+    if !status.success() {
+        anyhow::bail!("git {} failed", args.join(" "));
+    }
+    Ok(())
+}
+
+fn shallow_clone(repo: &str, reference: &GitReference, dir: &str) -> Result<()> {
+    match reference {
+        GitReference::Branch(name) | GitReference::Tag(name) => {
+            git(&["clone", "--depth", "1", "--branch", name, repo, dir])
+        }
+        // `--branch` only takes names, commits have to be fetched on their own.
+        GitReference::Rev(rev) => {
+            git(&["init", "--quiet", dir])?;
+            git(&["-C", dir, "remote", "add", "origin", repo])?;
+            git(&["-C", dir, "fetch", "--depth", "1", "origin", rev])?;
+            git(&["-C", dir, "checkout", "--quiet", "FETCH_HEAD"])
+        }
+    }
+}
+
+// The single repository and ref of the `git` patches.
+struct GitPatches {
+    repo: String,
+    reference: GitReference,
+    // Crate names and their `version`.
+    patched: Vec<(String, Option<String>)>,
+}
+
+fn git_patches(cargo_toml: &Path, dir: &Path) -> Result<GitPatches> {
+    let mut source: Option<(String, GitReference)> = None;
+    let mut patched = Vec::new();
+    for (name, patch) in read_patches(cargo_toml)? {
+        let Some(repo) = patch.get("git").and_then(|git| git.as_str()) else {
+            continue;
+        };
+        let reference = git_reference(&patch).unwrap_or(GitReference::Branch("main".to_owned()));
+        match &source {
+            Some((known_repo, known_reference))
+                if known_repo != repo || *known_reference != reference =>
+            {
+                anyhow::bail!(
+                    "{} patches from several repositories or refs, only one can be at {}",
+                    cargo_toml.display(),
+                    dir.display()
+                );
+            }
+            Some(_) => {}
+            None => source = Some((repo.to_owned(), reference)),
+        }
+        let version = patch.get("version").and_then(|version| version.as_str());
+        patched.push((name, version.map(str::to_owned)));
+    }
+
+    let Some((repo, reference)) = source else {
+        anyhow::bail!("{} has no git patches to convert", cargo_toml.display());
+    };
+    Ok(GitPatches {
+        repo,
+        reference,
+        patched,
+    })
+}
+
+fn git_to_path(
+    cargo_toml: &Path,
+    clone_to: &Path,
+    crates_root: &str,
+    github: &Github,
+) -> Result<Vec<PatchGroup>> {
+    let GitPatches {
+        repo,
+        reference,
+        patched,
+    } = git_patches(cargo_toml, clone_to)?;
+    if clone_to.exists() {
+        anyhow::bail!("{} already exists", clone_to.display());
+    }
+    let dir = clone_to.to_string_lossy().into_owned();
+    shallow_clone(&repo, &reference, &dir)?;
+    path_group(clone_to, cargo_toml, &repo, patched, crates_root, github)
+}
+
+// `checkout` is relative to the working directory, the entries get it relative to the manifest.
+fn path_group(
+    checkout: &Path,
+    cargo_toml: &Path,
+    repo: &str,
+    patched: Vec<(String, Option<String>)>,
+    crates_root: &str,
+    github: &Github,
+) -> Result<Vec<PatchGroup>> {
+    // Cargo looks crates up by name anywhere in the repository, so map names back to directories.
+    let dir = checkout.to_string_lossy().into_owned();
+    let path = PatchSource::Path { path: dir.clone() };
+    let mut subpaths = vec![String::new()];
+    subpaths.extend(
+        fetch_crates_from_local(&dir, crates_root)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|name| format!("{crates_root}/{name}")),
+    );
+    let mut packages = Vec::new();
+    for subpath in subpaths {
+        if let Ok(metadata) = fetch_crate_metadata(&path, &subpath, github) {
+            packages.push((metadata.name, subpath));
+        }
+    }
+
+    let mut entries = Vec::new();
+    for (name, version) in patched {
+        let (_, subpath) = packages
+            .iter()
+            .find(|(package, _)| *package == name)
+            .ok_or_else(|| anyhow::anyhow!("{name} isn't a crate of {repo}"))?;
+        let mut entry = PatchEntry::new(name, subpath.clone());
+        entry.version = version;
+        entries.push(entry);
+    }
+
+    Ok(vec![PatchGroup {
+        source: PatchSource::Path {
+            path: relative_to(checkout, manifest_dir(cargo_toml))?,
+        },
+        entries,
+    }])
+}
+
+// Whether the checkout at `dir` is at `reference`, only what git knows locally is checked.
+fn checkout_matches(dir: &str, reference: &GitReference) -> Result<bool> {
+    let head = git_output(dir, &["rev-parse", "HEAD"])?.trim().to_owned();
+    Ok(match reference {
+        GitReference::Rev(rev) => head.starts_with(rev.as_str()),
+        GitReference::Tag(tag) => git_output(dir, &["rev-parse", &format!("{tag}^{{commit}}")])
+            .is_ok_and(|sha| sha.trim() == head),
+        GitReference::Branch(branch) => git_output(dir, &["rev-parse", "--abbrev-ref", "HEAD"])
+            .is_ok_and(|current| current.trim() == branch),
+    })
+}
+
+// Points the `[patch.crates-io]` entries of the groups at their new source. The other keys of
+// an entry, the other entries and the rest of the manifest stay as they were.
+fn rewrite_entries(content: &str, groups: &[PatchGroup]) -> Result<String> {
+    const SOURCE_KEYS: [&str; 5] = ["git", "branch", "tag", "rev", "path"];
+
+    let mut manifest: toml_edit::DocumentMut = content.parse()?;
+    let patches = manifest
+        .get_mut("patch")
+        .and_then(|patch| patch.get_mut("crates-io"))
+        .and_then(|patches| patches.as_table_like_mut())
+        .ok_or_else(|| anyhow::anyhow!("no [patch.crates-io]"))?;
+
+    for group in groups {
+        for entry in &group.entries {
+            let mut fields = Vec::new();
+            for (key, value) in group.source.fields(&entry.subpath) {
+                fields.push((key, value.parse::<toml_edit::Value>()?));
+            }
+            let Some(patch) = patches.get_mut(&entry.name) else {
+                continue;
+            };
+            if let Some(patch) = patch.as_inline_table_mut() {
+                // Rebuilt so the source keys come first again.
+                let mut rebuilt = toml_edit::InlineTable::new();
+                for (key, value) in fields {
+                    rebuilt.insert(key, value);
+                }
+                for (key, value) in patch.iter() {
+                    if !SOURCE_KEYS.contains(&key) {
+                        rebuilt.insert(key, value.clone());
+                    }
+                }
+                rebuilt.fmt();
+                *rebuilt.decor_mut() = patch.decor().clone();
+                *patch = rebuilt;
+            } else if let Some(patch) = patch.as_table_like_mut() {
+                for key in SOURCE_KEYS {
+                    patch.remove(key);
+                }
+                for (key, value) in fields {
+                    patch.insert(key, toml_edit::value(value));
+                }
+            }
+        }
+    }
+    Ok(manifest.to_string())
+}
+
+// `convert --to`, rewrites the patch entries of Cargo.toml in place.
+pub fn rewrite(
+    cargo_toml: &Path,
+    to: ConvertTo,
+    checkout: Option<&Path>,
+    crates_root: &str,
+    github: &Github,
+) -> Result<()> {
+    let groups = match to {
+        ConvertTo::Git => path_to_git(cargo_toml)?,
+        ConvertTo::Path => {
+            let checkout = checkout
+                .ok_or_else(|| anyhow::anyhow!("--to path needs --path <DIR> to a checkout"))?;
+            let GitPatches {
+                repo,
+                reference,
+                patched,
+            } = git_patches(cargo_toml, checkout)?;
+            let dir = checkout.to_string_lossy().into_owned();
+            if !checkout.is_dir() {
+                anyhow::bail!("{dir} isn't a checkout of {repo}");
+            }
+            if !checkout_matches(&dir, &reference)? {
+                warning!(
+                    "{dir} isn't at {} {}, the crates may differ from the pinned ones",
+                    reference.key(),
+                    reference.as_str()
+                );
+            }
+            path_group(checkout, cargo_toml, &repo, patched, crates_root, github)?
+        }
+    };
+
+    atomic::edit(cargo_toml, |content| rewrite_entries(content, &groups))?;
+
+    let count: usize = groups.iter().map(|group| group.entries.len()).sum();
+    eprintln!(
+        "note: pointed {count} entries of {} at {}",
+        cargo_toml.display(),
+        groups
+            .iter()
+            .map(|group| group.source.label())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_only_the_converted_entries() {
+        let content = "\
+[package]
+name = \"game\" # kept
+
+[patch.crates-io]
+# Bevy Patch
+bevy = { path = \"../bevy\" }
+bevy_ecs = { path = \"../bevy/crates/bevy_ecs\", version = \"0.15\" } # pinned
+other = { path = \"../other\" }
+
+[patch.crates-io.bevy_app]
+path = \"../bevy/crates/bevy_app\"
+features = [\"std\"]
+";
+        let groups = vec![PatchGroup {
+            source: PatchSource::Git {
+                repo: "https://github.com/bevyengine/bevy".to_owned(),
+                reference: GitReference::Rev("abc".to_owned()),
+            },
+            entries: vec![
+                PatchEntry::new("bevy", ""),
+                PatchEntry::new("bevy_ecs", "crates/bevy_ecs"),
+                PatchEntry::new("bevy_app", "crates/bevy_app"),
+            ],
+        }];
+        assert_eq!(
+            rewrite_entries(content, &groups).unwrap(),
+            "\
+[package]
+name = \"game\" # kept
+
+[patch.crates-io]
+# Bevy Patch
+bevy = { git = \"https://github.com/bevyengine/bevy\", rev = \"abc\" }
+bevy_ecs = { git = \"https://github.com/bevyengine/bevy\", rev = \"abc\", version = \"0.15\" } # pinned
+other = { path = \"../other\" }
+
+[patch.crates-io.bevy_app]
+features = [\"std\"]
+git = \"https://github.com/bevyengine/bevy\"
+rev = \"abc\"
+"
+        );
+    }
+
+    #[test]
+    fn only_bevy_and_marked_entries_are_converted() {
+        let dir = std::env::temp_dir().join(format!("bevy-patch-convert-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("game")).unwrap();
+        let cargo_toml = dir.join("game/Cargo.toml");
+        std::fs::write(
+            &cargo_toml,
+            "\
+[patch.crates-io]
+# bevy-patch-begin
+bevy = { path = \"../bevy\" }
+leafwing = { path = \"../leafwing\" }
+# bevy-patch-end
+bevy_ecs = { path = \"../bevy/crates/bevy_ecs\" }
+other = { path = \"x\" }
+",
+        )
+        .unwrap();
+        let patches = read_patches(&cargo_toml).unwrap();
+        let names: Vec<&str> = patches.keys().map(String::as_str).collect();
+        assert_eq!(names, ["bevy", "bevy_ecs", "leafwing"]);
+
+        std::fs::create_dir_all(dir.join("bevy")).unwrap();
+        let checkout = dir.join("game/../bevy");
+        assert_eq!(
+            relative_to(&checkout, manifest_dir(&cargo_toml)).unwrap(),
+            "../bevy"
+        );
+        assert_eq!(
+            relative_to(&dir.join("game"), &dir.join("game")).unwrap(),
+            "."
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+use crate::{GitReference, PatchEntry, PatchGroup, PatchSource};
+
+// Returns why the entry is a form cargo frowns upon, given every generated group.
+type Rule = Box<dyn Fn(&[PatchGroup], &PatchSource, &PatchEntry) -> Option<String>>;
+
+pub struct DeprecationChecker {
+    rules: Vec<Rule>,
+}
+
+impl Default for DeprecationChecker {
+    fn default() -> Self {
+        Self {
+            rules: vec![
+                Box::new(|groups, _, entry| {
+                    let count = groups
+                        .iter()
+                        .flat_map(|group| &group.entries)
+                        .filter(|other| other.name == entry.name)
+                        .count();
+                    (count > 1 && entry.version.is_none()).then(|| {
+                        format!(
+                            "{} is patched {count} times, cargo needs a `version` to tell them apart, pass --add-version",
+                            entry.name
+                        )
+                    })
+                }),
+                Box::new(|_, source, entry| match source {
+                    PatchSource::Git {
+                        reference: GitReference::Branch(branch),
+                        ..
+                    } if branch == "master" => Some(format!(
+                        "{} follows `branch = \"master\"`, most repositories moved to `main`",
+                        entry.name
+                    )),
+                    _ => None,
+                }),
+                Box::new(|_, source, entry| match source {
+                    PatchSource::Git { repo, .. } if repo.starts_with("http://") => Some(format!(
+                        "{} is fetched over http://, use https://",
+                        entry.name
+                    )),
+                    _ => None,
+                }),
+            ],
+        }
+    }
+}
+
+impl DeprecationChecker {
+    pub fn check(&self, groups: &[PatchGroup]) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for group in groups {
+            for entry in &group.entries {
+                warnings.extend(
+                    self.rules
+                        .iter()
+                        .filter_map(|rule| rule(groups, &group.source, entry)),
+                );
+            }
+        }
+        warnings
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::format::Format;
+use crate::{
+    GitReference, Github, PatchSource, crate_subpath, fetch_crate_metadata,
+    fetch_crates_from_github, user_friendly_repo,
+};
+
+#[derive(Serialize)]
+struct Crate {
+    name: String,
+    subpath: String,
+}
+
+#[derive(Serialize)]
+struct Moved {
+    name: String,
+    from: String,
+    to: String,
+}
+
+#[derive(Serialize)]
+struct VersionChange {
+    name: String,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[derive(Serialize)]
+struct CrateDiff {
+    repo: String,
+    from: String,
+    to: String,
+    added: Vec<Crate>,
+    removed: Vec<Crate>,
+    moved: Vec<Moved>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    versions: Option<Vec<VersionChange>>,
+}
+
+fn source(repo: &str, git_ref: &str) -> PatchSource {
+    PatchSource::Git {
+        repo: repo.to_owned(),
+        reference: GitReference::Rev(git_ref.to_owned()),
+    }
+}
+
+// Directory -> package name, a directory without a readable manifest keeps its own name.
+fn package_names(
+    repo: &str,
+    git_ref: &str,
+    crates_root: &str,
+    dirs: &[&String],
+    github: &Github,
+) -> BTreeMap<String, String> {
+    dirs.iter()
+        .map(|&dir| {
+            let subpath = crate_subpath(crates_root, dir);
+            let name = fetch_crate_metadata(&source(repo, git_ref), &subpath, github)
+                .map_or_else(|_| dir.clone(), |metadata| metadata.name);
+            (dir.clone(), name)
+        })
+        .collect()
+}
+
+pub fn run(
+    repo: &str,
+    from: &str,
+    to: &str,
+    versions: bool,
+    crates_root: &str,
+    format: Format,
+    github: &Github,
+) -> Result<()> {
+    let repo = user_friendly_repo(repo);
+    let discover = |git_ref: &str| {
+        fetch_crates_from_github(&repo, crates_root, git_ref, github)
+            .with_context(|| format!("Failed to list the crates of {repo} at {git_ref}"))
+    };
+    let (before, after) = std::thread::scope(|scope| {
+        let before = scope.spawn(|| discover(from));
+        let after = discover(to);
+        (before.join().expect("crate discovery panicked"), after)
+    });
+    let (before, after) = (before?, after?);
+
+    let removed: Vec<&String> = before.iter().filter(|dir| !after.contains(dir)).collect();
+    let added: Vec<&String> = after.iter().filter(|dir| !before.contains(dir)).collect();
+    // A package whose directory changed shows up on both sides, matched by package name.
+    let removed_names = package_names(&repo, from, crates_root, &removed, github);
+    let added_names = package_names(&repo, to, crates_root, &added, github);
+
+    let mut diff = CrateDiff {
+        repo: repo.clone(),
+        from: from.to_owned(),
+        to: to.to_owned(),
+        added: Vec::new(),
+        removed: Vec::new(),
+        moved: Vec::new(),
+        versions: None,
+    };
+    for (dir, name) in &removed_names {
+        match added_names.iter().find(|(_, added)| *added == name) {
+            Some((new_dir, _)) => diff.moved.push(Moved {
+                name: name.clone(),
+                from: crate_subpath(crates_root, dir),
+                to: crate_subpath(crates_root, new_dir),
+            }),
+            None => diff.removed.push(Crate {
+                name: name.clone(),
+                subpath: crate_subpath(crates_root, dir),
+            }),
+        }
+    }
+    for (dir, name) in &added_names {
+        if !removed_names.values().any(|removed| removed == name) {
+            diff.added.push(Crate {
+                name: name.clone(),
+                subpath: crate_subpath(crates_root, dir),
+            });
+        }
+    }
+
+    if versions {
+        let mut changes = Vec::new();
+        for dir in before.iter().filter(|dir| after.contains(dir)) {
+            let subpath = crate_subpath(crates_root, dir);
+            let version = |git_ref: &str| {
+                fetch_crate_metadata(&source(&repo, git_ref), &subpath, github)
+                    .map(|metadata| metadata.version)
+            };
+            let (old, new) = (version(from)?, version(to)?);
+            if old != new {
+                changes.push(VersionChange {
+                    name: dir.clone(),
+                    from: old,
+                    to: new,
+                });
+            }
+        }
+        diff.versions = Some(changes);
+    }
+
+    if format == Format::Json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(());
+    }
+
+    println!("{repo}: {from} -> {to}");
+    for added in &diff.added {
+        println!("  added    {} ({})", added.name, added.subpath);
+    }
+    for removed in &diff.removed {
+        println!("  removed  {} ({})", removed.name, removed.subpath);
+    }
+    for moved in &diff.moved {
+        println!("  moved    {} {} -> {}", moved.name, moved.from, moved.to);
+    }
+    for change in diff.versions.iter().flatten() {
+        let version = |version: &Option<String>| version.clone().unwrap_or_else(|| "-".to_owned());
+        println!(
+            "  version  {} {} -> {}",
+            change.name,
+            version(&change.from),
+            version(&change.to)
+        );
+    }
+    if diff.added.is_empty()
+        && diff.removed.is_empty()
+        && diff.moved.is_empty()
+        && diff.versions.iter().flatten().next().is_none()
+    {
+        println!("  no changes");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use bevy_patch::is_bevy_crate;
+use serde::Serialize;
+
+use crate::explain::{cargo_metadata, str_field};
+use crate::format::Format;
+use crate::{
+    GitReference, Github, PatchSource, fetch_crate_metadata, fetch_file_from_github, is_github,
+    user_friendly_repo,
+};
+
+#[derive(Serialize)]
+struct PluginStatus {
+    name: String,
+    version: String,
+    // The plugin's requirement on the bevy crate it depends on, e.g. `bevy_ecs ^0.15`.
+    requirement: String,
+    compatible: bool,
+    repository: Option<String>,
+    // Whether the plugin's default branch depends on bevy through git, i.e. follows bevy main.
+    tracks_bevy_main: Option<bool>,
+}
+
+// A git dependency on bevy anywhere in the plugin's root manifest.
+fn tracks_bevy_main(repository: &str, github: &Github) -> Option<bool> {
+    let manifest = fetch_file_from_github(repository, "Cargo.toml", "HEAD", github).ok()?;
+    let manifest: toml::Table = toml::from_str(&manifest).ok()?;
+    let tables = [
+        manifest.get("dependencies"),
+        manifest
+            .get("workspace")
+            .and_then(|workspace| workspace.get("dependencies")),
+    ];
+    Some(tables.into_iter().flatten().any(|dependencies| {
+        dependencies
+            .as_table()
+            .into_iter()
+            .flatten()
+            .any(|(name, dependency)| {
+                is_bevy_crate(name)
+                    && dependency
+                        .get("git")
+                        .and_then(|git| git.as_str())
+                        .is_some_and(|git| git.contains("bevyengine/bevy"))
+            })
+    }))
+}
+
+pub fn run(
+    manifest_path: &Path,
+    repo: &str,
+    git_ref: &str,
+    format: Format,
+    github: &Github,
+) -> Result<()> {
+    let repo = user_friendly_repo(repo);
+    let source = PatchSource::Git {
+        repo: repo.clone(),
+        reference: GitReference::Branch(git_ref.to_owned()),
+    };
+    let target = fetch_crate_metadata(&source, "", github)?
+        .version
+        .ok_or_else(|| anyhow::anyhow!("bevy on {repo} @ {git_ref} has no version"))?;
+    let target = semver::Version::parse(&target)
+        .with_context(|| format!("bevy on {repo} @ {git_ref} has version {target:?}"))?;
+
+    let metadata = cargo_metadata(manifest_path)?;
+    let members = metadata["workspace_members"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let packages = metadata["packages"].as_array().cloned().unwrap_or_default();
+
+    let mut plugins = Vec::new();
+    for package in &packages {
+        let name = str_field(package, "name").unwrap_or_default();
+        if members.contains(&package["id"]) || is_bevy_crate(name) {
+            continue;
+        }
+        let dependencies = package["dependencies"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        let Some(dependency) = dependencies
+            .iter()
+            .find(|dependency| str_field(dependency, "name").is_some_and(is_bevy_crate))
+        else {
+            continue;
+        };
+
+        let req = str_field(dependency, "req").unwrap_or("*");
+        let compatible = semver::VersionReq::parse(req).is_ok_and(|req| req.matches(&target));
+        let repository = str_field(package, "repository").map(str::to_owned);
+        let tracks_bevy_main = match &repository {
+            Some(repository) if !compatible && is_github(repository) => {
+                tracks_bevy_main(repository, github)
+            }
+            _ => None,
+        };
+        plugins.push(PluginStatus {
+            name: name.to_owned(),
+            version: str_field(package, "version").unwrap_or("?").to_owned(),
+            requirement: format!(
+                "{} {req}",
+                str_field(dependency, "name").unwrap_or_default()
+            ),
+            compatible,
+            repository,
+            tracks_bevy_main,
+        });
+    }
+    plugins.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if format == Format::Json {
+        let output = serde_json::json!({ "bevy_version": target.to_string(), "plugins": plugins });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    println!("bevy {target} from {repo} @ {git_ref}");
+    let width = plugins
+        .iter()
+        .map(|p| p.name.len() + p.version.len() + 1)
+        .max()
+        .unwrap_or(0);
+    for plugin in &plugins {
+        let status = if plugin.compatible {
+            "compatible".to_owned()
+        } else {
+            match plugin.tracks_bevy_main {
+                Some(true) => "incompatible, its default branch tracks bevy main".to_owned(),
+                Some(false) => {
+                    "incompatible, its default branch doesn't track bevy main".to_owned()
+                }
+                None => "incompatible".to_owned(),
+            }
+        };
+        println!(
+            "{:<width$}  {:<24}  {status}",
+            format!("{} {}", plugin.name, plugin.version),
+            plugin.requirement
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,320 @@
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::format::Format;
+
+#[derive(Serialize)]
+struct Explanation {
+    #[serde(rename = "crate")]
+    crate_name: String,
+    // The `[patch.crates-io]` entry of the workspace root, as written.
+    patch: Option<String>,
+    // Every source the resolver picked for the crate, several versions can coexist.
+    resolved: Vec<String>,
+    in_effect: bool,
+    reason: String,
+}
+
+fn read_manifest(path: &Path) -> Result<toml::Table> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn patch_value<'a>(manifest: &'a toml::Table, crate_name: &str) -> Option<&'a toml::Value> {
+    manifest.get("patch")?.get("crates-io")?.get(crate_name)
+}
+
+// Whether the resolver picked the exact git URL (and rev) or path the patch names.
+fn matches_patch(patch: &toml::Value, package: &serde_json::Value, root: &Path) -> bool {
+    let field = |key| patch.get(key).and_then(|value| value.as_str());
+    let source = str_field(package, "source");
+
+    if let Some(git) = field("git") {
+        let Some(source) = source.and_then(|source| source.strip_prefix("git+")) else {
+            return false;
+        };
+        let (url, sha) = source.split_once('#').unwrap_or((source, ""));
+        let url = url.split('?').next().unwrap_or(url);
+        let normalize = |url: &str| {
+            url.trim_end_matches('/')
+                .trim_end_matches(".git")
+                .to_owned()
+        };
+        return normalize(url) == normalize(git)
+            && field("rev").is_none_or(|rev| sha.starts_with(rev));
+    }
+
+    if let Some(path) = field("path") {
+        let expected = root.join(path);
+        let expected = expected.canonicalize().unwrap_or(expected);
+        let manifest = str_field(package, "manifest_path").map(Path::new);
+        return source.is_none()
+            && manifest
+                .and_then(Path::parent)
+                .is_some_and(|dir| dir == expected);
+    }
+
+    false
+}
+
+pub fn cargo_metadata(manifest_path: &Path) -> Result<serde_json::Value> {
+    let output = std::process::Command::new("cargo")
+        .args(["metadata", "--format-version", "1", "--manifest-path"])
+        .arg(manifest_path)
+        .output()
+        .context("Failed to run cargo metadata")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "cargo metadata failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    serde_json::from_slice(&output.stdout).context("Failed to parse cargo metadata")
+}
+
+pub fn str_field<'a>(value: &'a serde_json::Value, field: &str) -> Option<&'a str> {
+    value.get(field).and_then(|field| field.as_str())
+}
+
+// `registry+...` is crates.io, `git+<url>?<ref>#<sha>` a git source, nothing a path.
+fn describe_source(package: &serde_json::Value) -> String {
+    let version = str_field(package, "version").unwrap_or("?");
+    match str_field(package, "source") {
+        Some(source) if source.starts_with("registry+") => format!("{version} from crates.io"),
+        Some(source) => format!(
+            "{version} from {}",
+            source.strip_prefix("git+").unwrap_or(source)
+        ),
+        None => {
+            let manifest = str_field(package, "manifest_path").unwrap_or("?");
+            let dir = Path::new(manifest).parent().unwrap_or(Path::new(manifest));
+            format!("{version} from {}", dir.display())
+        }
+    }
+}
+
+// The `[[patch.unused]]` version recorded for the crate in Cargo.lock.
+fn unused_patch(workspace_root: &Path, crate_name: &str) -> Option<String> {
+    let lock = read_manifest(&workspace_root.join("Cargo.lock")).ok()?;
+    lock.get("patch")?
+        .get("unused")?
+        .as_array()?
+        .iter()
+        .find(|unused| unused.get("name").and_then(|name| name.as_str()) == Some(crate_name))
+        .map(|unused| {
+            unused
+                .get("version")
+                .and_then(|version| version.as_str())
+                .unwrap_or("?")
+                .to_owned()
+        })
+}
+
+// `(<dependent>, <req>)` for everything depending on the crate.
+fn requirements(metadata: &serde_json::Value, crate_name: &str) -> Vec<(String, String)> {
+    let packages = metadata["packages"].as_array().cloned().unwrap_or_default();
+    let mut requirements = Vec::new();
+    for package in &packages {
+        let dependencies = package["dependencies"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        for dependency in dependencies {
+            if str_field(&dependency, "name") == Some(crate_name) {
+                requirements.push((
+                    str_field(package, "name").unwrap_or("?").to_owned(),
+                    str_field(&dependency, "req").unwrap_or("*").to_owned(),
+                ));
+            }
+        }
+    }
+    requirements
+}
+
+// Workspace members that carry their own `[patch]`, which cargo ignores.
+fn member_patches(metadata: &serde_json::Value, crate_name: &str, root: &Path) -> Vec<PathBuf> {
+    let members = metadata["workspace_members"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let packages = metadata["packages"].as_array().cloned().unwrap_or_default();
+    packages
+        .iter()
+        .filter(|package| members.contains(&package["id"]))
+        .filter_map(|package| str_field(package, "manifest_path").map(PathBuf::from))
+        .filter(|manifest| manifest != root)
+        .filter(|manifest| {
+            read_manifest(manifest).is_ok_and(|member| patch_value(&member, crate_name).is_some())
+        })
+        .collect()
+}
+
+fn satisfies(version: &str, req: &str) -> bool {
+    match (
+        semver::Version::parse(version),
+        semver::VersionReq::parse(req),
+    ) {
+        (Ok(version), Ok(req)) => req.matches(&version),
+        _ => false,
+    }
+}
+
+struct Workspace {
+    metadata: serde_json::Value,
+    root: PathBuf,
+    root_manifest: PathBuf,
+    manifest: toml::Table,
+}
+
+fn load_workspace(manifest_path: &Path) -> Result<Workspace> {
+    let metadata = cargo_metadata(manifest_path)?;
+    let root = PathBuf::from(
+        str_field(&metadata, "workspace_root")
+            .ok_or_else(|| anyhow::anyhow!("cargo metadata has no workspace_root"))?,
+    );
+    let root_manifest = root.join("Cargo.toml");
+    let manifest = read_manifest(&root_manifest)?;
+    Ok(Workspace {
+        metadata,
+        root,
+        root_manifest,
+        manifest,
+    })
+}
+
+fn explain(crate_name: &str, workspace: &Workspace) -> Explanation {
+    let Workspace {
+        metadata,
+        root: workspace_root,
+        root_manifest,
+        manifest,
+    } = workspace;
+    let patch = patch_value(manifest, crate_name);
+
+    let packages = metadata["packages"].as_array().cloned().unwrap_or_default();
+    let resolved: Vec<&serde_json::Value> = packages
+        .iter()
+        .filter(|package| str_field(package, "name") == Some(crate_name))
+        .collect();
+    let in_effect = patch.is_some_and(|patch| {
+        resolved
+            .iter()
+            .any(|package| matches_patch(patch, package, workspace_root))
+    });
+
+    let reason = if resolved.is_empty() {
+        format!("{crate_name} isn't part of the dependency graph, nothing depends on it")
+    } else if in_effect {
+        "the patch is in effect".to_owned()
+    } else if patch.is_none() {
+        let members = member_patches(metadata, crate_name, root_manifest);
+        if members.is_empty() {
+            format!("{} has no patch for {crate_name}", root_manifest.display())
+        } else {
+            format!(
+                "{crate_name} is only patched in {}, cargo ignores [patch] outside the workspace root",
+                members
+                    .iter()
+                    .map(|member| member.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        }
+    } else if let Some(version) = unused_patch(workspace_root, crate_name) {
+        let requirements = requirements(metadata, crate_name);
+        let mismatched: Vec<String> = requirements
+            .iter()
+            .filter(|(_, req)| !satisfies(&version, req))
+            .map(|(dependent, req)| format!("{dependent} requires {req}"))
+            .collect();
+        if mismatched.is_empty() {
+            format!(
+                "the patch provides {version}, but Cargo.lock still pins the crates.io version, \
+                 run `cargo update -p {crate_name}`"
+            )
+        } else {
+            format!(
+                "the patch provides {version}, which doesn't match the requirements ({}), so cargo left it unused",
+                mismatched.join(", ")
+            )
+        }
+    } else {
+        "the patch isn't used yet, run `cargo update` so Cargo.lock picks it up".to_owned()
+    };
+
+    Explanation {
+        crate_name: crate_name.to_owned(),
+        patch: patch.map(|patch| patch.to_string()),
+        resolved: resolved.into_iter().map(describe_source).collect(),
+        in_effect,
+        reason,
+    }
+}
+
+pub fn run(crate_name: &str, manifest_path: &Path, format: Format) -> Result<()> {
+    let explanation = explain(crate_name, &load_workspace(manifest_path)?);
+
+    if format == Format::Json {
+        println!("{}", serde_json::to_string_pretty(&explanation)?);
+        return Ok(());
+    }
+
+    match &explanation.patch {
+        Some(patch) => println!("patch:    {crate_name} = {patch}"),
+        None => println!("patch:    none"),
+    }
+    if explanation.resolved.is_empty() {
+        println!("resolved: nothing");
+    }
+    for resolved in &explanation.resolved {
+        println!("resolved: {resolved}");
+    }
+    println!("reason:   {}", explanation.reason);
+    Ok(())
+}
+
+// Every crate patched by the workspace root that doesn't resolve to its patch.
+pub fn check_effective(manifest_path: &Path, strict: bool, format: Format) -> Result<ExitCode> {
+    let workspace = load_workspace(manifest_path)?;
+    let patched: Vec<String> = workspace
+        .manifest
+        .get("patch")
+        .and_then(|patch| patch.get("crates-io"))
+        .and_then(|patches| patches.as_table())
+        .map(|patches| patches.keys().cloned().collect())
+        .unwrap_or_default();
+    let ineffective: Vec<Explanation> = patched
+        .iter()
+        .map(|crate_name| explain(crate_name, &workspace))
+        .filter(|explanation| !explanation.in_effect)
+        .collect();
+
+    if format == Format::Json {
+        let output = serde_json::json!({ "patched": patched.len(), "ineffective": ineffective });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+    } else if ineffective.is_empty() {
+        println!("All {} patches are in effect", patched.len());
+    } else {
+        for explanation in &ineffective {
+            let resolved = if explanation.resolved.is_empty() {
+                "nothing".to_owned()
+            } else {
+                explanation.resolved.join(", ")
+            };
+            println!(
+                "{} resolves to {resolved}: {}",
+                explanation.crate_name, explanation.reason
+            );
+        }
+    }
+
+    Ok(if strict && !ineffective.is_empty() {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
@@ -0,0 +1,146 @@
+use anyhow::Result;
+use bevy_patch::CrateFetcher;
+
+use crate::azure::Azure;
+use crate::bitbucket::Bitbucket;
+use crate::gitlab::{GitLabUrl, Gitlab};
+use crate::{
+    Cli, GitReference, Github, azure, bitbucket, fetch_crates_from_bare_git,
+    fetch_crates_from_github, fetch_crates_from_local, gitlab, is_bare_git_repo, is_git_missing,
+};
+
+pub struct LocalFetcher<'a> {
+    pub path: &'a str,
+    pub crates_root: &'a str,
+}
+
+impl CrateFetcher for LocalFetcher<'_> {
+    fn fetch(&self) -> Result<Vec<String>> {
+        if !is_bare_git_repo(self.path) {
+            return fetch_crates_from_local(self.path, self.crates_root);
+        }
+        match fetch_crates_from_bare_git(self.path, self.crates_root) {
+            Err(err) if is_git_missing(&err) => {
+                fetch_crates_from_local(self.path, self.crates_root)
+            }
+            crates => crates,
+        }
+    }
+}
+
+pub struct GithubFetcher<'a> {
+    pub repo: &'a str,
+    pub crates_root: &'a str,
+    pub git_ref: &'a str,
+    pub github: &'a Github,
+}
+
+impl CrateFetcher for GithubFetcher<'_> {
+    fn fetch(&self) -> Result<Vec<String>> {
+        fetch_crates_from_github(self.repo, self.crates_root, self.git_ref, self.github)
+    }
+}
+
+pub struct GitlabFetcher<'a> {
+    pub url: GitLabUrl,
+    pub crates_root: &'a str,
+    pub git_ref: &'a str,
+    pub gitlab: Gitlab,
+}
+
+impl CrateFetcher for GitlabFetcher<'_> {
+    fn fetch(&self) -> Result<Vec<String>> {
+        gitlab::fetch_crates_from_gitlab(&self.url, self.crates_root, self.git_ref, &self.gitlab)
+    }
+}
+
+pub struct BitbucketFetcher<'a> {
+    pub workspace: &'a str,
+    pub repo: &'a str,
+    pub crates_root: &'a str,
+    pub git_ref: &'a str,
+    pub bitbucket: Bitbucket,
+}
+
+impl CrateFetcher for BitbucketFetcher<'_> {
+    fn fetch(&self) -> Result<Vec<String>> {
+        bitbucket::fetch_crates_from_bitbucket(
+            self.workspace,
+            self.repo,
+            self.crates_root,
+            self.git_ref,
+            &self.bitbucket,
+        )
+    }
+}
+
+pub struct AzureFetcher<'a> {
+    pub org: &'a str,
+    pub project: &'a str,
+    pub repo: &'a str,
+    pub crates_root: &'a str,
+    pub reference: &'a GitReference,
+    pub azure: Azure,
+}
+
+impl CrateFetcher for AzureFetcher<'_> {
+    fn fetch(&self) -> Result<Vec<String>> {
+        azure::fetch_crates_from_azure(
+            self.org,
+            self.project,
+            self.repo,
+            self.crates_root,
+            self.reference,
+            &self.azure,
+        )
+    }
+}
+
+// Picks the backend from the repository URL, GitHub unless it looks like another host.
+pub fn for_repo<'a>(
+    repo: &'a str,
+    reference: &'a GitReference,
+    cli: &'a Cli,
+    github: &'a Github,
+) -> Result<Box<dyn CrateFetcher + 'a>> {
+    let crates_root = cli.crates_root();
+    let git_ref = reference.as_str();
+    Ok(if bitbucket::is_bitbucket(repo) {
+        let (workspace, repo) = bitbucket::parse_bitbucket_repo(repo)
+            .ok_or_else(|| anyhow::anyhow!("{repo} isn't a Bitbucket repository"))?;
+        Box::new(BitbucketFetcher {
+            workspace,
+            repo,
+            crates_root,
+            git_ref,
+            bitbucket: cli.bitbucket(),
+        })
+    } else if gitlab::is_gitlab(repo) {
+        let url = gitlab::parse_gitlab_url(repo)
+            .ok_or_else(|| anyhow::anyhow!("{repo} isn't a GitLab repository"))?;
+        Box::new(GitlabFetcher {
+            url,
+            crates_root,
+            git_ref,
+            gitlab: cli.gitlab(),
+        })
+    } else if azure::is_azure(repo) {
+        let (org, project, repo) = azure::parse_azure_repo(repo)
+            .ok_or_else(|| anyhow::anyhow!("{repo} isn't an Azure DevOps repository"))?;
+        Box::new(AzureFetcher {
+            org,
+            project,
+            repo,
+            crates_root,
+            reference,
+            azure: cli.azure(),
+        })
+    } else {
+        Box::new(GithubFetcher {
+            repo,
+            crates_root,
+            git_ref,
+            github,
+        })
+    })
+}
@@ -0,0 +1,965 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+
+use crate::repo_url::RepoUrl;
+use crate::signed::Verification;
+use crate::{PatchEntry, PatchGroup, PatchSource};
+
+// Forks are compared against this repository in markdown output.
+const UPSTREAM: (&str, &str) = ("bevyengine", "bevy");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// `[patch.crates-io]` entries for Cargo.toml
+    Toml,
+    /// A Nix overlay overriding the bevy sources
+    Nix,
+    /// A JSON object listing the patch entries
+    Json,
+    /// The TOML patch with links and a crate list, for PR descriptions and issues
+    Markdown,
+    /// `[source.crates-io] replace-with`, replacing every crates.io crate with the git source
+    SourceReplacement,
+    /// `--config` arguments for cargo, quoted for `--shell`, e.g. `eval "cargo build $(bevy-patch ... )"`
+    CargoArgs,
+    /// One unquoted `--config=...` argument per line, e.g. for `xargs -d '\n'`
+    CargoArgsLines,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Sh,
+    Powershell,
+}
+
+impl Shell {
+    pub fn quote(self, arg: &str) -> String {
+        match self {
+            Shell::Sh => format!("'{}'", arg.replace('\'', "'\\''")),
+            Shell::Powershell => format!("'{}'", arg.replace('\'', "''")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum NixFetcher {
+    #[value(name = "fetchFromGitHub")]
+    FetchFromGitHub,
+    #[value(name = "fetchgit")]
+    Fetchgit,
+}
+
+impl NixFetcher {
+    fn name(self) -> &'static str {
+        match self {
+            NixFetcher::FetchFromGitHub => "fetchFromGitHub",
+            NixFetcher::Fetchgit => "fetchgit",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GroupBy {
+    /// A comment naming the source above each group of entries
+    Source,
+    /// `# --- Rendering ---` style comments, grouping crates by engine area
+    Component,
+}
+
+// A crate name prefix and the area of the engine it belongs to.
+type CrateGroup = (&'static str, &'static str);
+
+// The longest matching prefix wins, e.g. `bevy_core_pipeline` is rendering, not core.
+static CRATE_GROUPS: &[CrateGroup] = &[
+    ("bevy_a11y", "Windowing"),
+    ("bevy_animation", "Animation"),
+    ("bevy_anti_aliasing", "Rendering"),
+    ("bevy_app", "Core"),
+    ("bevy_asset", "Assets"),
+    ("bevy_audio", "Audio"),
+    ("bevy_camera", "Rendering"),
+    ("bevy_color", "Rendering"),
+    ("bevy_core", "Core"),
+    ("bevy_core_pipeline", "Rendering"),
+    ("bevy_derive", "Core"),
+    ("bevy_dev_tools", "Dev tools"),
+    ("bevy_diagnostic", "Dev tools"),
+    ("bevy_ecs", "ECS"),
+    ("bevy_encase_derive", "Rendering"),
+    ("bevy_feathers", "UI"),
+    ("bevy_gilrs", "Input"),
+    ("bevy_gizmos", "Rendering"),
+    ("bevy_gltf", "Assets"),
+    ("bevy_hierarchy", "ECS"),
+    ("bevy_image", "Rendering"),
+    ("bevy_input", "Input"),
+    ("bevy_internal", "Core"),
+    ("bevy_light", "Rendering"),
+    ("bevy_log", "Core"),
+    ("bevy_macro_utils", "Core"),
+    ("bevy_math", "Math"),
+    ("bevy_mesh", "Rendering"),
+    ("bevy_pbr", "Rendering"),
+    ("bevy_picking", "Input"),
+    ("bevy_platform", "Core"),
+    ("bevy_post_process", "Rendering"),
+    ("bevy_ptr", "ECS"),
+    ("bevy_reflect", "Reflection"),
+    ("bevy_remote", "Dev tools"),
+    ("bevy_render", "Rendering"),
+    ("bevy_scene", "Assets"),
+    ("bevy_shader", "Rendering"),
+    ("bevy_sprite", "Rendering"),
+    ("bevy_state", "Core"),
+    ("bevy_tasks", "Core"),
+    ("bevy_text", "UI"),
+    ("bevy_time", "Core"),
+    ("bevy_transform", "Math"),
+    ("bevy_ui", "UI"),
+    ("bevy_utils", "Core"),
+    ("bevy_window", "Windowing"),
+    ("bevy_winit", "Windowing"),
+];
+
+fn component_of(name: &str) -> Option<&'static str> {
+    CRATE_GROUPS
+        .iter()
+        .filter(|(prefix, _)| name.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, label)| *label)
+}
+
+// Entries sorted by component label, ungrouped ones last, with the label on the first of each.
+fn by_component(entries: &[PatchEntry]) -> Vec<(Option<&'static str>, &PatchEntry)> {
+    let mut sorted: Vec<_> = entries
+        .iter()
+        .map(|entry| (component_of(&entry.name), entry))
+        .collect();
+    sorted.sort_by_key(|(label, _)| (label.is_none(), *label));
+
+    let mut previous = None;
+    sorted
+        .into_iter()
+        .map(|(label, entry)| {
+            let first = label != previous;
+            previous = label;
+            (first.then_some(label.unwrap_or("Other")), entry)
+        })
+        .collect()
+}
+
+pub struct FormatOptions {
+    pub format: Format,
+    pub toml: TomlFormatter,
+    pub nix_fetcher: NixFetcher,
+    pub nix_sha256: Option<String>,
+    pub shell: Shell,
+    // Commits git sources resolved to, only needed by `--format markdown`.
+    pub commits: HashMap<PatchSource, String>,
+    // Commit signatures checked by `--require-signed`, noted next to their group.
+    pub verifications: HashMap<PatchSource, Verification>,
+    pub stats: Option<Stats>,
+}
+
+// A summary of the run, `--no-stats` leaves it out.
+#[derive(Debug, Clone)]
+pub struct Stats {
+    pub crate_count: usize,
+    // `github.com/bevyengine/bevy @ main`, sources joined with `, `.
+    pub source: String,
+    // Only with `--timings`, the time isn't the same from run to run.
+    pub elapsed: Option<Duration>,
+}
+
+impl Stats {
+    pub fn comment(&self) -> String {
+        let mut comment = format!("# {} crates patched from {}", self.crate_count, self.source);
+        if let Some(elapsed) = self.elapsed {
+            comment.push_str(&format!(" (fetched in {:.1}s)", elapsed.as_secs_f64()));
+        }
+        comment
+    }
+
+    fn json(&self) -> serde_json::Value {
+        let mut stats = serde_json::json!({
+            "crate_count": self.crate_count,
+            "source": self.source,
+        });
+        if let Some(elapsed) = self.elapsed {
+            stats["elapsed_ms"] = (elapsed.as_millis() as u64).into();
+        }
+        stats
+    }
+}
+
+impl FormatOptions {
+    fn toml(&self) -> TomlFormatter {
+        TomlFormatter {
+            verifications: self.verifications.clone(),
+            stats: self.stats.clone(),
+            ..self.toml.clone()
+        }
+    }
+
+    pub fn formatter(&self) -> Box<dyn Formatter> {
+        match self.format {
+            Format::Toml => Box::new(self.toml()),
+            Format::Nix => Box::new(NixFormatter {
+                fetcher: self.nix_fetcher,
+                sha256: self.nix_sha256.clone(),
+            }),
+            Format::Json => Box::new(JsonFormatter {
+                extra_registries: self.toml.extra_registries.clone(),
+                verifications: self.verifications.clone(),
+                stats: self.stats.clone(),
+            }),
+            Format::Markdown => Box::new(MarkdownFormatter {
+                toml: self.toml(),
+                commits: self.commits.clone(),
+            }),
+            Format::SourceReplacement => Box::new(SourceReplacementFormatter),
+            Format::CargoArgs => Box::new(CargoArgsFormatter {
+                shell: Some(self.shell),
+                extra_registries: self.toml.extra_registries.clone(),
+            }),
+            Format::CargoArgsLines => Box::new(CargoArgsFormatter {
+                shell: None,
+                extra_registries: self.toml.extra_registries.clone(),
+            }),
+        }
+    }
+}
+
+pub trait Formatter {
+    fn format(&self, groups: &[PatchGroup]) -> Result<String>;
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TomlFormatter {
+    // Pad keys so the `=` line up.
+    pub align: bool,
+    pub group_by: Option<GroupBy>,
+    // `[patch.crates-io.<name>]` tables instead of inline tables.
+    pub expand: bool,
+    // Registry URLs and the crates patched in their `[patch.'<url>']` table.
+    pub extra_registries: Vec<(String, Vec<String>)>,
+    pub verifications: HashMap<PatchSource, Verification>,
+    pub stats: Option<Stats>,
+}
+
+const CRATES_IO: &str = "crates-io";
+
+// `--extra-registry <url>:<crate>,<crate>`
+pub fn parse_extra_registry(spec: &str) -> Result<(String, Vec<String>), String> {
+    let (url, crates) = spec
+        .rsplit_once(':')
+        .filter(|(url, crates)| !url.is_empty() && !crates.contains('/'))
+        .ok_or_else(|| format!("{spec:?} should look like <url>:<crate>,<crate>"))?;
+    let crates: Vec<String> = crates
+        .split(',')
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+        .collect();
+    if crates.is_empty() {
+        return Err(format!("{spec:?} doesn't list any crates"));
+    }
+    Ok((url.to_owned(), crates))
+}
+
+impl TomlFormatter {
+    // The `[patch.<registry>]` table holding these groups.
+    fn section(&self, registry: &str, groups: &[PatchGroup]) -> Vec<String> {
+        let mut result = Vec::new();
+        result.push(format!("[patch.{registry}]"));
+        result.push("# Bevy Patch".to_owned());
+        if let Some(stats) = &self.stats {
+            result.push(stats.comment());
+        }
+
+        let entries = groups.iter().flat_map(|group| &group.entries);
+        let name_width = if self.align {
+            entries
+                .map(|entry| crate::toml_key(&entry.name).len())
+                .max()
+                .unwrap_or(0)
+        } else {
+            0
+        };
+
+        for (i, group) in groups.iter().enumerate() {
+            let commented = match self.group_by {
+                Some(GroupBy::Source) => true,
+                Some(GroupBy::Component) | None => groups.len() > 1,
+            };
+            if commented {
+                if self.expand || (self.group_by.is_some() && i > 0) {
+                    result.push(String::new());
+                }
+                result.push(format!("# {}", group.source.label()));
+            }
+            if let Some(verification) = self.verifications.get(&group.source) {
+                result.push(format!("# signature: {}", verification.describe()));
+            }
+
+            let entries: Vec<_> = if self.group_by == Some(GroupBy::Component) {
+                by_component(&group.entries)
+            } else {
+                group.entries.iter().map(|entry| (None, entry)).collect()
+            };
+            for (j, (component, entry)) in entries.into_iter().enumerate() {
+                if let Some(component) = component {
+                    if j > 0 || (self.expand && !commented) {
+                        result.push(String::new());
+                    }
+                    result.push(format!("# --- {component} ---"));
+                }
+                if !self.expand {
+                    if self.align {
+                        let fields: Vec<String> = entry
+                            .fields(&group.source)
+                            .into_iter()
+                            .map(|(key, value)| format!("{key} = {value}"))
+                            .collect();
+                        result.push(format!(
+                            "{:name_width$} = {{ {} }}",
+                            crate::toml_key(&entry.name),
+                            fields.join(", ")
+                        ));
+                    } else {
+                        result.push(entry.line(&group.source));
+                    }
+                    continue;
+                }
+
+                // The group comment sits right above the first table.
+                if !(commented && j == 0) && component.is_none() {
+                    result.push(String::new());
+                }
+                result.push(format!(
+                    "[patch.{registry}.{}]",
+                    crate::toml_key(&entry.name)
+                ));
+                let fields = entry.fields(&group.source);
+                let key_width = if self.align {
+                    fields.iter().map(|(key, _)| key.len()).max().unwrap_or(0)
+                } else {
+                    0
+                };
+                for (key, value) in fields {
+                    result.push(format!("{key:key_width$} = {value}"));
+                }
+            }
+        }
+
+        result
+    }
+}
+
+impl Formatter for TomlFormatter {
+    fn format(&self, groups: &[PatchGroup]) -> Result<String> {
+        // Keyed by registry URL, crates that aren't listed for one stay in crates-io.
+        let mut registries: BTreeMap<String, Vec<PatchGroup>> = BTreeMap::new();
+        for group in groups {
+            for entry in &group.entries {
+                let registry =
+                    extra_registry_of(&self.extra_registries, &entry.name).unwrap_or(CRATES_IO);
+                let registry_groups = registries.entry(registry.to_owned()).or_default();
+                match registry_groups.last_mut() {
+                    Some(last) if last.source == group.source => last.entries.push(entry.clone()),
+                    _ => registry_groups.push(PatchGroup {
+                        source: group.source.clone(),
+                        entries: vec![entry.clone()],
+                    }),
+                }
+            }
+        }
+
+        for (url, crates) in &self.extra_registries {
+            for name in crates {
+                if !groups
+                    .iter()
+                    .any(|group| group.entries.iter().any(|entry| &entry.name == name))
+                {
+                    warning!("{name} is listed for {url} but isn't patched");
+                }
+            }
+        }
+
+        let mut sections = Vec::new();
+        if registries.contains_key(CRATES_IO) || self.extra_registries.is_empty() {
+            let groups = registries.remove(CRATES_IO).unwrap_or_default();
+            sections.push(self.section(CRATES_IO, &groups).join("\n"));
+        }
+        for (url, _) in &self.extra_registries {
+            if let Some(groups) = registries.remove(url) {
+                sections.push(self.section(&registry_key(url), &groups).join("\n"));
+            }
+        }
+
+        Ok(sections.join("\n\n"))
+    }
+}
+
+// The `--extra-registry` URL a crate is patched in, `None` for crates-io.
+fn extra_registry_of<'a>(
+    extra_registries: &'a [(String, Vec<String>)],
+    name: &str,
+) -> Option<&'a str> {
+    extra_registries
+        .iter()
+        .find(|(_, crates)| crates.iter().any(|listed| listed == name))
+        .map(|(url, _)| url.as_str())
+}
+
+// TOML literal string when possible, as in `[patch.'https://...']`.
+fn registry_key(url: &str) -> String {
+    if url.contains('\'') {
+        crate::toml_string(url)
+    } else {
+        format!("'{url}'")
+    }
+}
+
+pub struct NixFormatter {
+    fetcher: NixFetcher,
+    sha256: Option<String>,
+}
+
+impl NixFormatter {
+    fn src(&self, source: &PatchSource) -> Result<String> {
+        let (repo, rev) = match source {
+            PatchSource::Path { path } => {
+                // Nix path literals need a slash to be told apart from identifiers.
+                return Ok(if path.starts_with('/') || path.starts_with('.') {
+                    path.clone()
+                } else {
+                    format!("./{path}")
+                });
+            }
+            PatchSource::Git { repo, reference } => (repo, reference.as_str()),
+        };
+
+        let github = github_owner_repo(repo);
+        let sha256 = match &self.sha256 {
+            Some(sha256) => sha256.clone(),
+            None => {
+                let (owner, name) = github.as_ref().ok_or_else(|| {
+                    anyhow::anyhow!("Can only prefetch GitHub sources, pass --nix-sha256")
+                })?;
+                prefetch_sha256(&format!(
+                    "https://github.com/{owner}/{name}/archive/{rev}.tar.gz"
+                ))?
+            }
+        };
+
+        let fetch = match self.fetcher {
+            NixFetcher::FetchFromGitHub => {
+                let (owner, name) = github.as_ref().ok_or_else(|| {
+                    anyhow::anyhow!("{repo} isn't on GitHub, use --nix-fetcher fetchgit")
+                })?;
+                format!(
+                    "owner = {}; repo = {}; rev = {};",
+                    nix_string(owner),
+                    nix_string(name),
+                    nix_string(rev)
+                )
+            }
+            NixFetcher::Fetchgit => {
+                format!("url = {}; rev = {};", nix_string(repo), nix_string(rev))
+            }
+        };
+
+        Ok(format!(
+            "prev.{} {{ {fetch} sha256 = {}; }}",
+            self.fetcher.name(),
+            nix_string(&sha256)
+        ))
+    }
+}
+
+impl Formatter for NixFormatter {
+    fn format(&self, groups: &[PatchGroup]) -> Result<String> {
+        let [group] = groups else {
+            anyhow::bail!("--format nix needs all crates to come from one source");
+        };
+
+        let mut result = Vec::new();
+        result.push("final: prev:".to_owned());
+        result.push("let".to_owned());
+        result.push(format!("  src = {};", self.src(&group.source)?));
+        result.push("in".to_owned());
+        result.push("{".to_owned());
+
+        for entry in &group.entries {
+            let src = if entry.subpath.is_empty() {
+                "inherit src;".to_owned()
+            } else {
+                format!("src = \"${{src}}/{}\";", entry.subpath)
+            };
+            result.push(format!(
+                "  {name} = prev.{name}.override {{ {src} }};",
+                name = entry.name
+            ));
+        }
+
+        result.push("}".to_owned());
+        Ok(result.join("\n"))
+    }
+}
+
+pub struct JsonFormatter {
+    // Entries of these crates get the registry URL as `registry`.
+    extra_registries: Vec<(String, Vec<String>)>,
+    verifications: HashMap<PatchSource, Verification>,
+    stats: Option<Stats>,
+}
+
+impl Formatter for JsonFormatter {
+    fn format(&self, groups: &[PatchGroup]) -> Result<String> {
+        let mut patches = Vec::new();
+        for group in groups {
+            for entry in &group.entries {
+                let mut patch = entry.json(&group.source);
+                if let Some(url) = extra_registry_of(&self.extra_registries, &entry.name) {
+                    patch.insert("registry".to_owned(), url.into());
+                }
+                if let Some(verification) = self.verifications.get(&group.source) {
+                    patch.insert("signature".to_owned(), serde_json::to_value(verification)?);
+                }
+                patches.push(serde_json::Value::Object(patch));
+            }
+        }
+
+        let mut root = serde_json::json!({ "patches": patches });
+        if let Some(stats) = &self.stats {
+            root["stats"] = stats.json();
+        }
+        Ok(serde_json::to_string_pretty(&root)?)
+    }
+}
+
+pub struct MarkdownFormatter {
+    toml: TomlFormatter,
+    commits: HashMap<PatchSource, String>,
+}
+
+impl MarkdownFormatter {
+    fn describe(&self, source: &PatchSource) -> String {
+        let (repo, reference) = match source {
+            PatchSource::Path { path } => return format!("the local checkout at `{path}`"),
+            PatchSource::Git { repo, reference } => (repo, reference),
+        };
+
+        let sha = match reference {
+            crate::GitReference::Rev(rev) => Some(rev),
+            _ => self.commits.get(source),
+        };
+        let (Some((owner, name)), Some(sha)) = (github_owner_repo(repo), sha) else {
+            return format!("{repo} at `{}`", reference.as_str());
+        };
+
+        // GitHub compares across forks with the `owner:repo:ref` syntax.
+        let head = if (owner.as_str(), name.as_str()) == UPSTREAM {
+            sha.clone()
+        } else {
+            format!("{owner}:{name}:{sha}")
+        };
+        format!(
+            "[{owner}/{name}](https://github.com/{owner}/{name}) at `{}` \
+             ([{}](https://github.com/{owner}/{name}/commit/{sha}), \
+             [compare with bevy main](https://github.com/{}/{}/compare/main...{head}))",
+            reference.as_str(),
+            &sha[..sha.len().min(7)],
+            UPSTREAM.0,
+            UPSTREAM.1
+        )
+    }
+}
+
+impl Formatter for MarkdownFormatter {
+    fn format(&self, groups: &[PatchGroup]) -> Result<String> {
+        let sources: Vec<String> = groups
+            .iter()
+            .map(|group| self.describe(&group.source))
+            .collect();
+        let crates: Vec<&str> = groups
+            .iter()
+            .flat_map(|group| &group.entries)
+            .map(|entry| entry.name.as_str())
+            .collect();
+
+        let mut result = Vec::new();
+        result.push(format!(
+            "To test this, patch bevy to {} by adding this to your Cargo.toml:",
+            sources.join(" and ")
+        ));
+        result.push(String::new());
+        result.push("```toml".to_owned());
+        result.push(self.toml.format(groups)?);
+        result.push("```".to_owned());
+        result.push(String::new());
+        result.push("<details>".to_owned());
+        result.push(format!(
+            "<summary>{} patched crates</summary>",
+            crates.len()
+        ));
+        result.push(String::new());
+        for name in crates {
+            result.push(format!("- `{name}`"));
+        }
+        result.push(String::new());
+        result.push("</details>".to_owned());
+
+        Ok(result.join("\n"))
+    }
+}
+
+// Name of the replacement source in the generated `[source]` tables.
+const REPLACEMENT_SOURCE: &str = "vendored";
+
+pub struct SourceReplacementFormatter;
+
+impl Formatter for SourceReplacementFormatter {
+    fn format(&self, groups: &[PatchGroup]) -> Result<String> {
+        let Some((repo, reference)) = groups.iter().find_map(|group| match &group.source {
+            PatchSource::Git { repo, reference } => Some((repo, reference)),
+            PatchSource::Path { .. } => None,
+        }) else {
+            anyhow::bail!("--format source-replacement needs a git source");
+        };
+        if groups.len() > 1 {
+            warning!("only {repo} is used, source replacement takes a single source");
+        }
+        warning!(
+            "source replacement swaps out crates.io for every dependency, not just bevy, \
+             any crate missing from {repo} will fail to resolve"
+        );
+
+        let mut result = Vec::new();
+        result.push("[source.crates-io]".to_owned());
+        result.push(format!(
+            "replace-with = {}",
+            crate::toml_string(REPLACEMENT_SOURCE)
+        ));
+        result.push(String::new());
+        result.push(format!("[source.{REPLACEMENT_SOURCE}]"));
+        result.push(format!("git = {}", crate::toml_string(repo)));
+        result.push(format!(
+            "{} = {}",
+            reference.key(),
+            crate::toml_string(reference.as_str())
+        ));
+
+        Ok(result.join("\n"))
+    }
+}
+
+pub struct CargoArgsFormatter {
+    // Arguments are printed one per line and unquoted without a shell.
+    shell: Option<Shell>,
+    extra_registries: Vec<(String, Vec<String>)>,
+}
+
+impl Formatter for CargoArgsFormatter {
+    fn format(&self, groups: &[PatchGroup]) -> Result<String> {
+        let mut configs = Vec::new();
+        for group in groups {
+            for entry in &group.entries {
+                let mut fields = match &group.source {
+                    PatchSource::Path { path } if entry.subpath.is_empty() => {
+                        vec![("path", path.clone())]
+                    }
+                    PatchSource::Path { path } => {
+                        vec![("path", format!("{path}/{}", entry.subpath))]
+                    }
+                    PatchSource::Git { repo, reference } => vec![
+                        ("git", repo.clone()),
+                        (reference.key(), reference.as_str().to_owned()),
+                    ],
+                };
+                if let Some(version) = &entry.version {
+                    fields.push(("version", version.clone()));
+                }
+                let registry = extra_registry_of(&self.extra_registries, &entry.name)
+                    .map_or_else(|| CRATES_IO.to_owned(), registry_key);
+                for (key, value) in fields {
+                    configs.push(format!(
+                        "patch.{registry}.{}.{key}={}",
+                        crate::toml_key(&entry.name),
+                        crate::toml_string(&value)
+                    ));
+                }
+            }
+        }
+
+        Ok(match self.shell {
+            Some(shell) => configs
+                .iter()
+                .map(|config| format!("--config {}", shell.quote(config)))
+                .collect::<Vec<_>>()
+                .join(" "),
+            None => configs
+                .iter()
+                .map(|config| format!("--config={config}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        })
+    }
+}
+
+// Only github.com itself, `notgithub.com/a/b` or a path mentioning it isn't one.
+fn github_owner_repo(repo: &str) -> Option<(String, String)> {
+    let url = RepoUrl::parse(repo).ok()?;
+    if !url.host.eq_ignore_ascii_case("github.com") || url.owner.contains('/') {
+        return None;
+    }
+    let name = url.repo.trim_end_matches(".git").to_owned();
+    Some((url.owner, name))
+}
+
+fn nix_string(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${");
+    format!("\"{escaped}\"")
+}
+
+fn prefetch_sha256(url: &str) -> Result<String> {
+    let output = std::process::Command::new("nix-prefetch-url")
+        .args(["--unpack", url])
+        .output()
+        .context("Failed to run nix-prefetch-url, pass --nix-sha256 instead")?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "nix-prefetch-url failed for {url}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8(output.stdout)?.trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GitReference;
+
+    const REGISTRY: &str = "https://my-registry.com/index";
+
+    fn groups() -> Vec<PatchGroup> {
+        vec![
+            PatchGroup {
+                source: PatchSource::Path {
+                    path: r#"C:\work\"bevy""#.to_owned(),
+                },
+                entries: vec![
+                    PatchEntry::root(),
+                    PatchEntry::discovered("crates", r#"bevy_"quoted"\name"#),
+                ],
+            },
+            PatchGroup {
+                source: PatchSource::Git {
+                    repo: "https://github.com/alice/bevy".to_owned(),
+                    reference: GitReference::Branch(r#"fix/"it"\now"#.to_owned()),
+                },
+                entries: vec![
+                    PatchEntry::discovered("crates", "bevy_render"),
+                    PatchEntry::discovered("crates", "bevy_ecs"),
+                ],
+            },
+        ]
+    }
+
+    fn extra_registry_options() -> [Vec<(String, Vec<String>)>; 2] {
+        [
+            Vec::new(),
+            vec![(REGISTRY.to_owned(), vec!["bevy_ecs".to_owned()])],
+        ]
+    }
+
+    // The registry and fields each crate should be patched with, as `--format json` has them.
+    fn expected(
+        extra_registries: &[(String, Vec<String>)],
+    ) -> Vec<(&'static str, String, serde_json::Value)> {
+        groups()
+            .iter()
+            .flat_map(|group| {
+                group.entries.iter().map(|entry| {
+                    let mut fields = entry.json(&group.source);
+                    fields.remove("name");
+                    let registry = extra_registry_of(extra_registries, &entry.name)
+                        .map_or(CRATES_IO, |_| REGISTRY);
+                    (registry, entry.name.clone(), fields.into())
+                })
+            })
+            .collect()
+    }
+
+    // Every `[patch.<registry>.<name>]` of a TOML document, whatever form it was written in.
+    fn check_toml(output: &str, extra_registries: &[(String, Vec<String>)]) {
+        let parsed: toml::Table =
+            toml::from_str(output).unwrap_or_else(|err| panic!("{err}\n{output}"));
+        let expected = expected(extra_registries);
+        let patched: usize = parsed["patch"]
+            .as_table()
+            .unwrap()
+            .values()
+            .map(|patches| patches.as_table().unwrap().len())
+            .sum();
+        assert_eq!(patched, expected.len(), "{output}");
+        for (registry, name, fields) in expected {
+            let actual = serde_json::to_value(&parsed["patch"][registry][&name]).unwrap();
+            assert_eq!(actual, fields, "{name} in {registry}\n{output}");
+        }
+    }
+
+    #[test]
+    fn toml_output_parses_back_in_every_style() {
+        for align in [false, true] {
+            for group_by in [None, Some(GroupBy::Source), Some(GroupBy::Component)] {
+                for expand in [false, true] {
+                    for extra_registries in extra_registry_options() {
+                        let formatter = TomlFormatter {
+                            align,
+                            group_by,
+                            expand,
+                            extra_registries: extra_registries.clone(),
+                            stats: Some(Stats {
+                                crate_count: 4,
+                                source: "C:\\work, \"bevy\"".to_owned(),
+                                elapsed: None,
+                            }),
+                            ..TomlFormatter::default()
+                        };
+                        check_toml(&formatter.format(&groups()).unwrap(), &extra_registries);
+
+                        let markdown = MarkdownFormatter {
+                            toml: formatter,
+                            commits: HashMap::new(),
+                        }
+                        .format(&groups())
+                        .unwrap();
+                        let block = markdown
+                            .split_once("```toml\n")
+                            .and_then(|(_, rest)| rest.split_once("\n```"))
+                            .unwrap()
+                            .0;
+                        check_toml(block, &extra_registries);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn json_output_parses_back() {
+        for extra_registries in extra_registry_options() {
+            let output = JsonFormatter {
+                extra_registries: extra_registries.clone(),
+                verifications: HashMap::new(),
+                stats: None,
+            }
+            .format(&groups())
+            .unwrap();
+            let parsed: serde_json::Value = serde_json::from_str(&output).unwrap();
+            let patches = parsed["patches"].as_array().unwrap();
+            let expected = expected(&extra_registries);
+            assert_eq!(patches.len(), expected.len());
+            for (patch, (registry, name, mut fields)) in patches.iter().zip(expected) {
+                fields["name"] = name.into();
+                if registry != CRATES_IO {
+                    fields["registry"] = registry.into();
+                }
+                assert_eq!(*patch, fields);
+            }
+        }
+    }
+
+    #[test]
+    fn cargo_args_parse_back() {
+        for extra_registries in extra_registry_options() {
+            let output = CargoArgsFormatter {
+                shell: None,
+                extra_registries: extra_registries.clone(),
+            }
+            .format(&groups())
+            .unwrap();
+            // cargo reads each `--config` value as a TOML document of one dotted key.
+            let mut merged = toml::Table::new();
+            for line in output.lines() {
+                let config = line.strip_prefix("--config=").unwrap();
+                let parsed: toml::Table =
+                    toml::from_str(config).unwrap_or_else(|err| panic!("{err}\n{config}"));
+                let mut table = &parsed;
+                let mut target = &mut merged;
+                // Down to the crate's table, then its one key.
+                while let [(key, toml::Value::Table(inner))] = table.iter().collect::<Vec<_>>()[..]
+                {
+                    target = target
+                        .entry(key.clone())
+                        .or_insert_with(|| toml::Table::new().into())
+                        .as_table_mut()
+                        .unwrap();
+                    table = inner;
+                }
+                target.extend(table.clone());
+            }
+            check_toml(&toml::to_string(&merged).unwrap(), &extra_registries);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cargo_args_are_quoted_for_sh() {
+        let format = |shell| {
+            CargoArgsFormatter {
+                shell,
+                extra_registries: Vec::new(),
+            }
+            .format(&groups())
+            .unwrap()
+        };
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("printf '%s\\n' {}", format(Some(Shell::Sh))))
+            .output()
+            .unwrap();
+        let unquoted = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<String> = format(None)
+            .lines()
+            .flat_map(|line| ["--config".to_owned(), line["--config=".len()..].to_owned()])
+            .collect();
+        assert_eq!(unquoted.lines().collect::<Vec<_>>(), lines);
+    }
+
+    #[test]
+    fn source_replacement_parses_back() {
+        let output = SourceReplacementFormatter.format(&groups()[1..]).unwrap();
+        let parsed: toml::Table = toml::from_str(&output).unwrap();
+        assert_eq!(
+            parsed["source"]["vendored"]["branch"].as_str(),
+            Some(r#"fix/"it"\now"#)
+        );
+    }
+
+    #[test]
+    fn only_github_com_has_an_owner_and_repo() {
+        let owner_repo =
+            |repo| github_owner_repo(repo).map(|(owner, name)| format!("{owner}/{name}"));
+        assert_eq!(
+            owner_repo("https://github.com/bevyengine/bevy.git").as_deref(),
+            Some("bevyengine/bevy")
+        );
+        assert_eq!(owner_repo("alice/bevy").as_deref(), Some("alice/bevy"));
+        assert_eq!(owner_repo("https://notgithub.com/alice/bevy"), None);
+        assert_eq!(
+            owner_repo("https://gitlab.com/mirror/github.com/alice/bevy"),
+            None
+        );
+    }
+}
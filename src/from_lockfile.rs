@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::repo_url::RepoUrl;
+use crate::{GitReference, PatchEntry, PatchGroup, PatchSource};
+
+#[derive(Deserialize)]
+struct CargoLock {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+}
+
+// `git+https://github.com/bevyengine/bevy?branch=main#<sha>` -> the repository and the sha.
+fn git_source(source: &str) -> Option<(&str, &str)> {
+    let (url, sha) = source.strip_prefix("git+")?.rsplit_once('#')?;
+    Some((url.split('?').next()?, sha))
+}
+
+fn same_repo(a: &RepoUrl, b: &RepoUrl) -> bool {
+    a.host.eq_ignore_ascii_case(&b.host)
+        && a.owner.eq_ignore_ascii_case(&b.owner)
+        && a.repo
+            .trim_end_matches(".git")
+            .eq_ignore_ascii_case(b.repo.trim_end_matches(".git"))
+}
+
+// One group per revision of `repo` the lock file was built with.
+pub fn run(
+    lock_file: &Path,
+    repo: &str,
+    crates_root: &str,
+    add_version: bool,
+) -> Result<Vec<PatchGroup>> {
+    let content = std::fs::read_to_string(lock_file)
+        .with_context(|| format!("Failed to read {}", lock_file.display()))?;
+    let lock: CargoLock = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", lock_file.display()))?;
+    let wanted = RepoUrl::parse(repo)?;
+
+    let mut revisions: BTreeMap<(String, String), Vec<PatchEntry>> = BTreeMap::new();
+    for package in lock.package {
+        let Some((url, sha)) = package.source.as_deref().and_then(git_source) else {
+            continue;
+        };
+        if !RepoUrl::parse(url).is_ok_and(|url| same_repo(&url, &wanted)) {
+            continue;
+        }
+
+        let mut entry = if package.name == "bevy" {
+            PatchEntry::root()
+        } else {
+            PatchEntry::discovered(crates_root, &package.name)
+        };
+        if add_version {
+            entry.version = Some(package.version);
+        }
+        revisions
+            .entry((url.to_owned(), sha.to_owned()))
+            .or_default()
+            .push(entry);
+    }
+
+    if revisions.is_empty() {
+        anyhow::bail!("{} has no packages from {wanted}", lock_file.display());
+    }
+    if revisions.len() > 1 {
+        let shas: Vec<_> = revisions.keys().map(|(_, sha)| sha.as_str()).collect();
+        warning!(
+            "{} was built with several revisions of {wanted}: {}",
+            lock_file.display(),
+            shas.join(", ")
+        );
+    }
+
+    Ok(revisions
+        .into_iter()
+        .map(|((url, sha), mut entries)| {
+            entries.sort_by(|a, b| (a.name != "bevy", &a.name).cmp(&(b.name != "bevy", &b.name)));
+            PatchGroup {
+                source: PatchSource::Git {
+                    repo: RepoUrl::parse(&url).map_or(url, |url| url.to_string()),
+                    reference: GitReference::Rev(sha),
+                },
+                entries,
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,57 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use bevy_patch::is_bevy_crate;
+
+// `(registry, crate)` -> entry, for every `[patch.<registry>]` table.
+fn patch_entries(manifest: &toml::Table) -> BTreeMap<(String, String), toml::Value> {
+    let mut entries = BTreeMap::new();
+    let registries = manifest.get("patch").and_then(|patch| patch.as_table());
+    for (registry, patches) in registries.into_iter().flatten() {
+        for (name, entry) in patches.as_table().into_iter().flatten() {
+            entries.insert((registry.clone(), name.clone()), entry.clone());
+        }
+    }
+    entries
+}
+
+// Compares the entries bevy-patch would generate with the manifest's, by value so formatting
+// and comments don't count. Entries for other crates are left to their owners.
+pub fn check(cargo_toml: &Path, generated: &str) -> Result<()> {
+    let content = std::fs::read_to_string(cargo_toml)
+        .with_context(|| format!("Failed to read {}", cargo_toml.display()))?;
+    let manifest: toml::Table = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", cargo_toml.display()))?;
+    let generated: toml::Table =
+        toml::from_str(generated).context("Failed to parse the generated entries")?;
+
+    let expected = patch_entries(&generated);
+    let mut actual = patch_entries(&manifest);
+    actual.retain(|key, _| expected.contains_key(key) || is_bevy_crate(&key.1));
+
+    let mut diff = Vec::new();
+    let keys: std::collections::BTreeSet<_> = expected.keys().chain(actual.keys()).collect();
+    for key @ (registry, name) in keys {
+        let (old, new) = (actual.get(key), expected.get(key));
+        if old == new {
+            continue;
+        }
+        diff.push(format!("[patch.{registry}]"));
+        if let Some(old) = old {
+            diff.push(format!("-{name} = {old}"));
+        }
+        if let Some(new) = new {
+            diff.push(format!("+{name} = {new}"));
+        }
+    }
+
+    if !diff.is_empty() {
+        anyhow::bail!(
+            "{} doesn't match the generated entries:\n{}",
+            cargo_toml.display(),
+            diff.join("\n")
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::{PatchGroup, PatchSource, ci};
+
+fn append(path: &Path, content: &str) -> Result<()> {
+    std::fs::File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .with_context(|| format!("Failed to append to {}", path.display()))
+}
+
+fn source_row(group: &PatchGroup, commits: &HashMap<PatchSource, String>) -> [String; 4] {
+    let (repo, reference) = match &group.source {
+        PatchSource::Path { path } => (path.clone(), String::new()),
+        PatchSource::Git { repo, reference } => (repo.clone(), reference.as_str().to_owned()),
+    };
+    let sha = commits.get(&group.source).cloned().unwrap_or_default();
+    [repo, reference, sha, group.entries.len().to_string()]
+}
+
+// A table cell: a `|` would end the cell early and a line break the row.
+fn cell(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace(['\r', '\n'], " ")
+}
+
+fn summary(groups: &[PatchGroup], commits: &HashMap<PatchSource, String>) -> String {
+    let mut summary =
+        String::from("## bevy-patch\n\n| Source | Ref | SHA | Crates |\n|---|---|---|---|\n");
+    for group in groups {
+        let [repo, reference, sha, count] = source_row(group, commits);
+        let sha = if sha.is_empty() {
+            sha
+        } else {
+            format!("`{sha}`")
+        };
+        summary.push_str(&format!(
+            "| {} | {} | {sha} | {count} |\n",
+            cell(&repo),
+            cell(&reference)
+        ));
+    }
+    let warnings = ci::warnings();
+    if !warnings.is_empty() {
+        summary.push_str("\n### Warnings\n\n");
+        for warning in warnings {
+            summary.push_str(&format!("- {}\n", warning.replace('\n', " ")));
+        }
+    }
+    summary.push('\n');
+    summary
+}
+
+// Multi-line values use the heredoc form, with a delimiter that can't appear in crate names.
+fn outputs(groups: &[PatchGroup], commits: &HashMap<PatchSource, String>) -> String {
+    let sha = groups
+        .iter()
+        .find_map(|group| commits.get(&group.source))
+        .cloned()
+        .unwrap_or_default();
+    let crates: Vec<&str> = groups
+        .iter()
+        .flat_map(|group| &group.entries)
+        .map(|entry| entry.name.as_str())
+        .collect();
+    let delimiter = format!("bevy-patch-{}", std::process::id());
+    format!(
+        "sha={sha}\ncrates<<{delimiter}\n{}\n{delimiter}\n",
+        crates.join("\n")
+    )
+}
+
+// Appends the run summary and sets the `sha` and `crates` step outputs, outside Actions the
+// summary goes to stderr instead.
+pub fn report(groups: &[PatchGroup], commits: &HashMap<PatchSource, String>) -> Result<()> {
+    let Some(summary_path) = std::env::var_os("GITHUB_STEP_SUMMARY") else {
+        for group in groups {
+            let commit = commits
+                .get(&group.source)
+                .map(|sha| format!(" ({sha})"))
+                .unwrap_or_default();
+            eprintln!(
+                "note: {}{commit}, {} crates",
+                group.source.label(),
+                group.entries.len()
+            );
+        }
+        return Ok(());
+    };
+    append(Path::new(&summary_path), &summary(groups, commits))?;
+    if let Some(output_path) = std::env::var_os("GITHUB_OUTPUT") {
+        append(Path::new(&output_path), &outputs(groups, commits))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GitReference, PatchEntry};
+
+    fn groups() -> Vec<PatchGroup> {
+        vec![
+            PatchGroup {
+                source: PatchSource::Git {
+                    repo: "https://github.com/alice/bevy".to_owned(),
+                    reference: GitReference::Branch("fix|table\nrow".to_owned()),
+                },
+                entries: vec![
+                    PatchEntry::root(),
+                    PatchEntry::discovered("crates", "bevy_ecs"),
+                ],
+            },
+            PatchGroup {
+                source: PatchSource::Path {
+                    path: r"C:\work\bevy".to_owned(),
+                },
+                entries: vec![PatchEntry::discovered("crates", "bevy_render")],
+            },
+        ]
+    }
+
+    #[test]
+    fn summary_table_has_one_row_per_source() {
+        let groups = groups();
+        let commits = HashMap::from([(groups[0].source.clone(), "abc123".to_owned())]);
+        crate::ci::warning("two\nlines");
+        let summary = summary(&groups, &commits);
+        assert!(
+            summary.contains(
+                "| Source | Ref | SHA | Crates |\n|---|---|---|---|\n\
+                 | https://github.com/alice/bevy | fix\\|table row | `abc123` | 2 |\n\
+                 | C:\\\\work\\\\bevy |  |  | 1 |\n"
+            ),
+            "{summary}"
+        );
+        assert!(summary.contains("\n- two lines\n"), "{summary}");
+    }
+
+    #[test]
+    fn outputs_use_a_heredoc_for_the_crates() {
+        let groups = groups();
+        let commits = HashMap::from([(groups[0].source.clone(), "abc123".to_owned())]);
+        let delimiter = format!("bevy-patch-{}", std::process::id());
+        assert_eq!(
+            outputs(&groups, &commits),
+            format!("sha=abc123\ncrates<<{delimiter}\nbevy\nbevy_ecs\nbevy_render\n{delimiter}\n")
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::Github;
+
+const GISTS_URL: &str = "https://api.github.com/gists";
+// Far above any real patch, but keeps a mistaken run from uploading something huge.
+const MAX_GIST_BYTES: usize = 256 << 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ShareTarget {
+    /// A GitHub gist with the TOML patch and the markdown summary
+    Gist,
+}
+
+#[derive(Deserialize)]
+struct CreatedGist {
+    html_url: String,
+}
+
+// Returns the gist's URL.
+pub fn create(toml: &str, markdown: &str, public: bool, github: &Github) -> Result<String> {
+    if github.token.is_none() {
+        anyhow::bail!(
+            "Creating a gist needs a GitHub token with the gist scope, pass --token or set GITHUB_TOKEN"
+        );
+    }
+    let size = toml.len() + markdown.len();
+    if size > MAX_GIST_BYTES {
+        anyhow::bail!(
+            "The patch is {} KB, more than the {} KB a shared gist may hold",
+            size.div_ceil(1024),
+            MAX_GIST_BYTES >> 10
+        );
+    }
+
+    let body = serde_json::json!({
+        "description": "bevy-patch",
+        "public": public,
+        "files": {
+            "bevy-patch.toml": { "content": toml },
+            "README.md": { "content": markdown },
+        },
+    });
+    let gist: CreatedGist = github
+        .post(GISTS_URL, &body)
+        .context("Failed to create the gist, the token needs the gist scope")?;
+    Ok(gist.html_url)
+}
@@ -0,0 +1,165 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::encode_query;
+
+pub struct Gitlab {
+    pub client: reqwest::blocking::Client,
+    pub token: Option<String>,
+    pub timeout: Duration,
+}
+
+// `namespace` holds the group and any subgroups, e.g. `group/subgroup`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct GitLabUrl {
+    pub base: String,
+    pub namespace: String,
+    pub project: String,
+}
+
+impl GitLabUrl {
+    // The API takes the full path as one URL-encoded segment, `group%2Fsubgroup%2Fproject`.
+    pub fn api_url(&self) -> String {
+        format!(
+            "{}/api/v4/projects/{}",
+            self.base,
+            encode_query(&format!("{}/{}", self.namespace, self.project))
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct GitlabTreeEntry {
+    name: String,
+    #[serde(rename = "type")]
+    entry_type: String,
+}
+
+fn host(url: &str) -> &str {
+    let path = url.split_once("://").map_or(url, |(_, path)| path);
+    path.split('/').next().unwrap_or_default()
+}
+
+pub fn is_gitlab(repo: &str) -> bool {
+    let host = host(repo);
+    host == "gitlab.com" || host.starts_with("gitlab.")
+}
+
+// https://gitlab.com/<group>/<subgroups...>/<project>, any depth of subgroups.
+pub fn parse_gitlab_url(url: &str) -> Option<GitLabUrl> {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("https", url));
+    let (host, path) = rest.split_once('/')?;
+    // Web URLs continue with `/-/tree/<ref>` and the like.
+    let path = path.split("/-/").next()?.trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    let (namespace, project) = path.rsplit_once('/')?;
+    if namespace.is_empty() || project.is_empty() {
+        return None;
+    }
+
+    Some(GitLabUrl {
+        base: format!("{scheme}://{host}"),
+        namespace: namespace.to_owned(),
+        project: project.to_owned(),
+    })
+}
+
+impl Gitlab {
+    // The parsed body and the next page, from the `X-Next-Page` header.
+    fn get<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<(T, Option<String>)> {
+        let mut request = self
+            .client
+            .get(url)
+            .timeout(self.timeout)
+            .header("User-Agent", "bevy-patch");
+        if let Some(token) = &self.token {
+            request = request.header("PRIVATE-TOKEN", token);
+        }
+        let response = request.send().context("Failed to fetch from GitLab")?;
+
+        let status = response.status();
+        if status == 200 {
+            let next_page = response
+                .headers()
+                .get("X-Next-Page")
+                .and_then(|value| value.to_str().ok())
+                .filter(|value| !value.is_empty())
+                .map(str::to_owned);
+            let body = response.json().context("Failed to parse GitLab response")?;
+            Ok((body, next_page))
+        } else {
+            let body = response.text().unwrap_or_default();
+            Err(anyhow::anyhow!("{}: {}", status.as_u16(), body.trim()))
+        }
+    }
+}
+
+pub fn fetch_crates_from_gitlab(
+    url: &GitLabUrl,
+    crates_root: &str,
+    git_ref: &str,
+    gitlab: &Gitlab,
+) -> Result<Vec<String>> {
+    let mut crates = Vec::new();
+    let mut page = Some("1".to_owned());
+    while let Some(current) = page {
+        let (entries, next_page): (Vec<GitlabTreeEntry>, _) = gitlab.get(&format!(
+            "{}/repository/tree?path={}&ref={}&per_page=100&page={current}",
+            url.api_url(),
+            encode_query(crates_root),
+            encode_query(git_ref)
+        ))?;
+        crates.extend(
+            entries
+                .into_iter()
+                .filter(|entry| entry.entry_type == "tree")
+                .map(|entry| entry.name),
+        );
+        page = next_page;
+    }
+
+    crates.sort();
+    Ok(crates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_level_namespace() {
+        let url = parse_gitlab_url("https://gitlab.com/group/subgroup/bevy").unwrap();
+        assert_eq!(url.namespace, "group/subgroup");
+        assert_eq!(url.project, "bevy");
+        assert_eq!(
+            url.api_url(),
+            "https://gitlab.com/api/v4/projects/group%2Fsubgroup%2Fbevy"
+        );
+    }
+
+    #[test]
+    fn three_level_namespace() {
+        let url = parse_gitlab_url("https://gitlab.com/group/a/b/bevy.git").unwrap();
+        assert_eq!(url.namespace, "group/a/b");
+        assert_eq!(url.project, "bevy");
+        assert_eq!(
+            url.api_url(),
+            "https://gitlab.com/api/v4/projects/group%2Fa%2Fb%2Fbevy"
+        );
+    }
+
+    #[test]
+    fn four_level_namespace() {
+        let url =
+            parse_gitlab_url("https://gitlab.example.com/group/a/b/c/bevy/-/tree/main").unwrap();
+        assert_eq!(url.base, "https://gitlab.example.com");
+        assert_eq!(url.namespace, "group/a/b/c");
+        assert_eq!(url.project, "bevy");
+        assert_eq!(
+            url.api_url(),
+            "https://gitlab.example.com/api/v4/projects/group%2Fa%2Fb%2Fc%2Fbevy"
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::BTreeMap;
+
+use crate::CrateMetadata;
+
+// Crate name -> the crates of the same set it depends on.
+pub type Graph = BTreeMap<String, Vec<String>>;
+
+pub fn build_dep_graph(crates: &[CrateMetadata]) -> Graph {
+    crates
+        .iter()
+        .map(|metadata| {
+            let dependencies = metadata
+                .dependencies
+                .iter()
+                .filter(|dependency| crates.iter().any(|other| &other.name == *dependency))
+                .cloned()
+                .collect();
+            (metadata.name.clone(), dependencies)
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Visit {
+    InProgress,
+    Done,
+}
+
+fn visit(
+    graph: &Graph,
+    node: &str,
+    path: &mut Vec<String>,
+    visits: &mut BTreeMap<String, Visit>,
+    cycles: &mut Vec<Vec<String>>,
+) {
+    visits.insert(node.to_owned(), Visit::InProgress);
+    path.push(node.to_owned());
+
+    for next in graph.get(node).into_iter().flatten() {
+        match visits.get(next) {
+            // A back edge, the cycle is the path from `next` onwards.
+            Some(Visit::InProgress) => {
+                let start = path.iter().position(|n| n == next).unwrap_or_default();
+                let mut cycle = path[start..].to_vec();
+                cycle.push(next.clone());
+                cycles.push(cycle);
+            }
+            Some(Visit::Done) => {}
+            None => visit(graph, next, path, visits, cycles),
+        }
+    }
+
+    path.pop();
+    visits.insert(node.to_owned(), Visit::Done);
+}
+
+// Each cycle starts and ends with the same crate, e.g. `[a, b, a]`.
+pub fn find_cycles(graph: &Graph) -> Vec<Vec<String>> {
+    let mut visits = BTreeMap::new();
+    let mut cycles = Vec::new();
+    for node in graph.keys() {
+        if !visits.contains_key(node) {
+            visit(graph, node, &mut Vec::new(), &mut visits, &mut cycles);
+        }
+    }
+    cycles
+}
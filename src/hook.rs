@@ -0,0 +1,92 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::Subcommand;
+
+use crate::format::Shell;
+use crate::{git_output, lock};
+
+const MARKER: &str = "# Installed by bevy-patch hook install";
+
+#[derive(Subcommand)]
+pub enum HookCommand {
+    /// Add a pre-commit hook failing when the patch entries drifted from `--frozen` output.
+    /// Without a command line, the one recorded in bevy-patch.lock is used
+    Install {
+        /// Replace a pre-commit hook bevy-patch didn't install
+        #[arg(long)]
+        force: bool,
+        /// The generating command, e.g. `git --branch main`
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+}
+
+fn locked_args(cargo_toml: &Path) -> Result<Vec<String>> {
+    let path = lock::lock_path(cargo_toml);
+    let lock = lock::read(&path)?.ok_or_else(|| {
+        anyhow::anyhow!(
+            "{} doesn't exist, create it with --lock or pass the command line",
+            path.display()
+        )
+    })?;
+    let mut args = lock
+        .request()
+        .to_args()
+        .with_context(|| format!("Can't recover the command line from {}", path.display()))?;
+    args.push("--locked".to_owned());
+    Ok(args)
+}
+
+pub fn run(command: &HookCommand, cargo_toml: &Path) -> Result<()> {
+    let HookCommand::Install { force, args } = command;
+    let args = if args.is_empty() {
+        locked_args(cargo_toml)?
+    } else {
+        args.clone()
+    };
+
+    let dir = match cargo_toml.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let dir = dir.to_string_lossy();
+    // Follows core.hooksPath and worktrees.
+    let hooks = git_output(&dir, &["rev-parse", "--git-path", "hooks"])?;
+    let hooks = Path::new(dir.as_ref()).join(hooks.trim());
+    let path = hooks.join("pre-commit");
+    if let Ok(existing) = std::fs::read_to_string(&path)
+        && !existing.contains(MARKER)
+        && !force
+    {
+        anyhow::bail!(
+            "{} already exists, pass --force to replace it",
+            path.display()
+        );
+    }
+
+    let manifest = std::fs::canonicalize(cargo_toml).unwrap_or_else(|_| cargo_toml.to_owned());
+    let command: Vec<String> = ["bevy-patch", "--frozen", "--cargo-toml"]
+        .into_iter()
+        .map(str::to_owned)
+        .chain([manifest.to_string_lossy().into_owned()])
+        .chain(args)
+        .map(|arg| Shell::Sh.quote(&arg))
+        .collect();
+    let script = format!(
+        "#!/bin/sh\n{MARKER}\nexec {} > /dev/null\n",
+        command.join(" ")
+    );
+
+    std::fs::create_dir_all(&hooks)
+        .with_context(|| format!("Failed to create {}", hooks.display()))?;
+    crate::atomic::write(&path, script)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("Failed to make {} executable", path.display()))?;
+    }
+    eprintln!("note: installed {}", path.display());
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+
+use crate::cargo_config;
+
+static CA_CERTIFICATES: OnceLock<Vec<reqwest::Certificate>> = OnceLock::new();
+static PROXY: OnceLock<reqwest::Proxy> = OnceLock::new();
+
+// The variables reqwest picks proxies up from by itself.
+const PROXY_VARS: [&str; 6] = [
+    "HTTPS_PROXY",
+    "https_proxy",
+    "HTTP_PROXY",
+    "http_proxy",
+    "ALL_PROXY",
+    "all_proxy",
+];
+
+// Where an HTTP setting came from, for `--show-http-config`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Flag(&'static str),
+    Env(String),
+    CargoConfig(PathBuf),
+    GitConfig,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Flag(flag) => write!(f, "{flag}"),
+            Origin::Env(name) => write!(f, "${name}"),
+            Origin::CargoConfig(path) => write!(f, "{}", path.display()),
+            Origin::GitConfig => write!(f, "git config"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setting<T> {
+    pub value: T,
+    pub origin: Origin,
+}
+
+// The `[http]` settings of the merged cargo config.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct CargoHttp {
+    proxy: Option<Setting<String>>,
+    cainfo: Option<Setting<PathBuf>>,
+    check_revoke: Option<Setting<bool>>,
+}
+
+// `configs` in cargo's precedence order, the first file setting a key wins.
+fn cargo_http(configs: &[(PathBuf, toml::Table)]) -> CargoHttp {
+    let mut http = CargoHttp::default();
+    for (path, config) in configs {
+        let Some(table) = config.get("http").and_then(|http| http.as_table()) else {
+            continue;
+        };
+        let origin = || Origin::CargoConfig(path.clone());
+        if http.proxy.is_none()
+            && let Some(proxy) = table.get("proxy").and_then(|proxy| proxy.as_str())
+        {
+            http.proxy = Some(Setting {
+                value: proxy.to_owned(),
+                origin: origin(),
+            });
+        }
+        if http.cainfo.is_none()
+            && let Some(cainfo) = table.get("cainfo").and_then(|cainfo| cainfo.as_str())
+        {
+            // Relative to the directory holding `.cargo`.
+            let base = path
+                .parent()
+                .and_then(Path::parent)
+                .unwrap_or(Path::new("."));
+            http.cainfo = Some(Setting {
+                value: base.join(cainfo),
+                origin: origin(),
+            });
+        }
+        if http.check_revoke.is_none()
+            && let Some(check_revoke) = table.get("check-revoke").and_then(|value| value.as_bool())
+        {
+            http.check_revoke = Some(Setting {
+                value: check_revoke,
+                origin: origin(),
+            });
+        }
+    }
+    http
+}
+
+fn env_setting(name: &str) -> Option<Setting<String>> {
+    std::env::var(name)
+        .ok()
+        .filter(|value| !value.is_empty())
+        .map(|value| Setting {
+            value,
+            origin: Origin::Env(name.to_owned()),
+        })
+}
+
+// Cargo's `CARGO_HTTP_*` variables, then its config files.
+fn read_cargo_http() -> CargoHttp {
+    let dir = std::env::current_dir().unwrap_or_default();
+    let configs: Vec<(PathBuf, toml::Table)> = cargo_config::config_files(&dir)
+        .into_iter()
+        .filter_map(|path| {
+            let config = toml::from_str(&std::fs::read_to_string(&path).ok()?).ok()?;
+            Some((path, config))
+        })
+        .collect();
+    let mut http = cargo_http(&configs);
+    if let Some(proxy) = env_setting("CARGO_HTTP_PROXY") {
+        http.proxy = Some(proxy);
+    }
+    if let Some(Setting { value, origin }) = env_setting("CARGO_HTTP_CAINFO") {
+        http.cainfo = Some(Setting {
+            value: PathBuf::from(value),
+            origin,
+        });
+    }
+    if let Some(Setting { value, origin }) = env_setting("CARGO_HTTP_CHECK_REVOKE") {
+        http.check_revoke = Some(Setting {
+            value: value == "true",
+            origin,
+        });
+    }
+    http
+}
+
+fn git_config(key: &str) -> Option<Setting<String>> {
+    let output = std::process::Command::new("git")
+        .args(["config", "--get", key])
+        .output()
+        .ok()?;
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    (output.status.success() && !value.is_empty()).then_some(Setting {
+        value,
+        origin: Origin::GitConfig,
+    })
+}
+
+// What the HTTP client ends up using, and where each setting came from.
+#[derive(Debug, Default)]
+pub struct HttpConfig {
+    // `None` with a proxy variable set, reqwest reads those itself.
+    pub proxy: Option<Setting<String>>,
+    pub proxy_var: Option<String>,
+    pub cainfo: Option<Setting<PathBuf>>,
+    pub check_revoke: Option<Setting<bool>>,
+}
+
+impl HttpConfig {
+    // Each setting from the first of: the flag or its variable, the standard variables, cargo's
+    // config, git's config.
+    pub fn resolve(proxy: Option<Setting<String>>, ca_bundle: Option<Setting<PathBuf>>) -> Self {
+        let cargo = read_cargo_http();
+        let proxy_var = PROXY_VARS
+            .iter()
+            .find(|name| std::env::var_os(name).is_some_and(|value| !value.is_empty()))
+            .map(|name| (*name).to_owned());
+        let proxy = match proxy {
+            Some(proxy) => Some(proxy),
+            None if proxy_var.is_some() => None,
+            None => cargo.proxy.or_else(|| git_config("http.proxy")),
+        };
+        let cainfo = ca_bundle.or(cargo.cainfo).or_else(|| {
+            git_config("http.sslCAInfo").map(|Setting { value, origin }| Setting {
+                value: PathBuf::from(value),
+                origin,
+            })
+        });
+        HttpConfig {
+            proxy,
+            proxy_var,
+            cainfo,
+            check_revoke: cargo.check_revoke,
+        }
+    }
+
+    pub fn show(&self) {
+        match (&self.proxy, &self.proxy_var) {
+            (Some(proxy), _) => eprintln!("proxy: {} (from {})", proxy.value, proxy.origin),
+            (None, Some(name)) => eprintln!("proxy: from ${name}"),
+            (None, None) => eprintln!("proxy: none"),
+        }
+        match &self.cainfo {
+            Some(cainfo) => eprintln!(
+                "cainfo: {} (from {})",
+                cainfo.value.display(),
+                cainfo.origin
+            ),
+            None => eprintln!("cainfo: system roots only"),
+        }
+        match &self.check_revoke {
+            Some(check_revoke) => eprintln!(
+                "check-revoke: {} (from {}), not supported, certificates aren't checked for revocation",
+                check_revoke.value, check_revoke.origin
+            ),
+            None => eprintln!("check-revoke: not set"),
+        }
+    }
+}
+
+// Loaded up front, a bad bundle or proxy would otherwise surface on the first request.
+pub fn configure(config: &HttpConfig, verbose: bool) -> Result<()> {
+    if let Some(proxy) = &config.proxy {
+        // Cargo and git take a bare `host:port` as an HTTP proxy.
+        let url = if proxy.value.contains("://") {
+            proxy.value.clone()
+        } else {
+            format!("http://{}", proxy.value)
+        };
+        let proxy = reqwest::Proxy::all(&url)
+            .with_context(|| format!("Invalid proxy {url} from {}", proxy.origin))?
+            .no_proxy(reqwest::NoProxy::from_env());
+        let _ = PROXY.set(proxy);
+    }
+
+    let Some(cainfo) = &config.cainfo else {
+        return Ok(());
+    };
+    let path = &cainfo.value;
+    let pem = std::fs::read(path).with_context(|| {
+        format!(
+            "Failed to read the CA bundle {} from {}",
+            path.display(),
+            cainfo.origin
+        )
+    })?;
+    let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+        .with_context(|| format!("Failed to parse the CA bundle {}", path.display()))?;
+    if certificates.is_empty() {
+        anyhow::bail!("The CA bundle {} has no PEM certificates", path.display());
+    }
+    if verbose {
+        eprintln!(
+            "debug: using {} certificates from the CA bundle {}",
+            certificates.len(),
+            path.display()
+        );
+    }
+    let _ = CA_CERTIFICATES.set(certificates);
+    Ok(())
+}
+
+// Whether requests go through a proxy, configured or from the standard variables.
+pub fn uses_proxy() -> bool {
+    PROXY.get().is_some()
+        || PROXY_VARS
+            .iter()
+            .any(|name| std::env::var_os(name).is_some_and(|value| !value.is_empty()))
+}
+
+// A client trusting the system roots and the configured CA bundle, through the configured proxy.
+pub fn client() -> reqwest::blocking::Client {
+    let mut builder = reqwest::blocking::Client::builder();
+    for certificate in CA_CERTIFICATES.get().into_iter().flatten() {
+        builder = builder.add_root_certificate(certificate.clone());
+    }
+    if let Some(proxy) = PROXY.get() {
+        builder = builder.proxy(proxy.clone());
+    }
+    // Same failure mode as `Client::new()`, which panics when TLS can't be initialized.
+    builder.build().expect("Failed to build the HTTP client")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearer_cargo_configs_win() {
+        let project: toml::Table =
+            toml::from_str("[http]\nproxy = \"proxy.corp:3128\"\ncainfo = \"certs/ca.pem\"")
+                .unwrap();
+        let home: toml::Table =
+            toml::from_str("[http]\nproxy = \"ignored:1\"\ncheck-revoke = false").unwrap();
+        let project_path = PathBuf::from("/work/game/.cargo/config.toml");
+        let home_path = PathBuf::from("/home/me/.cargo/config.toml");
+
+        let http = cargo_http(&[(project_path.clone(), project), (home_path.clone(), home)]);
+        assert_eq!(
+            http.proxy,
+            Some(Setting {
+                value: "proxy.corp:3128".to_owned(),
+                origin: Origin::CargoConfig(project_path.clone()),
+            })
+        );
+        assert_eq!(
+            http.cainfo.map(|cainfo| cainfo.value),
+            Some(PathBuf::from("/work/game/certs/ca.pem"))
+        );
+        assert_eq!(
+            http.check_revoke,
+            Some(Setting {
+                value: false,
+                origin: Origin::CargoConfig(home_path),
+            })
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::path::Path;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::PatchGroup;
+use crate::explain::{cargo_metadata, str_field};
+use crate::format::Format;
+
+// How many of the patched crates with the most dependents the text report lists.
+const OFFENDERS: usize = 5;
+
+#[derive(Debug, Serialize)]
+struct Offender {
+    name: String,
+    // Packages rebuilding because of this crate alone.
+    dependents: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Impact {
+    // The patched crates found in the resolved graph.
+    patched: Vec<String>,
+    // `<name> <version>` of every package depending on a patched crate through normal or
+    // build dependencies, the patched crates themselves left out.
+    rebuilt: Vec<String>,
+    // Workspace members reaching a patched crate only through their dev-dependencies, so only
+    // their tests, examples and benches rebuild.
+    dev_only: Vec<String>,
+    workspace_members: Vec<String>,
+    offenders: Vec<Offender>,
+}
+
+// Dependency -> the packages depending on it, split by whether a dev edge is the only one.
+#[derive(Default)]
+struct Dependents<'a> {
+    normal: HashMap<&'a str, Vec<&'a str>>,
+    dev: HashMap<&'a str, Vec<&'a str>>,
+}
+
+fn dependents(metadata: &serde_json::Value) -> Dependents<'_> {
+    let mut dependents = Dependents::default();
+    let nodes = metadata["resolve"]["nodes"]
+        .as_array()
+        .into_iter()
+        .flatten();
+    for node in nodes {
+        let Some(id) = str_field(node, "id") else {
+            continue;
+        };
+        for dep in node["deps"].as_array().into_iter().flatten() {
+            let Some(pkg) = str_field(dep, "pkg") else {
+                continue;
+            };
+            // A `null` kind is a normal dependency.
+            let dev_only = dep["dep_kinds"].as_array().is_some_and(|kinds| {
+                !kinds.is_empty()
+                    && kinds
+                        .iter()
+                        .all(|kind| str_field(kind, "kind") == Some("dev"))
+            });
+            let edges = if dev_only {
+                &mut dependents.dev
+            } else {
+                &mut dependents.normal
+            };
+            edges.entry(pkg).or_default().push(id);
+        }
+    }
+    dependents
+}
+
+// Everything reaching `roots` through normal and build edges, `roots` excluded.
+fn transitive<'a>(
+    roots: &[&'a str],
+    dependents: &HashMap<&'a str, Vec<&'a str>>,
+) -> BTreeSet<&'a str> {
+    let mut seen: BTreeSet<&str> = roots.iter().copied().collect();
+    let mut queue: VecDeque<&str> = roots.iter().copied().collect();
+    while let Some(id) = queue.pop_front() {
+        for &dependent in dependents.get(id).into_iter().flatten() {
+            if seen.insert(dependent) {
+                queue.push_back(dependent);
+            }
+        }
+    }
+    for root in roots {
+        seen.remove(root);
+    }
+    seen
+}
+
+fn compute(metadata: &serde_json::Value, patched: &BTreeSet<&str>) -> Impact {
+    // Package id -> `<name> <version>`, the same name can be resolved at several versions.
+    let mut names: BTreeMap<&str, (&str, String)> = BTreeMap::new();
+    for package in metadata["packages"].as_array().into_iter().flatten() {
+        if let (Some(id), Some(name)) = (str_field(package, "id"), str_field(package, "name")) {
+            let version = str_field(package, "version").unwrap_or("?");
+            names.insert(id, (name, format!("{name} {version}")));
+        }
+    }
+    let members: BTreeSet<&str> = metadata["workspace_members"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|member| member.as_str())
+        .collect();
+    let label = |id: &str| {
+        names
+            .get(id)
+            .map_or_else(|| id.to_owned(), |(_, label)| label.clone())
+    };
+
+    let roots: Vec<&str> = names
+        .iter()
+        .filter(|(_, (name, _))| patched.contains(name))
+        .map(|(&id, _)| id)
+        .collect();
+    let dependents = dependents(metadata);
+    let rebuilt = transitive(&roots, &dependents.normal);
+
+    // Cargo only resolves the dev-dependencies of workspace members.
+    let affected: BTreeSet<&str> = rebuilt.iter().chain(&roots).copied().collect();
+    let dev_only: BTreeSet<&str> = affected
+        .iter()
+        .flat_map(|id| dependents.dev.get(id).into_iter().flatten())
+        .copied()
+        .filter(|id| members.contains(id) && !affected.contains(id))
+        .collect();
+
+    let mut offenders: Vec<Offender> = roots
+        .iter()
+        .map(|&root| Offender {
+            name: label(root),
+            dependents: transitive(&[root], &dependents.normal).len(),
+        })
+        .collect();
+    offenders.sort_by(|a, b| b.dependents.cmp(&a.dependents).then(a.name.cmp(&b.name)));
+
+    let mut rebuilt_labels: Vec<String> = rebuilt.iter().map(|id| label(id)).collect();
+    rebuilt_labels.sort();
+    Impact {
+        patched: roots.iter().map(|id| label(id)).collect(),
+        workspace_members: rebuilt
+            .iter()
+            .filter(|id| members.contains(*id))
+            .map(|id| label(id))
+            .collect(),
+        rebuilt: rebuilt_labels,
+        dev_only: dev_only.iter().map(|id| label(id)).collect(),
+        offenders,
+    }
+}
+
+// `--impact`, what patching `groups` makes cargo rebuild in the project's current graph.
+pub fn report(groups: &[PatchGroup], cargo_toml: &Path, format: Format) -> Result<()> {
+    let metadata = cargo_metadata(cargo_toml)?;
+    let patched: BTreeSet<&str> = groups
+        .iter()
+        .flat_map(|group| &group.entries)
+        .map(|entry| entry.name.as_str())
+        .collect();
+    let impact = compute(&metadata, &patched);
+
+    if format == Format::Json {
+        println!("{}", serde_json::to_string_pretty(&impact)?);
+        return Ok(());
+    }
+
+    if impact.patched.is_empty() {
+        println!(
+            "None of the patched crates are in the dependency graph of {}",
+            cargo_toml.display()
+        );
+        return Ok(());
+    }
+    println!(
+        "Patching {} crates rebuilds {} packages depending on them, {} of them workspace members",
+        impact.patched.len(),
+        impact.rebuilt.len(),
+        impact.workspace_members.len()
+    );
+    if !impact.dev_only.is_empty() {
+        println!(
+            "Only the tests, examples and benches of {} rebuild through dev-dependencies",
+            impact.dev_only.join(", ")
+        );
+    }
+    let width = impact
+        .offenders
+        .iter()
+        .take(OFFENDERS)
+        .map(|offender| offender.name.len())
+        .max()
+        .unwrap_or(0);
+    println!("Biggest offenders:");
+    for offender in impact.offenders.iter().take(OFFENDERS) {
+        println!(
+            "  {:width$}  {} dependents",
+            offender.name, offender.dependents
+        );
+    }
+    println!(
+        "Narrow the patch with --crates to leave crates out, --format json lists every package"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dev_dependencies_are_reported_apart() {
+        // game -> bevy -> bevy_render -> bevy_ecs, tool -> bevy_ecs, bench -(dev)-> bevy_ecs.
+        let metadata = serde_json::json!({
+            "packages": [
+                { "id": "game", "name": "game", "version": "0.1.0" },
+                { "id": "tool", "name": "tool", "version": "0.1.0" },
+                { "id": "bench", "name": "bench", "version": "0.1.0" },
+                { "id": "bevy", "name": "bevy", "version": "0.15.0" },
+                { "id": "bevy_render", "name": "bevy_render", "version": "0.15.0" },
+                { "id": "bevy_ecs", "name": "bevy_ecs", "version": "0.15.0" },
+            ],
+            "workspace_members": ["game", "tool", "bench"],
+            "resolve": { "nodes": [
+                { "id": "game", "deps": [{ "pkg": "bevy", "dep_kinds": [{ "kind": null }] }] },
+                { "id": "tool", "deps": [{ "pkg": "bevy_ecs", "dep_kinds": [{ "kind": "build" }] }] },
+                { "id": "bench", "deps": [{ "pkg": "bevy_ecs", "dep_kinds": [{ "kind": "dev" }] }] },
+                { "id": "bevy", "deps": [{ "pkg": "bevy_render", "dep_kinds": [{ "kind": null }] }] },
+                { "id": "bevy_render", "deps": [{ "pkg": "bevy_ecs", "dep_kinds": [{ "kind": null }] }] },
+                { "id": "bevy_ecs", "deps": [] },
+            ] },
+        });
+
+        let impact = compute(&metadata, &BTreeSet::from(["bevy_ecs", "bevy_render"]));
+        assert_eq!(impact.rebuilt, ["bevy 0.15.0", "game 0.1.0", "tool 0.1.0"]);
+        assert_eq!(impact.workspace_members, ["game 0.1.0", "tool 0.1.0"]);
+        assert_eq!(impact.dev_only, ["bench 0.1.0"]);
+        assert_eq!(impact.offenders[0].name, "bevy_ecs 0.15.0");
+        assert_eq!(impact.offenders[0].dependents, 4);
+        assert_eq!(impact.offenders[1].dependents, 2);
+    }
+}
@@ -0,0 +1,185 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+use crate::{prompt, toml_string};
+
+const CONFIG_FILE: &str = "bevy-patch.toml";
+
+struct Answers {
+    repo: String,
+    ref_kind: String,
+    git_ref: String,
+    crates: Vec<String>,
+    skip_root: bool,
+    // Where the generated [patch] tables go, `Cargo.toml` or `.cargo/config.toml`.
+    destination: String,
+    format: String,
+}
+
+const DESTINATIONS: [&str; 2] = ["Cargo.toml", ".cargo/config.toml"];
+
+impl Default for Answers {
+    fn default() -> Self {
+        Self {
+            repo: "https://github.com/bevyengine/bevy".to_owned(),
+            ref_kind: "branch".to_owned(),
+            git_ref: "main".to_owned(),
+            crates: Vec::new(),
+            skip_root: false,
+            destination: DESTINATIONS[0].to_owned(),
+            format: "toml".to_owned(),
+        }
+    }
+}
+
+fn ask() -> Result<Answers> {
+    let defaults = Answers::default();
+    let repo = prompt::input("Repository or fork to patch bevy with?", &defaults.repo)?;
+    let ref_kind = loop {
+        let kind = prompt::input("Follow a branch, tag or rev?", &defaults.ref_kind)?;
+        if ["branch", "tag", "rev"].contains(&kind.as_str()) {
+            break kind;
+        }
+        eprintln!("Enter branch, tag or rev");
+    };
+    let git_ref = prompt::input(&format!("Which {ref_kind}?"), &defaults.git_ref)?;
+    let crates = prompt::input(
+        "Crates to patch, separated by commas, or all of them?",
+        "all",
+    )?;
+    let skip_root = prompt::input("Leave out the umbrella `bevy` crate? (y/n)", "n")?;
+    let destination = loop {
+        let destination = prompt::input(
+            "Write the patch to Cargo.toml or .cargo/config.toml?",
+            &defaults.destination,
+        )?;
+        if DESTINATIONS.contains(&destination.as_str()) {
+            break destination;
+        }
+        eprintln!("Enter Cargo.toml or .cargo/config.toml");
+    };
+    let format = prompt::input(
+        "Output: toml for [patch] tables, cargo-args for `cargo --config`, or another --format?",
+        &defaults.format,
+    )?;
+
+    Ok(Answers {
+        repo,
+        ref_kind,
+        git_ref,
+        crates: if crates == "all" {
+            Vec::new()
+        } else {
+            crates
+                .split(',')
+                .map(|name| name.trim().to_owned())
+                .filter(|name| !name.is_empty())
+                .collect()
+        },
+        skip_root: matches!(skip_root.to_ascii_lowercase().as_str(), "y" | "yes"),
+        destination,
+        format,
+    })
+}
+
+fn render(answers: &Answers) -> String {
+    let crates = if answers.crates.is_empty() {
+        "# crates = [\"bevy_ecs\", \"bevy_render\"]".to_owned()
+    } else {
+        let names: Vec<String> = answers.crates.iter().map(|c| toml_string(c)).collect();
+        format!("crates = [{}]", names.join(", "))
+    };
+
+    format!(
+        r#"# Run with `bevy-patch import {CONFIG_FILE}` and put the output in {destination},
+# {why}. `bevy-patch export` writes the same settings as JSON.
+
+# The first source is where bevy comes from, `type` is "git", "path" or "github".
+# Single crates can come from elsewhere with more sources like
+# {{ type = "override", crate = "bevy_render", repo = "alice/bevy", ref = "fix" }}.
+[[sources]]
+type = "git"
+repo = {repo}
+{ref_kind} = {git_ref}
+
+[filter]
+# Only patch these crates instead of every crate in `crates_root`.
+{crates}
+crates_root = "crates"
+# Leave out the umbrella `bevy` entry.
+skip_root = {skip_root}
+
+[options]
+# toml, nix, json, markdown, source-replacement, cargo-args or cargo-args-lines
+format = {format}
+# Include each crate's `version` in the entries.
+add_version = false
+"#,
+        destination = answers.destination,
+        why = if answers.destination == DESTINATIONS[0] {
+            "next to the rest of the project's dependencies"
+        } else {
+            "cargo reads [patch] from there too and Cargo.toml stays as it is"
+        },
+        repo = toml_string(&answers.repo),
+        ref_kind = answers.ref_kind,
+        git_ref = toml_string(&answers.git_ref),
+        skip_root = answers.skip_root,
+        format = toml_string(&answers.format),
+    )
+}
+
+pub fn run(cargo_toml: &Path, defaults: bool, force: bool) -> Result<()> {
+    let dir = match cargo_toml.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let path = dir.join(CONFIG_FILE);
+    if path.exists() && !force {
+        anyhow::bail!(
+            "{} already exists, pass --force to overwrite it",
+            path.display()
+        );
+    }
+
+    let answers = if defaults { Answers::default() } else { ask()? };
+    let content = render(&answers);
+    crate::share::check_toml(&content).context("The answers don't make a valid config")?;
+    crate::atomic::write(&path, content)?;
+    eprintln!(
+        "note: wrote {}, run it with `bevy-patch import {}`",
+        path.display(),
+        path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_round_trip() {
+        crate::share::check_toml(&render(&Answers::default())).unwrap();
+    }
+
+    #[test]
+    fn answers_round_trip() {
+        let answers = Answers {
+            repo: "alice/bevy".to_owned(),
+            ref_kind: "tag".to_owned(),
+            git_ref: "v0.15.0\"".to_owned(),
+            crates: vec!["bevy_ecs".to_owned(), "bevy_render".to_owned()],
+            skip_root: true,
+            destination: DESTINATIONS[1].to_owned(),
+            format: "cargo-args".to_owned(),
+        };
+        let content = render(&answers);
+        crate::share::check_toml(&content).unwrap();
+        assert!(
+            content.contains("put the output in .cargo/config.toml,"),
+            "{content}"
+        );
+    }
+}
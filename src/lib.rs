@@ -0,0 +1,497 @@
+//! The pieces of bevy-patch other tools can build on, e.g. to list the crates of a repository
+//! hosted somewhere the `bevy-patch` binary doesn't know about.
+
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+pub mod apply;
+pub mod atomic;
+
+/// Whether `name` is one of bevy's crates, which bevy-patch takes over in a manifest.
+pub fn is_bevy_crate(name: &str) -> bool {
+    name == "bevy" || name.starts_with("bevy_")
+}
+
+/// Lists the crate directories of a bevy checkout, the names under `crates/` for bevy itself.
+pub trait CrateFetcher {
+    fn fetch(&self) -> Result<Vec<String>>;
+}
+
+/// A git ref as cargo takes it in a git dependency.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub enum GitReference {
+    Branch(String),
+    Tag(String),
+    Rev(String),
+}
+
+impl GitReference {
+    pub fn as_str(&self) -> &str {
+        match self {
+            GitReference::Branch(name) | GitReference::Tag(name) | GitReference::Rev(name) => name,
+        }
+    }
+
+    /// The key of the ref in a git dependency: `branch`, `tag` or `rev`.
+    pub fn key(&self) -> &'static str {
+        match self {
+            GitReference::Branch(_) => "branch",
+            GitReference::Tag(_) => "tag",
+            GitReference::Rev(_) => "rev",
+        }
+    }
+}
+
+/// Where the patched crates come from.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub enum PatchSource {
+    Path {
+        path: String,
+    },
+    Git {
+        repo: String,
+        reference: GitReference,
+    },
+}
+
+impl PatchSource {
+    pub fn label(&self) -> String {
+        match self {
+            PatchSource::Path { path } => path.clone(),
+            PatchSource::Git { repo, reference } => format!("{repo} @ {}", reference.as_str()),
+        }
+    }
+
+    /// Keys and TOML values pointing cargo at the crate in `subpath`.
+    pub fn fields(&self, subpath: &str) -> Vec<(&'static str, String)> {
+        match self {
+            PatchSource::Path { path } if subpath.is_empty() => {
+                vec![("path", toml_string(path))]
+            }
+            PatchSource::Path { path } => {
+                vec![("path", toml_string(&format!("{path}/{subpath}")))]
+            }
+            PatchSource::Git { repo, reference } => vec![
+                ("git", toml_string(repo)),
+                (reference.key(), toml_string(reference.as_str())),
+            ],
+        }
+    }
+}
+
+/// A basic TOML string, refs can contain quotes and backslashes.
+pub fn toml_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A TOML key, bare when it can be, e.g. `bevy_ecs`, quoted otherwise.
+pub fn toml_key(key: &str) -> String {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        key.to_owned()
+    } else {
+        toml_string(key)
+    }
+}
+
+/// Where the crate `name` lives in the repository, relative to its root.
+pub fn crate_subpath(crates_root: &str, name: &str) -> String {
+    if crates_root.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{crates_root}/{name}")
+    }
+}
+
+/// One `[patch.crates-io]` entry.
+#[derive(Debug, Clone)]
+pub struct PatchEntry {
+    pub name: String,
+    /// Relative to the repository root, empty for the umbrella `bevy` crate.
+    pub subpath: String,
+    pub version: Option<String>,
+}
+
+impl PatchEntry {
+    pub fn new(name: impl Into<String>, subpath: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            subpath: subpath.into(),
+            version: None,
+        }
+    }
+
+    /// The umbrella `bevy` crate at the repository root.
+    pub fn root() -> Self {
+        Self::new("bevy", "")
+    }
+
+    /// A crate found in its own directory under `crates_root`.
+    pub fn discovered(crates_root: &str, name: &str) -> Self {
+        Self::new(name, crate_subpath(crates_root, name))
+    }
+
+    pub fn fields(&self, source: &PatchSource) -> Vec<(&'static str, String)> {
+        let mut fields = source.fields(&self.subpath);
+        if let Some(version) = &self.version {
+            fields.push(("version", toml_string(version)));
+        }
+        fields
+    }
+
+    /// The entry as an inline table, `bevy_ecs = { git = "...", branch = "main" }`.
+    pub fn line(&self, source: &PatchSource) -> String {
+        let fields: Vec<String> = self
+            .fields(source)
+            .into_iter()
+            .map(|(key, value)| format!("{key} = {value}"))
+            .collect();
+        format!("{} = {{ {} }}", toml_key(&self.name), fields.join(", "))
+    }
+
+    /// The entry as an object of `--format json`'s `patches`.
+    pub fn json(&self, source: &PatchSource) -> serde_json::Map<String, serde_json::Value> {
+        let mut patch = serde_json::Map::new();
+        patch.insert("name".to_owned(), self.name.clone().into());
+        match source {
+            PatchSource::Path { path } if self.subpath.is_empty() => {
+                patch.insert("path".to_owned(), path.clone().into());
+            }
+            PatchSource::Path { path } => {
+                patch.insert("path".to_owned(), format!("{path}/{}", self.subpath).into());
+            }
+            PatchSource::Git { repo, reference } => {
+                patch.insert("git".to_owned(), repo.clone().into());
+                patch.insert(reference.key().to_owned(), reference.as_str().into());
+            }
+        }
+        if let Some(version) = &self.version {
+            patch.insert("version".to_owned(), version.clone().into());
+        }
+        patch
+    }
+}
+
+/// Entries sharing one source.
+#[derive(Debug, Clone)]
+pub struct PatchGroup {
+    pub source: PatchSource,
+    pub entries: Vec<PatchEntry>,
+}
+
+// The first lines of every `[patch.crates-io]` section bevy-patch writes.
+const HEADER: [&str; 2] = ["[patch.crates-io]", "# Bevy Patch"];
+
+/// A `[patch.crates-io]` section pointing `bevy` and every fetched crate at `source_spec`,
+/// the fields of a git dependency such as `git = "https://example.com/bevy", branch = "main"`.
+#[deprecated(note = "use PatchBuilder, which quotes the source fields itself")]
+pub fn generate_patch_section(fetcher: &dyn CrateFetcher, source_spec: &str) -> Result<String> {
+    let mut lines: Vec<String> = HEADER.map(str::to_owned).into();
+    for name in with_root(fetcher.fetch()?) {
+        lines.push(format!("{name} = {{ {source_spec} }}"));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Builds a [`PatchSection`] without going through the command line.
+///
+/// ```no_run
+/// # fn main() -> anyhow::Result<()> {
+/// let section = bevy_patch::PatchBuilder::new()
+///     .git_source("https://github.com/bevyengine/bevy")
+///     .branch("main")
+///     .include_only(&["bevy_ecs", "bevy_reflect"])
+///     .build()?;
+/// println!("{section}");
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct PatchBuilder {
+    source: Option<PatchSource>,
+    crates_root: Option<String>,
+    fetcher: Option<Box<dyn CrateFetcher>>,
+    exclude: Vec<String>,
+    include_only: Option<Vec<String>>,
+}
+
+impl PatchBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// On `main` unless `branch`, `tag` or `rev` says otherwise, as with `bevy-patch git`.
+    pub fn git_source(mut self, repo: &str) -> Self {
+        self.source = Some(PatchSource::Git {
+            repo: repo.to_owned(),
+            reference: GitReference::Branch("main".to_owned()),
+        });
+        self
+    }
+
+    /// A local checkout, its crates are listed from disk unless a fetcher is given.
+    pub fn path_source(mut self, path: &str) -> Self {
+        self.source = Some(PatchSource::Path {
+            path: path.to_owned(),
+        });
+        self
+    }
+
+    fn reference(mut self, git_reference: GitReference) -> Self {
+        if let Some(PatchSource::Git { reference, .. }) = &mut self.source {
+            *reference = git_reference;
+        }
+        self
+    }
+
+    /// Only for git sources, the last of `branch`, `tag` and `rev` wins.
+    pub fn branch(self, branch: &str) -> Self {
+        self.reference(GitReference::Branch(branch.to_owned()))
+    }
+
+    pub fn tag(self, tag: &str) -> Self {
+        self.reference(GitReference::Tag(tag.to_owned()))
+    }
+
+    pub fn rev(self, rev: &str) -> Self {
+        self.reference(GitReference::Rev(rev.to_owned()))
+    }
+
+    /// Where crate directories live in the repository, `crates` by default.
+    pub fn crates_root(mut self, crates_root: &str) -> Self {
+        self.crates_root = Some(crates_root.trim_matches('/').to_owned());
+        self
+    }
+
+    /// Lists the crates, needed for git sources unless `include_only` names them.
+    pub fn fetcher(mut self, fetcher: impl CrateFetcher + 'static) -> Self {
+        self.fetcher = Some(Box::new(fetcher));
+        self
+    }
+
+    pub fn exclude(mut self, crates: &[&str]) -> Self {
+        self.exclude
+            .extend(crates.iter().map(|name| (*name).to_owned()));
+        self
+    }
+
+    /// A closed list, `bevy` itself is only patched when it's listed.
+    pub fn include_only(mut self, crates: &[&str]) -> Self {
+        self.include_only
+            .get_or_insert_with(Vec::new)
+            .extend(crates.iter().map(|name| (*name).to_owned()));
+        self
+    }
+
+    pub fn build(self) -> Result<PatchSection> {
+        let source = self
+            .source
+            .ok_or_else(|| anyhow::anyhow!("No source, call git_source or path_source"))?;
+        if self.include_only.is_some() && !self.exclude.is_empty() {
+            anyhow::bail!("include_only and exclude can't be combined");
+        }
+        let crates_root = self.crates_root.unwrap_or_else(|| "crates".to_owned());
+
+        let mut crates = match (self.include_only, &self.fetcher, &source) {
+            (Some(crates), _, _) => crates,
+            (None, Some(fetcher), _) => with_root(fetcher.fetch()?),
+            (None, None, PatchSource::Path { path }) => {
+                with_root(list_dirs(&Path::new(path).join(&crates_root))?)
+            }
+            (None, None, PatchSource::Git { .. }) => {
+                anyhow::bail!("A git source needs a fetcher or include_only to know its crates")
+            }
+        };
+        crates.retain(|name| !self.exclude.contains(name));
+
+        let entries = crates
+            .iter()
+            .map(|name| match name.as_str() {
+                "bevy" => PatchEntry::root(),
+                name => PatchEntry::discovered(&crates_root, name),
+            })
+            .collect();
+        Ok(PatchSection {
+            group: PatchGroup { source, entries },
+        })
+    }
+}
+
+// Discovery finds the crates under the crates root, `bevy` is the repository root.
+fn with_root(mut crates: Vec<String>) -> Vec<String> {
+    crates.retain(|name| name != "bevy");
+    crates.sort();
+    crates.insert(0, "bevy".to_owned());
+    crates
+}
+
+fn list_dirs(dir: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?
+    {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    Ok(names)
+}
+
+/// `[patch.crates-io]` entries: TOML through `Display`, JSON through `Serialize`.
+#[derive(Debug, Clone)]
+pub struct PatchSection {
+    group: PatchGroup,
+}
+
+impl PatchSection {
+    pub fn crates(&self) -> Vec<&str> {
+        self.group
+            .entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect()
+    }
+
+    pub fn group(&self) -> &PatchGroup {
+        &self.group
+    }
+
+    /// Writes the entries to the manifest at `path` as `bevy-patch apply` does: between
+    /// `# bevy-patch-begin` and `# bevy-patch-end` markers, replacing the bevy crates and the
+    /// entries of the same crates, under the same lock and with the same atomic write.
+    pub fn write_to(&self, path: &Path) -> Result<()> {
+        atomic::edit(path, |content| {
+            apply::apply_block(content, &self.to_string())
+        })?;
+        Ok(())
+    }
+}
+
+// The same lines as `bevy-patch` prints with `--no-stats`.
+impl fmt::Display for PatchSection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", HEADER.join("\n"))?;
+        for entry in &self.group.entries {
+            write!(f, "\n{}", entry.line(&self.group.source))?;
+        }
+        Ok(())
+    }
+}
+
+// The same shape as `--format json`.
+impl Serialize for PatchSection {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let patches: Vec<_> = self
+            .group
+            .entries
+            .iter()
+            .map(|entry| entry.json(&self.group.source))
+            .collect();
+        serde_json::json!({ "patches": patches }).serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(Vec<String>);
+
+    impl CrateFetcher for Fixed {
+        fn fetch(&self) -> Result<Vec<String>> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn patch_section() {
+        let fetcher = Fixed(vec!["bevy_render".to_owned(), "bevy_ecs".to_owned()]);
+        let section =
+            generate_patch_section(&fetcher, r#"git = "https://example.com/bevy", tag = "v1""#)
+                .unwrap();
+        assert_eq!(
+            section,
+            r#"[patch.crates-io]
+# Bevy Patch
+bevy = { git = "https://example.com/bevy", tag = "v1" }
+bevy_ecs = { git = "https://example.com/bevy", tag = "v1" }
+bevy_render = { git = "https://example.com/bevy", tag = "v1" }"#
+        );
+    }
+
+    #[test]
+    fn builder() {
+        let section = PatchBuilder::new()
+            .git_source("https://github.com/bevyengine/bevy")
+            .branch("main")
+            .fetcher(Fixed(vec!["bevy_ecs".to_owned(), "bevy_audio".to_owned()]))
+            .exclude(&["bevy_audio"])
+            .build()
+            .unwrap();
+        assert_eq!(
+            section.to_string(),
+            r#"[patch.crates-io]
+# Bevy Patch
+bevy = { git = "https://github.com/bevyengine/bevy", branch = "main" }
+bevy_ecs = { git = "https://github.com/bevyengine/bevy", branch = "main" }"#
+        );
+        assert_eq!(
+            serde_json::to_value(&section).unwrap()["patches"][1]["name"],
+            "bevy_ecs"
+        );
+    }
+
+    #[test]
+    fn include_only_and_exclude_conflict() {
+        let built = PatchBuilder::new()
+            .git_source("https://github.com/bevyengine/bevy")
+            .exclude(&["bevy_audio"])
+            .include_only(&["bevy_ecs"])
+            .build();
+        assert!(built.is_err());
+    }
+
+    #[test]
+    fn write_to_keeps_the_manifest() {
+        let path =
+            std::env::temp_dir().join(format!("bevy-patch-builder-{}.toml", std::process::id()));
+        std::fs::write(&path, "[package]\nname = \"game\" # mine\n").unwrap();
+        PatchBuilder::new()
+            .path_source("../bevy")
+            .include_only(&["bevy_ecs"])
+            .build()
+            .unwrap()
+            .write_to(&path)
+            .unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(written.starts_with("[package]\nname = \"game\" # mine\n"));
+        assert!(written.contains(apply::BEGIN) && written.contains(apply::END));
+        let manifest: toml::Table = toml::from_str(&written).unwrap();
+        assert_eq!(
+            manifest["patch"]["crates-io"]["bevy_ecs"]["path"].as_str(),
+            Some("../bevy/crates/bevy_ecs")
+        );
+    }
+}
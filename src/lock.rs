@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::share::Request;
+use crate::{GitReference, PatchEntry, PatchGroup, PatchSource};
+
+const LOCK_FILE: &str = "bevy-patch.lock";
+// Bumped whenever the layout changes, older files are rejected rather than misread.
+const LOCK_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct Lock {
+    version: u32,
+    request: Request,
+    #[serde(rename = "source")]
+    sources: Vec<LockedSource>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LockedSource {
+    repo: String,
+    commit: String,
+    // Crate name -> subpath in the repository.
+    crates: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    versions: BTreeMap<String, String>,
+}
+
+pub fn lock_path(cargo_toml: &Path) -> PathBuf {
+    match cargo_toml.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.join(LOCK_FILE),
+        _ => PathBuf::from(LOCK_FILE),
+    }
+}
+
+pub fn read(path: &Path) -> Result<Option<Lock>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let version: toml::Table =
+        toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))?;
+    if version.get("version").and_then(|v| v.as_integer()) != Some(LOCK_VERSION as i64) {
+        anyhow::bail!(
+            "{} isn't a version {LOCK_VERSION} lock file, regenerate it with --update-lock",
+            path.display()
+        );
+    }
+    let lock =
+        toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(Some(lock))
+}
+
+impl Lock {
+    // Groups have to be pinned to commits already.
+    pub fn new(request: Request, groups: &[PatchGroup]) -> Result<Self> {
+        let mut sources = Vec::new();
+        for group in groups {
+            let PatchSource::Git {
+                repo,
+                reference: GitReference::Rev(commit),
+            } = &group.source
+            else {
+                anyhow::bail!("Only git sources pinned to a commit can be locked");
+            };
+            sources.push(LockedSource {
+                repo: repo.clone(),
+                commit: commit.clone(),
+                crates: group
+                    .entries
+                    .iter()
+                    .map(|entry| (entry.name.clone(), entry.subpath.clone()))
+                    .collect(),
+                versions: group
+                    .entries
+                    .iter()
+                    .filter_map(|entry| Some((entry.name.clone(), entry.version.clone()?)))
+                    .collect(),
+            });
+        }
+
+        Ok(Self {
+            version: LOCK_VERSION,
+            request,
+            sources,
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let content = format!(
+            "# Written by bevy-patch --lock, refresh the pins with --update-lock.\n{}",
+            toml::to_string_pretty(self)?
+        );
+        crate::atomic::write(path, content)
+    }
+
+    pub fn request(&self) -> &Request {
+        &self.request
+    }
+
+    pub fn check(&self, request: &Request, path: &Path) -> Result<()> {
+        if &self.request != request {
+            anyhow::bail!(
+                "The command line doesn't match what {} was locked for, \
+                 pass --update-lock to resolve and lock it again",
+                path.display()
+            );
+        }
+        Ok(())
+    }
+
+    pub fn groups(&self) -> Vec<PatchGroup> {
+        self.sources
+            .iter()
+            .map(|source| PatchGroup {
+                source: PatchSource::Git {
+                    repo: source.repo.clone(),
+                    reference: GitReference::Rev(source.commit.clone()),
+                },
+                entries: source
+                    .crates
+                    .iter()
+                    .map(|(name, subpath)| PatchEntry {
+                        version: source.versions.get(name).cloned(),
+                        ..PatchEntry::new(name.clone(), subpath.clone())
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+}
@@ -1,17 +1,487 @@
-use std::time::Duration;
+use std::cell::LazyCell;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use base64::Engine;
+use bevy_patch::{
+    CrateFetcher, GitReference, PatchEntry, PatchGroup, PatchSource, apply, atomic, crate_subpath,
+    toml_key, toml_string,
+};
+use clap::builder::BoolishValueParser;
+use clap::parser::ValueSource;
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+use crate::auth::AuthCommand;
+use crate::azure::Azure;
+use crate::bitbucket::Bitbucket;
+use crate::cache::{CacheCommand, CachePolicy};
+use crate::convert::ConvertCommand;
+use crate::fetcher::{GithubFetcher, LocalFetcher};
+use crate::format::{Format, FormatOptions, GroupBy, NixFetcher, Shell, TomlFormatter};
+use crate::gist::ShareTarget;
+use crate::gitlab::Gitlab;
+use crate::hook::HookCommand;
+use crate::profile::ProfileCommand;
+use crate::repo_url::RepoUrl;
+use crate::report::ErrorFormat;
+use crate::signed::RequireSigned;
+use crate::update::SelfCommand;
+
+// `eprintln!` for warnings, they become annotations in GitHub Actions.
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::ci::warning(&format!($($arg)*))
+    };
+}
+
+mod auth;
+mod azure;
+mod bevy_versions;
+mod bisect;
+mod bitbucket;
+mod cache;
+mod cargo_config;
+mod ci;
+mod compare;
+mod convert;
+mod deprecations;
+mod diff_crates;
+mod ecosystem;
+mod explain;
+mod fetcher;
+mod format;
+mod from_lockfile;
+mod frozen;
+mod gha;
+mod gist;
+mod gitlab;
+mod graph;
+mod hook;
+mod http;
+mod impact;
+mod init;
+mod lock;
+mod manifest_version;
+mod missing_crates;
+mod msrv;
+mod netrc;
+mod outdated;
+mod profile;
+mod prompt;
+mod provenance;
+mod repo_url;
+mod report;
+mod share;
+mod signed;
+mod since;
+mod template;
+mod update;
+mod watch;
+mod workspace;
 
 #[derive(Parser)]
 #[command(name = "bevy-patch")]
 #[command(about = "Generate bevy patch entries")]
 struct Cli {
+    /// Include each crate's `version` in the generated entries
+    #[arg(long, global = true)]
+    add_version: bool,
+    /// Patch exactly the crates listed in a file (`-` for stdin, `@file` also works),
+    /// one per line, instead of discovering them
+    #[arg(long, global = true, value_name = "FILE")]
+    crates: Option<String>,
+    /// Also patch a crate living outside `crates/`, e.g. `tools/my_crate[:package_name]`
+    #[arg(long, global = true, value_name = "SUBPATH[:NAME]")]
+    extra_crate: Vec<String>,
+    /// Take one crate from another repository, e.g. `bevy_render=alice/bevy#render-fix`
+    #[arg(long, global = true, value_name = "CRATE=REPO#REF")]
+    source: Vec<String>,
+    /// Patch a crates.io crate with a fork crate living in a renamed directory,
+    /// e.g. `bevy_render=bevy_render_ng`
+    #[arg(long, global = true, value_name = "CRATES_IO_NAME=FORK_NAME")]
+    rename: Vec<String>,
+    /// Where crate directories live, relative to the repository root
+    #[arg(long, global = true, default_value = "crates", value_name = "SUBPATH")]
+    crates_root: String,
+    /// Render the output with a minijinja template instead, see --print-default-template
+    #[arg(long, global = true, value_name = "FILE", conflicts_with = "format")]
+    template: Option<PathBuf>,
+    /// Print the template --format toml renders like and exit
+    #[arg(long)]
+    print_default_template: bool,
+    /// Don't check that the output of --template parses as TOML
+    #[arg(long, global = true, requires = "template")]
+    no_validate: bool,
+    /// Report the packages of --cargo-toml's graph that depend on a patched crate, and would
+    /// rebuild, instead of the entries
+    #[arg(long, global = true)]
+    impact: bool,
+    /// Print the crates added or removed since an earlier output of bevy-patch
+    #[arg(long, global = true, value_name = "FILE")]
+    compare_to: Option<PathBuf>,
+    /// Exit with 2 when --compare-to found added or removed crates
+    #[arg(long, global = true, requires = "compare_to")]
+    fail_on_change: bool,
+    /// Also write a JSON record of the resolved commits, crates and local content digests
+    #[arg(long, global = true, value_name = "FILE")]
+    provenance: Option<PathBuf>,
+    /// Summarize the patched crates and their source in a comment, on by default
+    #[arg(long, global = true, overrides_with = "no_stats")]
+    stats: bool,
+    /// Leave the summary comment out
+    #[arg(long, global = true, overrides_with = "stats")]
+    no_stats: bool,
+    /// Add how long fetching took to the summary, the output then changes from run to run
+    #[arg(long, global = true, conflicts_with_all = ["no_stats", "deterministic"])]
+    timings: bool,
+    /// Make the output byte-identical for the same inputs on every platform, e.g. `/` in paths
+    #[arg(long, global = true)]
+    deterministic: bool,
+    /// Warn about dependency cycles between the patched crates
+    #[arg(long, global = true)]
+    check_circular: bool,
+    /// Warn about entry forms newer cargo versions reject or discourage
+    #[arg(long, global = true)]
+    check_deprecations: bool,
+    /// Fail unless the commits patched in from GitHub carry a valid signature, `warn` only warns
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        num_args = 0..=1,
+        default_missing_value = "error",
+        value_name = "LEVEL"
+    )]
+    require_signed: Option<RequireSigned>,
+    /// Leave out the umbrella `bevy` entry
+    #[arg(long, global = true)]
+    skip_root: bool,
+    /// Leave out implementation-detail crates, `bevy_internal` and any --internal-pattern
+    #[arg(long, global = true)]
+    skip_bevy_internal: bool,
+    /// Another crate name glob for --skip-bevy-internal, e.g. `*_macros`
+    #[arg(
+        long,
+        global = true,
+        value_name = "GLOB",
+        requires = "skip_bevy_internal"
+    )]
+    internal_pattern: Vec<String>,
+    /// Leave out crates with `publish = false`, fetches each crate's Cargo.toml
+    #[arg(long, global = true)]
+    skip_unpublished: bool,
+    /// Only emit the umbrella `bevy` entry
+    #[arg(long, global = true, conflicts_with_all = ["skip_root", "crates", "extra_crate"])]
+    root_only: bool,
+    #[arg(long, global = true, env = "BITBUCKET_USER")]
+    bitbucket_user: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        env = "BITBUCKET_APP_PASSWORD",
+        hide_env_values = true,
+        requires = "bitbucket_user"
+    )]
+    bitbucket_app_password: Option<String>,
+    #[arg(
+        long,
+        global = true,
+        env = "AZURE_DEVOPS_TOKEN",
+        hide_env_values = true
+    )]
+    azure_token: Option<String>,
+    #[arg(long, global = true, env = "BITBUCKET_TOKEN", hide_env_values = true)]
+    bitbucket_token: Option<String>,
+    #[arg(long, global = true, env = "GITLAB_TOKEN", hide_env_values = true)]
+    gitlab_token: Option<String>,
+    /// Answer yes to confirmations instead of prompting
+    #[arg(short, long, global = true)]
+    yes: bool,
+    /// GitHub token, raises the API rate limit and gives access to private repos.
+    /// Falls back to GITHUB_TOKEN, the password of `machine api.github.com` in ~/.netrc, then to `auth device-login`
+    #[arg(long, global = true, env = "BEVY_PATCH_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+    /// Host of a GitHub Enterprise server, its repositories are read through its API and get
+    /// the token. Other hosts never do
+    #[arg(long, global = true, value_name = "HOST")]
+    github_enterprise_host: Vec<String>,
+    /// PEM file with extra root certificates, for TLS-intercepting proxies and internal CAs.
+    /// Falls back to cargo's `http.cainfo`, then git's `http.sslCAInfo`
+    #[arg(long, global = true, env = "SSL_CERT_FILE", value_name = "PATH")]
+    ca_bundle: Option<PathBuf>,
+    /// Proxy for requests, e.g. `http://proxy:3128`. Falls back to HTTPS_PROXY and the like,
+    /// cargo's `http.proxy`, then git's
+    #[arg(long, global = true, value_name = "URL")]
+    proxy: Option<String>,
+    /// Print which proxy and CA bundle requests use, and where each setting came from
+    #[arg(long, global = true)]
+    show_http_config: bool,
+    /// Don't read cached GitHub responses, fresh ones are still cached
+    #[arg(long, global = true)]
+    no_cache: bool,
+    /// Directory for cached GitHub responses
+    #[arg(
+        long,
+        global = true,
+        env = "BEVY_PATCH_CACHE_DIR",
+        visible_alias = "http-cache-dir",
+        value_name = "DIR"
+    )]
+    cache_dir: Option<PathBuf>,
+    /// Drop the cached responses for the requested repo and ref before fetching
+    #[arg(long, global = true)]
+    refresh: bool,
+    /// URL form written into git entries, `ssh` writes `ssh://git@<host>/<path>.git`
+    /// since cargo doesn't accept the `git@<host>:<path>` form
+    #[arg(long, global = true, value_enum, default_value_t = GitUrlStyle::Https)]
+    git_url_style: GitUrlStyle,
+    /// Set `net.git-fetch-with-cli = true` in the project's .cargo/config.toml,
+    /// needed for cargo to fetch private repositories
+    #[arg(long, global = true)]
+    setup_git_cli: bool,
+    /// Wait out a 429 from GitHub instead of failing, secondary rate limits reported as a 403
+    /// are always retried
+    #[arg(long, global = true)]
+    wait_on_rate_limit: bool,
+    /// Longest wait, in seconds, accepted by --wait-on-rate-limit
+    #[arg(long, global = true, default_value_t = 120, value_name = "SECONDS")]
+    max_rate_limit_wait: u64,
+    /// Seconds a request to GitHub, GitLab, Bitbucket or Azure DevOps may take, 5 by
+    /// default and 30 in CI
+    #[arg(long, global = true, value_name = "SECONDS")]
+    timeout: Option<u64>,
+    /// Value of the `X-GitHub-Api-Version` header sent to api.github.com
+    #[arg(long, global = true, default_value = GITHUB_API_VERSION, value_name = "DATE")]
+    github_api_version: String,
+    /// Don't check that the GitHub API host resolves before the first request, never done
+    /// through a proxy
+    #[arg(long, global = true)]
+    skip_preflight: bool,
+    /// The project manifest read by convert, outdated, explain, the checks, watch and init
+    #[arg(
+        long,
+        global = true,
+        default_value = "Cargo.toml",
+        visible_alias = "manifest-path",
+        env = "BEVY_PATCH_MANIFEST_PATH",
+        value_name = "PATH"
+    )]
+    cargo_toml: PathBuf,
+    /// Write the patch with `apply` or `watch --write` even though the project depends on
+    /// several bevy versions
+    #[arg(long, global = true)]
+    allow_multiple: bool,
+    /// The bevy version the patch is meant for when the project depends on several,
+    /// e.g. `0.15`
+    #[arg(long, global = true, requires = "allow_multiple", value_name = "REQ")]
+    target_version: Option<semver::VersionReq>,
+    /// Fail with a diff when the manifest's patch entries differ from the generated ones,
+    /// instead of printing them
+    #[arg(long, global = true)]
+    frozen: bool,
+    /// In GitHub Actions, write a step summary and set the `sha` and `crates` step outputs
+    #[arg(long, global = true)]
+    gha: bool,
+    /// How errors and warnings are printed to stderr
+    #[arg(long, global = true, value_enum, default_value_t = ErrorFormat::Human, value_name = "FMT")]
+    error_format: ErrorFormat,
+    /// Print debug details to stderr
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    #[arg(long, global = true, value_enum, default_value_t = Format::Toml)]
+    format: Format,
+    /// Pad crate names, or keys with --expand, so the `=` line up
+    #[arg(long, global = true)]
+    align: bool,
+    /// Cluster entries by source with a comment per group, even for a single source
+    #[arg(long, global = true, value_enum, value_name = "KEY")]
+    group_by: Option<GroupBy>,
+    /// Short for `--group-by component`
+    #[arg(long, global = true, conflicts_with = "group_by")]
+    group_by_component: bool,
+    /// Write each entry as a `[patch.crates-io.<name>]` table instead of an inline table
+    #[arg(long, global = true)]
+    expand: bool,
+    /// Patch these crates in the `[patch.'<url>']` table of another registry,
+    /// e.g. `https://my-registry.com/index:bevy_ecs,bevy_render`
+    #[arg(long, global = true, value_parser = format::parse_extra_registry, value_name = "URL:CRATES")]
+    extra_registry: Vec<(String, Vec<String>)>,
+    /// Nix function used to fetch the source with `--format nix`
+    #[arg(long, global = true, value_enum, default_value_t = NixFetcher::FetchFromGitHub)]
+    nix_fetcher: NixFetcher,
+    /// Source hash for `--format nix`, prefetched with `nix-prefetch-url` when omitted
+    #[arg(long, global = true, value_name = "HASH")]
+    nix_sha256: Option<String>,
+    /// Shell that `--format cargo-args` quotes for
+    #[arg(long, global = true, value_enum, default_value_t = Shell::Sh)]
+    shell: Shell,
+    /// Pin git sources to their current commits in bevy-patch.lock next to the manifest,
+    /// later runs of the same command line reuse the pins
+    #[arg(long, global = true)]
+    lock: bool,
+    /// Resolve the refs again and rewrite bevy-patch.lock
+    #[arg(long, global = true, conflicts_with = "locked")]
+    update_lock: bool,
+    /// Fail instead of resolving refs when there's no bevy-patch.lock
+    #[arg(long, global = true)]
+    locked: bool,
+    /// Upload the patch and print a link to it
+    #[arg(long, global = true, value_enum, value_name = "TARGET")]
+    share: Option<ShareTarget>,
+    /// Make the shared gist public instead of secret
+    #[arg(long, global = true, requires = "share")]
+    public: bool,
+    /// Turn a warning into an error
+    #[arg(long, global = true, value_enum, value_name = "CHECK")]
+    deny: Vec<Deny>,
+    // Set by `apply`, which writes the entries instead of printing them.
+    #[arg(skip)]
+    apply: bool,
     #[command(subcommand)]
     command: Command,
 }
 
+// The token when --token isn't given, looked up once: the keychain may prompt.
+fn fallback_token() -> &'static Option<String> {
+    static TOKEN: OnceLock<Option<String>> = OnceLock::new();
+    TOKEN.get_or_init(|| {
+        std::env::var("GITHUB_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty())
+            // GitHub tokens are stored as the netrc password.
+            .or_else(|| netrc::parse_netrc("api.github.com").map(|(_, password)| password))
+            .or_else(auth::stored_token)
+    })
+}
+
+impl Cli {
+    fn cache_policy(&self) -> CachePolicy {
+        CachePolicy {
+            read: !self.no_cache && !self.refresh,
+            write: true,
+        }
+    }
+
+    // 5s, or 30s in CI where the network tends to be slower.
+    fn timeout(&self) -> Duration {
+        let default = if ci::CiEnvironment::detect().is_some() {
+            30
+        } else {
+            5
+        };
+        Duration::from_secs(self.timeout.unwrap_or(default))
+    }
+
+    fn azure(&self) -> Azure {
+        Azure {
+            client: http::client(),
+            token: self.azure_token.clone(),
+            timeout: self.timeout(),
+        }
+    }
+
+    fn gitlab(&self) -> Gitlab {
+        Gitlab {
+            client: http::client(),
+            token: self.gitlab_token.clone(),
+            timeout: self.timeout(),
+        }
+    }
+
+    fn bitbucket(&self) -> Bitbucket {
+        Bitbucket {
+            client: http::client(),
+            user: self.bitbucket_user.clone(),
+            app_password: self.bitbucket_app_password.clone(),
+            token: self.bitbucket_token.clone(),
+            timeout: self.timeout(),
+        }
+    }
+
+    fn github(&self) -> Github {
+        if self.verbose {
+            eprintln!(
+                "debug: using GitHub API version {}",
+                self.github_api_version
+            );
+        }
+        Github {
+            client: http::client(),
+            token: self.token.clone().or_else(|| fallback_token().clone()),
+            cache: self.cache_policy(),
+            wait_on_rate_limit: self.wait_on_rate_limit,
+            max_rate_limit_wait: Duration::from_secs(self.max_rate_limit_wait),
+            rate_limit_backoff: Github::SECONDARY_RATE_LIMIT_BACKOFF,
+            timeout: self.timeout(),
+            api_version: self.github_api_version.clone(),
+            preflight: !self.skip_preflight,
+        }
+    }
+
+    fn check_bevy_versions(&self) -> Result<()> {
+        bevy_versions::check(
+            &self.cargo_toml,
+            self.allow_multiple,
+            self.target_version.as_ref(),
+            self.verbose,
+        )
+    }
+
+    fn group_by(&self) -> Option<GroupBy> {
+        if self.group_by_component {
+            Some(GroupBy::Component)
+        } else {
+            self.group_by
+        }
+    }
+
+    fn crates_root(&self) -> &str {
+        self.crates_root.trim_matches('/')
+    }
+
+    fn format_options(&self) -> FormatOptions {
+        FormatOptions {
+            format: self.format,
+            toml: TomlFormatter {
+                align: self.align,
+                group_by: self.group_by(),
+                expand: self.expand,
+                extra_registries: self.extra_registry.clone(),
+                verifications: HashMap::new(),
+                stats: None,
+            },
+            nix_fetcher: self.nix_fetcher,
+            nix_sha256: self.nix_sha256.clone(),
+            shell: self.shell,
+            commits: HashMap::new(),
+            verifications: HashMap::new(),
+            stats: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum GitUrlStyle {
+    Https,
+    Ssh,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Deny {
+    /// The target ref needs a newer rust than the project's toolchain
+    Msrv,
+    /// The project uses a bevy crate the target ref doesn't have
+    MissingCrates,
+}
+
 #[derive(Subcommand)]
 enum Command {
     Path {
@@ -26,13 +496,276 @@ enum Command {
         tag: Option<String>,
         #[arg(long)]
         rev: Option<String>,
+        /// Expand an abbreviated --rev to the full SHA, on by default
+        #[arg(long, requires = "rev", overrides_with = "no_expand_rev")]
+        expand_rev: bool,
+        /// Keep --rev as written in the output instead of expanding it to the full SHA
+        #[arg(long, requires = "rev", overrides_with = "expand_rev")]
+        no_expand_rev: bool,
+        /// A pull request ref, `pull/<n>/head` or `pull/<n>/merge`, pinned by its SHA
+        #[arg(long = "ref", value_name = "REF", conflicts_with_all = ["branch", "tag", "rev"])]
+        git_ref: Option<String>,
+        /// Don't look for a differently named bevy fork when `--repo <owner>` 404s
+        #[arg(long)]
+        no_fork_search: bool,
+        /// Search GitHub for Rust repositories named like `--repo` when it 404s
+        #[arg(long)]
+        fuzzy_repo: bool,
+        /// Take crates from several branches, each from the first branch providing it
+        #[arg(long, num_args = 1.., value_name = "BRANCH", conflicts_with_all = ["branch", "tag", "rev", "git_ref"])]
+        merge_branches: Vec<String>,
+        /// Use the latest release, or the highest semver tag when there are no releases
+        #[arg(long, conflicts_with_all = ["branch", "tag", "rev", "git_ref", "merge_branches"])]
+        latest_tag: bool,
+        /// Use the highest semver tag matching a glob, e.g. `v0.15.*`, implies --latest-tag
+        #[arg(
+            long,
+            visible_alias = "tag-glob",
+            value_name = "GLOB",
+            conflicts_with_all = ["branch", "tag", "rev", "git_ref", "merge_branches"]
+        )]
+        tag_pattern: Option<String>,
+        /// Use the newest `vX.Y.Z` tag matching the bevy requirement in --cargo-toml
+        #[arg(
+            long,
+            conflicts_with_all = ["branch", "tag", "rev", "git_ref", "merge_branches", "latest_tag", "tag_pattern"]
+        )]
+        from_manifest_version: bool,
+        /// Pin the newest commit on --branch at or before a date, RFC 3339 or `YYYY-MM-DD`
+        #[arg(long, value_name = "DATE", requires = "branch")]
+        since: Option<String>,
+        /// Read a --since without an offset as local time instead of UTC
+        #[arg(long, requires = "since")]
+        local: bool,
+    },
+    Github {
+        #[arg(long, default_value = "https://github.com/bevyengine/bevy")]
+        repo: String,
+        #[arg(long, value_parser = parse_pr_number)]
+        pr: u64, // #123456
+        /// Patch to a pull request that was already closed or merged
+        #[arg(long)]
+        allow_closed_pr: bool,
+    },
+    /// Rewrite the patch entries of an existing Cargo.toml
+    #[command(args_conflicts_with_subcommands = true)]
+    Convert {
+        #[command(subcommand)]
+        command: Option<ConvertCommand>,
+        /// Rewrite the patch entries of --cargo-toml in place to the other kind of source
+        #[arg(long, value_enum, value_name = "KIND", required = true)]
+        to: Option<convert::ConvertTo>,
+        /// Existing checkout for `--to path`, warns when it isn't at the pinned ref
+        #[arg(long, value_name = "DIR", required_if_eq("to", "path"))]
+        path: Option<PathBuf>,
+    },
+    /// Pin the revisions of a repository that Cargo.lock was built with
+    FromLockfile {
+        /// Defaults to the Cargo.lock next to --cargo-toml
+        #[arg(long, value_name = "PATH")]
+        lock_file: Option<PathBuf>,
+        #[arg(long, default_value = "https://github.com/bevyengine/bevy")]
+        repo: String,
+    },
+    /// Report whether the revs pinned in Cargo.toml are behind a branch,
+    /// exits with 1 when they are and 2 on errors
+    Outdated {
+        /// Branch the pins are expected to follow
+        #[arg(long, default_value = "main")]
+        branch: String,
+    },
+    /// List the crates added, removed or moved between two refs
+    DiffCrates {
+        #[arg(long, default_value = "https://github.com/bevyengine/bevy")]
+        repo: String,
+        #[arg(long, value_name = "REF")]
+        from: String,
+        #[arg(long, value_name = "REF", default_value = "main")]
+        to: String,
+        /// Also compare the version in each crate's Cargo.toml
+        #[arg(long)]
+        versions: bool,
+    },
+    /// Explain where a crate resolves from and why a patch does or doesn't apply
+    Explain {
+        #[arg(value_name = "CRATE")]
+        crate_name: String,
+    },
+    /// Check that every patched crate resolves to its patch after `cargo metadata`
+    CheckEffective {
+        /// Exit with 1 when a patch isn't in effect
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Report which bevy plugins in the dependency graph won't accept the patched bevy version
+    CheckEcosystem {
+        #[arg(long, default_value = "https://github.com/bevyengine/bevy")]
+        repo: String,
+        /// Branch the patch will point at
+        #[arg(long, default_value = "main")]
+        branch: String,
+    },
+    /// Poll a branch and follow it with the revs pinned in Cargo.toml
+    Watch {
+        #[arg(long, default_value = "main")]
+        branch: String,
+        /// Time between polls, e.g. `30s`, `15m` or `1h`
+        #[arg(long, default_value = "15m", value_parser = watch::parse_interval)]
+        interval: Duration,
+        /// Rewrite the pinned revs, otherwise only report that the branch moved
+        #[arg(long)]
+        write: bool,
+        /// Command to run after the pins were rewritten, e.g. "cargo check"
+        #[arg(long, value_name = "COMMAND", requires = "write")]
+        on_update: Option<String>,
+        /// Which patched crates to `cargo clean -p` after the pins were rewritten,
+        /// `--clean` alone means `changed`
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = watch::Clean::Never,
+            num_args = 0..=1,
+            default_missing_value = "changed",
+            value_name = "WHEN",
+            requires_if("always", "write"),
+            requires_if("changed", "write")
+        )]
+        clean: watch::Clean,
+        /// Run `cargo fetch` after the pins were rewritten, so `--offline` builds find the sources
+        #[arg(long, requires = "write")]
+        prefetch: bool,
+        /// --prefetch with `cargo fetch --locked`
+        #[arg(long, requires = "write", conflicts_with = "prefetch")]
+        prefetch_locked: bool,
+        /// Poll once and exit
+        #[arg(long)]
+        once: bool,
+    },
+    /// Save and replay frequently used command lines
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommand,
+    },
+    /// Write a commented bevy-patch.toml next to the project manifest, for `import`
+    Init {
+        /// Skip the questions and write the defaults
+        #[arg(long)]
+        defaults: bool,
+        /// Overwrite an existing bevy-patch.toml
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print a JSON config reproducing a path, git or github command line,
+    /// e.g. `export git --branch feature --skip-root > patch-config.json`
+    Export {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        args: Vec<String>,
+    },
+    /// Run the command line stored in a config written by `export`,
+    /// arguments after `--` are appended to it
+    Import {
+        file: PathBuf,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Write the entries of a path, git or github command line between `# bevy-patch-begin`
+    /// and `# bevy-patch-end` in --cargo-toml, replacing what an earlier run wrote there
+    Apply {
+        /// The generating command, e.g. `git --branch main`
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        args: Vec<String>,
+    },
+    /// Delete the block `apply` wrote to --cargo-toml, markers included
+    Remove,
+    /// Comment out the block `apply` wrote to --cargo-toml, or uncomment it, and print the state
+    Toggle {
+        /// Comment the block out, even when it already is
+        #[arg(long, conflicts_with = "on")]
+        off: bool,
+        /// Uncomment the block, even when it already is
+        #[arg(long)]
+        on: bool,
+    },
+    /// Print whether --cargo-toml has the block `apply` writes, and whether it's toggled on
+    Status,
+    /// Find the bevy commit that broke the project: patch --cargo-toml to the commits between
+    /// two refs, halving the range after each answer, then put the manifest back
+    Bisect {
+        #[arg(long, default_value = "https://github.com/bevyengine/bevy")]
+        repo: String,
+        /// A ref the project works with, e.g. `v0.14.0`
+        #[arg(long, value_name = "REF")]
+        good: String,
+        /// A ref the project is broken with
+        #[arg(long, value_name = "REF")]
+        bad: String,
+        /// Print a script for `git bisect run` in a bevy checkout instead of asking
+        #[arg(long)]
+        script: bool,
+        /// What the script runs to test a commit, from the manifest's directory
+        #[arg(
+            long,
+            value_name = "COMMAND",
+            default_value = "cargo build",
+            requires = "script"
+        )]
+        test_command: String,
+        /// More arguments for each `apply git` step, e.g. `-- --skip-root`
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Git hooks keeping the committed patch entries in sync
+    Hook {
+        #[command(subcommand)]
+        command: HookCommand,
+    },
+    /// Log in to GitHub without managing a personal access token
+    Auth {
+        #[command(subcommand)]
+        command: AuthCommand,
+    },
+    /// Inspect or clear the cache of GitHub responses
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Manage the bevy-patch installation itself
+    #[command(name = "self")]
+    SelfManage {
+        #[command(subcommand)]
+        command: SelfCommand,
     },
-    // Github { // todo: add shorthand for pull request fetching
-    //     #[arg(long, default_value = "https://github.com/bevyengine/bevy")]
-    //     repo: String,
-    //     #[arg(long)]
-    //     pr: String, // #123456
-    // },
+}
+
+impl Command {
+    // Commands writing `[patch]` entries, which only take effect in the workspace root.
+    fn writes_patch(&self) -> bool {
+        matches!(
+            self,
+            Command::Apply { .. }
+                | Command::Remove
+                | Command::Toggle { .. }
+                | Command::Bisect { .. }
+        )
+    }
+
+    fn reads_manifest(&self) -> bool {
+        matches!(
+            self,
+            Command::Convert { .. }
+                | Command::Apply { .. }
+                | Command::Remove
+                | Command::Toggle { .. }
+                | Command::Status
+                | Command::Bisect { .. }
+                | Command::Outdated { .. }
+                | Command::Explain { .. }
+                | Command::CheckEffective { .. }
+                | Command::CheckEcosystem { .. }
+                | Command::Watch { .. }
+                | Command::Init { .. }
+        )
+    }
 }
 
 #[derive(Deserialize)]
@@ -42,13 +775,123 @@ struct GithubContent {
     content_type: String,
 }
 
+#[derive(Deserialize)]
+struct GithubFile {
+    content: String,
+    encoding: String,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 struct GithubError {
     message: String,
     // documentation_url: Option<String>,
+    #[serde(default)]
     status: String,
 }
 
+impl GithubError {
+    fn is_not_found(err: &anyhow::Error) -> bool {
+        err.downcast_ref::<GithubError>()
+            .is_some_and(|err| err.status == "404")
+    }
+
+    fn is_unprocessable(err: &anyhow::Error) -> bool {
+        err.downcast_ref::<GithubError>()
+            .is_some_and(|err| err.status == "422")
+    }
+
+    // Shares the 403 status with auth failures, only the message tells them apart.
+    fn is_secondary_rate_limit(err: &anyhow::Error) -> bool {
+        err.downcast_ref::<GithubError>()
+            .is_some_and(|err| err.status == "403" && err.message.contains("secondary rate limit"))
+    }
+}
+
+#[derive(Deserialize)]
+struct GithubCommit {
+    sha: String,
+}
+
+#[derive(Deserialize)]
+struct GithubCommitSearch {
+    items: Vec<GithubCommitSearchItem>,
+}
+
+#[derive(Deserialize)]
+struct GithubCommitSearchItem {
+    sha: String,
+    commit: GithubCommitMessage,
+}
+
+#[derive(Deserialize)]
+struct GithubCommitMessage {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct GithubTag {
+    object: GithubObject,
+}
+
+#[derive(Deserialize)]
+struct GithubRelease {
+    tag_name: String,
+}
+
+#[derive(Deserialize)]
+struct GithubTagName {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct GithubRef {
+    object: GithubObject,
+}
+
+#[derive(Deserialize)]
+struct GithubObject {
+    sha: String,
+    #[serde(rename = "type")]
+    object_type: String,
+}
+
+#[derive(Deserialize)]
+struct GithubPull {
+    state: String,
+    #[serde(default)]
+    draft: bool,
+    head: GithubPullHead,
+}
+
+#[derive(Deserialize)]
+struct GithubPullHead {
+    sha: String,
+    // `None` once the fork the pull request came from is deleted.
+    repo: Option<GithubRepo>,
+}
+
+struct PullRequestHead {
+    sha: String,
+    repo: String,
+}
+
+#[derive(Deserialize)]
+struct GithubOwner {
+    #[serde(rename = "type")]
+    owner_type: String,
+}
+
+#[derive(Deserialize)]
+struct GithubRepo {
+    full_name: String,
+    html_url: String,
+    #[serde(default)]
+    fork: bool,
+    #[serde(default)]
+    private: bool,
+    parent: Option<Box<GithubRepo>>,
+}
+
 impl std::error::Error for GithubError {}
 
 impl std::fmt::Display for GithubError {
@@ -57,8 +900,150 @@ impl std::fmt::Display for GithubError {
     }
 }
 
-fn fetch_crates_from_local(path: &str) -> Result<Vec<String>> {
-    let dir = std::fs::read_dir(path.to_owned() + "/crates")?;
+// Percent-encodes everything but unreserved characters, for query values like `?ref=`.
+fn encode_query(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+// Like `encode_query`, but `feature/foo` stays two path segments as the GitHub API expects.
+fn encode_path(value: &str) -> String {
+    value
+        .split('/')
+        .map(encode_query)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[derive(Debug, Clone)]
+struct CrateMetadata {
+    name: String,
+    version: Option<String>,
+    #[allow(dead_code)]
+    description: Option<String>,
+    // Packages this crate needs to build, dev-dependencies are left out.
+    dependencies: Vec<String>,
+    publish: bool,
+}
+
+#[derive(Deserialize)]
+struct CrateManifest {
+    package: Option<CrateManifestPackage>,
+    #[serde(default)]
+    dependencies: toml::Table,
+    #[serde(default, rename = "build-dependencies")]
+    build_dependencies: toml::Table,
+}
+
+impl CrateManifest {
+    // Package names, `foo = { package = "bar" }` depends on `bar`.
+    fn dependency_names(&self) -> Vec<String> {
+        self.dependencies
+            .iter()
+            .chain(&self.build_dependencies)
+            .map(|(name, dependency)| {
+                dependency
+                    .get("package")
+                    .and_then(|package| package.as_str())
+                    .unwrap_or(name)
+                    .to_owned()
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct CrateManifestPackage {
+    name: String,
+    // Either a plain string or `{ workspace = true }`, only the former is reported.
+    version: Option<toml::Value>,
+    description: Option<toml::Value>,
+    // `false`, or a list of the registries it may be published to.
+    publish: Option<toml::Value>,
+}
+
+static METADATA_CACHE: LazyLock<Mutex<HashMap<(u64, String), CrateMetadata>>> =
+    LazyLock::new(Default::default);
+
+fn source_hash(source: &PatchSource) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Reads the manifest of the crate at `subpath` in the source, the root crate has an empty subpath.
+fn fetch_crate_metadata(
+    source: &PatchSource,
+    subpath: &str,
+    github: &Github,
+) -> Result<CrateMetadata> {
+    let key = (source_hash(source), subpath.to_owned());
+    if let Some(metadata) = METADATA_CACHE.lock().unwrap().get(&key) {
+        return Ok(metadata.clone());
+    }
+
+    let manifest = read_manifest(source, subpath, github)?;
+    let manifest_path = manifest_path(subpath);
+    let manifest: CrateManifest =
+        toml::from_str(&manifest).with_context(|| format!("Failed to parse {manifest_path}"))?;
+    let dependencies = manifest.dependency_names();
+    let package = manifest
+        .package
+        .ok_or_else(|| anyhow::anyhow!("{manifest_path} has no [package] table"))?;
+
+    let metadata = CrateMetadata {
+        name: package.name,
+        version: package.version.and_then(|v| v.as_str().map(str::to_owned)),
+        description: package
+            .description
+            .and_then(|d| d.as_str().map(str::to_owned)),
+        dependencies,
+        publish: match package.publish {
+            Some(toml::Value::Boolean(publish)) => publish,
+            Some(toml::Value::Array(registries)) => !registries.is_empty(),
+            _ => true,
+        },
+    };
+
+    METADATA_CACHE.lock().unwrap().insert(key, metadata.clone());
+    Ok(metadata)
+}
+
+fn manifest_path(subpath: &str) -> String {
+    if subpath.is_empty() {
+        "Cargo.toml".to_owned()
+    } else {
+        format!("{subpath}/Cargo.toml")
+    }
+}
+
+fn read_manifest(source: &PatchSource, subpath: &str, github: &Github) -> Result<String> {
+    let manifest_path = manifest_path(subpath);
+    match source {
+        PatchSource::Path { path } if is_bare_git_repo(path) => {
+            git_output(path, &["show", &format!("HEAD:{manifest_path}")])
+        }
+        PatchSource::Path { path } => std::fs::read_to_string(format!("{path}/{manifest_path}"))
+            .with_context(|| format!("Failed to read {path}/{manifest_path}")),
+        PatchSource::Git { repo, .. } if !is_github(repo) => {
+            anyhow::bail!("Reading crate manifests is only supported for GitHub, not {repo}")
+        }
+        PatchSource::Git { repo, reference } => {
+            fetch_file_from_github(repo, &manifest_path, reference.as_str(), github)
+        }
+    }
+}
+
+fn fetch_crates_from_local(path: &str, crates_root: &str) -> Result<Vec<String>> {
+    let dir = std::fs::read_dir(format!("{path}/{crates_root}"))?;
     let mut crates = Vec::new();
     for c in dir {
         let c = c?;
@@ -74,9 +1059,85 @@ fn fetch_crates_from_local(path: &str) -> Result<Vec<String>> {
         );
     }
 
+    // `read_dir` order depends on the file system.
+    crates.sort();
+    Ok(crates)
+}
+
+fn is_bare_git_repo(path: &str) -> bool {
+    let path = std::path::Path::new(path);
+    path.join("HEAD").is_file()
+        && std::fs::read_to_string(path.join("config")).is_ok_and(|config| {
+            config
+                .lines()
+                .map(|line| line.split_whitespace().collect::<String>())
+                .any(|line| line.eq_ignore_ascii_case("bare=true"))
+        })
+}
+
+fn git_output(path: &str, args: &[&str]) -> Result<String> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .args(args)
+        .output()?;
+
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed in {path}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+// Bare clones have no working tree to read, so list `crates/` out of HEAD instead.
+fn fetch_crates_from_bare_git(path: &str, crates_root: &str) -> Result<Vec<String>> {
+    let listing = git_output(path, &["ls-tree", &format!("HEAD:{crates_root}")])?;
+
+    // `<mode> <type> <object>\t<name>`
+    let mut crates: Vec<String> = listing
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .filter(|(info, _)| info.split_whitespace().nth(1) == Some("tree"))
+        .map(|(_, name)| name.to_owned())
+        .collect();
+
+    crates.sort();
+    Ok(crates)
+}
+
+fn is_git_missing(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>()
+        .is_some_and(|err| err.kind() == std::io::ErrorKind::NotFound)
+}
+
+// Reads a curated crate list, one name per line with `#` comments.
+fn read_crate_list(source: &str) -> Result<Vec<String>> {
+    let source = source.strip_prefix('@').unwrap_or(source);
+    let content = if source == "-" {
+        std::io::read_to_string(std::io::stdin()).context("Failed to read crates from stdin")?
+    } else {
+        std::fs::read_to_string(source).with_context(|| format!("Failed to read {source}"))?
+    };
+
+    let mut crates: Vec<String> = Vec::new();
+    for line in content.lines() {
+        let name = line.split('#').next().unwrap_or_default().trim();
+        if !name.is_empty() && !crates.iter().any(|c| c == name) {
+            crates.push(name.to_owned());
+        }
+    }
+
     Ok(crates)
 }
 
+fn is_ssh_url(repo: &str) -> bool {
+    repo.starts_with("git@") || repo.starts_with("ssh://")
+}
+
 // Takes:
 // https://github.com/bevyengine/bevy
 // https://github.com/aceeri/bevy
@@ -84,121 +1145,2299 @@ fn fetch_crates_from_local(path: &str) -> Result<Vec<String>> {
 // github.com/aceeri/bevy -> https://github.com/...
 // aceeri/bevy -> https://github.com/aceeri/bevy
 // aceeri -> https://github.com/aceeri/bevy
+// git@github.com:aceeri/bevy.git -> https://github.com/aceeri/bevy
+// ssh://git@github.com/aceeri/bevy.git -> https://github.com/aceeri/bevy
 fn user_friendly_repo(repo: &str) -> String {
-    let mut corrected = repo.to_owned();
+    RepoUrl::parse(repo).map_or_else(|_| repo.to_owned(), |url| url.to_string())
+}
 
-    // aceeri -> aceeri/bevy
-    if !corrected.contains("/") {
-        corrected = format!("{}/bevy", corrected);
-    }
+fn is_github(repo: &str) -> bool {
+    RepoUrl::parse(repo).is_ok_and(|url| url.is_github())
+}
 
-    // aceeri/bevy -> github.com/aceeri/bevy
-    if !corrected.contains("github.com/") {
-        corrected = format!("github.com/{}", corrected);
-    }
+// bitbucket.org/workspace/bevy, but not aceeri/bevy
+// https://github.com/aceeri/bevy -> ssh://git@github.com/aceeri/bevy.git
+// Cargo doesn't understand the scp-like `git@github.com:aceeri/bevy` form.
+fn ssh_repo_url(repo: &str) -> String {
+    RepoUrl::parse(repo).map_or_else(|_| repo.to_owned(), |url| url.ssh_url())
+}
 
-    // http:// -> https://
-    corrected = corrected.replace("http://", "https://");
+static REPO_REDIRECTS: LazyLock<Mutex<HashMap<String, String>>> = LazyLock::new(Default::default);
 
-    // github.com/aceeri/bevy -> https://github.com/aceeri/bevy
-    if !corrected.contains("https://") {
-        corrected = format!("https://{}", corrected);
+// Renamed and transferred repos redirect to `/repositories/<id>`, the contents API doesn't
+// follow that for the old name, so the repo's current URL is looked up once and used instead.
+// Errors leave the repo as is, the next request reports them.
+fn resolve_repo_redirect(repo: &str, github: &Github) -> Result<String> {
+    if !is_github(repo) {
+        return Ok(repo.to_owned());
+    }
+    if let Some(redirected) = REPO_REDIRECTS.lock().unwrap().get(repo) {
+        return Ok(redirected.clone());
     }
 
-    corrected
+    let redirected = match github.get::<GithubRepo>(&api_repo_url(repo)?) {
+        Ok(found)
+            if !found
+                .html_url
+                .eq_ignore_ascii_case(repo.trim_end_matches(".git")) =>
+        {
+            eprintln!("note: {repo} moved to {}", found.html_url);
+            found.html_url
+        }
+        _ => repo.to_owned(),
+    };
+    REPO_REDIRECTS
+        .lock()
+        .unwrap()
+        .insert(repo.to_owned(), redirected.clone());
+    Ok(redirected)
 }
 
-fn api_url(repo: &str, git_ref: &str) -> String {
-    let repo = user_friendly_repo(repo);
-    let mut api_url = repo.replace("github.com/", "api.github.com/repos/");
-
-    if api_url.ends_with(".git") {
-        api_url = api_url[0..api_url.len() - 4].to_owned();
+fn is_private_repo(repo: &str, github: &Github) -> bool {
+    let Ok(api_url) = api_repo_url(repo) else {
+        return false;
+    };
+    // Without a token GitHub answers 404 for private repositories, no need to ask.
+    if github.token.is_none() {
+        return false;
     }
+    github
+        .get::<GithubRepo>(&api_url)
+        .is_ok_and(|repo| repo.private)
+}
 
-    let url = format!("{}/contents/crates?ref={}", api_url, git_ref);
-    url
+fn api_repo_url(repo: &str) -> Result<String> {
+    RepoUrl::parse(repo)?.api_repo_url()
 }
 
-fn fetch_crates_from_github(repo: &str, git_ref: &str) -> Result<Vec<String>> {
-    let api_url = api_url(repo, git_ref);
+// Pins the REST API so GitHub's breaking changes only arrive when this is bumped.
+const GITHUB_API_VERSION: &str = "2022-11-28";
 
-    let client = reqwest::blocking::Client::new();
-    let response = client
-        .get(&api_url)
-        .timeout(Duration::from_secs(5))
-        .header("User-Agent", "bevy-patch")
-        .send()
-        .context("Failed to fetch from GitHub")?;
+struct Github {
+    client: reqwest::blocking::Client,
+    token: Option<String>,
+    cache: CachePolicy,
+    wait_on_rate_limit: bool,
+    max_rate_limit_wait: Duration,
+    // The first wait after a secondary rate limit without a Retry-After, doubled on each retry.
+    rate_limit_backoff: Duration,
+    // For each request, retries after a rate limit get their own.
+    timeout: Duration,
+    api_version: String,
+    preflight: bool,
+}
 
-    if response.status() == 200 {
-        let content: Vec<GithubContent> =
-            response.json().context("Failed to parse GitHub response")?;
+static PREFLIGHT: OnceLock<bool> = OnceLock::new();
 
-        let mut crates: Vec<String> = content
-            .into_iter()
-            .filter(|c| c.content_type == "dir")
-            .map(|c| c.name)
-            .collect();
+// A broken network otherwise only shows up once the request times out.
+fn resolves_within(host: &str, timeout: Duration) -> bool {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let address = format!("{host}:443");
+    std::thread::spawn(move || {
+        let resolved = address
+            .to_socket_addrs()
+            .is_ok_and(|mut addresses| addresses.next().is_some());
+        let _ = sender.send(resolved);
+    });
+    receiver.recv_timeout(timeout).unwrap_or(false)
+}
 
-        crates.sort();
-        Ok(crates)
-    } else {
-        let err: GithubError = response.json().context("Failed to parse GitHub response")?;
-        Err(anyhow::anyhow!(err))
+// The host to resolve before the first request. A proxy resolves it itself, and the host may
+// well not resolve from here.
+fn preflight_host(url: &str, proxied: bool) -> Option<String> {
+    if proxied {
+        return None;
     }
+    reqwest::Url::parse(url).ok()?.host_str().map(str::to_owned)
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+impl Github {
+    // How often a request is retried after a secondary rate limit before giving up.
+    const RATE_LIMIT_RETRIES: u32 = 3;
+    // Secondary rate limits without a Retry-After are waited out for 60s, 120s, then 240s.
+    const SECONDARY_RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(60);
 
-    let mut result = Vec::new();
-    result.push("[patch.crates-io]".to_owned());
-    result.push("# Bevy Patch".to_owned());
+    fn get<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        if self.cache.read
+            && let Some(body) = cache::read(url)
+        {
+            return serde_json::from_str(&body).context("Failed to parse cached GitHub response");
+        }
 
-    match cli.command {
-        Command::Path { path } => {
-            let crates = fetch_crates_from_local(&path)?;
+        let response = self.send(url)?;
+        if response.status() == 200 {
+            let body = response.text().context("Failed to read GitHub response")?;
+            let parsed = serde_json::from_str(&body).context("Failed to parse GitHub response")?;
+            if self.cache.write {
+                cache::write(url, &body);
+            }
+            Ok(parsed)
+        } else {
+            Err(Self::error(response))
+        }
+    }
+
+    fn error(response: reqwest::blocking::Response) -> anyhow::Error {
+        let status = response.status();
+        match response.text() {
+            Ok(body) => Self::parse_error(status, &body),
+            Err(err) => anyhow::anyhow!(err).context("Failed to read GitHub response"),
+        }
+    }
+
+    fn parse_error(status: reqwest::StatusCode, body: &str) -> anyhow::Error {
+        let mut err: GithubError = match serde_json::from_str(body) {
+            Ok(err) => err,
+            Err(err) => return anyhow::anyhow!(err).context("Failed to parse GitHub response"),
+        };
+        if err.status.is_empty() {
+            err.status = status.as_u16().to_string();
+        }
+        anyhow::anyhow!(err)
+    }
 
-            result.push(format!("bevy = {{ path = \"{path}\" }}"));
+    // The token only goes to GitHub's own API hosts, whatever URL a request ends up with.
+    fn token_for(&self, url: &str) -> Option<&str> {
+        self.token
+            .as_deref()
+            .filter(|_| repo_url::is_github_api_url(url))
+    }
 
-            for c in crates {
-                result.push(format!("{c} = {{ path = \"{path}/crates/{c}\" }}"));
-            }
+    // Never cached, posts create something.
+    fn post<T: DeserializeOwned>(&self, url: &str, body: &serde_json::Value) -> Result<T> {
+        let mut request = self
+            .client
+            .post(url)
+            .timeout(Duration::from_secs(10))
+            .header("User-Agent", "bevy-patch")
+            .header("X-GitHub-Api-Version", &self.api_version)
+            .json(body);
+        if let Some(token) = self.token_for(url) {
+            request = request.bearer_auth(token);
         }
-        Command::Git {
-            repo,
-            branch,
-            tag,
-            rev,
-        } => {
-            let git_ref = tag
-                .as_deref()
-                .or(branch.as_deref())
-                .or(rev.as_deref())
-                .unwrap_or("main");
-
-            let repo = user_friendly_repo(&repo);
-            let crates = fetch_crates_from_github(&repo, git_ref)
-                .context(format!("Github url: {:?}, ref: {:?}", repo, git_ref))?;
-
-            let specifier = if let Some(tag) = &tag {
-                format!("tag = \"{tag}\"")
-            } else if let Some(branch) = &branch {
-                format!("branch = \"{branch}\"")
-            } else if let Some(rev) = &rev {
-                format!("rev = \"{rev}\"")
-            } else {
-                "branch = \"main\"".to_string()
-            };
+        let response = request.send().context("Failed to send to GitHub")?;
+        if response.status().is_success() {
+            response.json().context("Failed to parse GitHub response")
+        } else {
+            Err(Self::error(response))
+        }
+    }
+
+    // Sends the request, waiting out secondary rate limits. GitHub reports them as a 403, always
+    // retried, or a 429, retried with --wait-on-rate-limit. With a Retry-After or not, the wait
+    // backs off without one.
+    fn send(&self, url: &str) -> Result<reqwest::blocking::Response> {
+        if self.preflight
+            && let Some(host) = preflight_host(url, http::uses_proxy())
+            && !*PREFLIGHT.get_or_init(|| resolves_within(&host, Duration::from_secs(1)))
+        {
+            anyhow::bail!(
+                "Cannot reach {host}: DNS resolution failed. Check network connectivity."
+            );
+        }
+
+        let mut retries = 0;
+        loop {
+            let mut request = self
+                .client
+                .get(url)
+                .timeout(self.timeout)
+                .header("User-Agent", "bevy-patch")
+                .header("X-GitHub-Api-Version", &self.api_version);
+            if let Some(token) = self.token_for(url) {
+                request = request.bearer_auth(token);
+            }
+            let response = request.send().context("Failed to fetch from GitHub")?;
+            let status = response.status();
+            if status != reqwest::StatusCode::FORBIDDEN
+                && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+            {
+                return Ok(response);
+            }
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(self.rate_limit_backoff * 2u32.pow(retries));
+            let seconds = retry_after.as_secs();
 
-            result.push(format!("bevy = {{ git = \"{repo}\", {specifier} }}"));
-            for c in crates {
-                result.push(format!("{c} = {{ git = \"{repo}\", {specifier} }}"));
+            if status == reqwest::StatusCode::FORBIDDEN {
+                let err = Self::parse_error(status, &response.text().unwrap_or_default());
+                if !GithubError::is_secondary_rate_limit(&err) {
+                    return Err(err);
+                }
+            } else if !self.wait_on_rate_limit {
+                anyhow::bail!(
+                    "Secondary rate limit hit. Retry after {seconds}s. Use --wait-on-rate-limit to wait automatically."
+                );
+            } else if retry_after > self.max_rate_limit_wait {
+                anyhow::bail!(
+                    "Secondary rate limit hit. Retry after {seconds}s, longer than --max-rate-limit-wait {}s.",
+                    self.max_rate_limit_wait.as_secs()
+                );
             }
+            if retries == Self::RATE_LIMIT_RETRIES {
+                anyhow::bail!(
+                    "GitHub secondary rate limit hit repeatedly. Try using --token for authenticated access."
+                );
+            }
+            retries += 1;
+
+            eprintln!(
+                "note: secondary rate limit hit, retry {retries} of {} in {seconds}s",
+                Self::RATE_LIMIT_RETRIES
+            );
+            std::thread::sleep(retry_after);
         }
     }
+}
 
-    println!("{}", result.join("\n"));
-    Ok(())
+fn fetch_crates_from_github(
+    repo: &str,
+    crates_root: &str,
+    git_ref: &str,
+    github: &Github,
+) -> Result<Vec<String>> {
+    let content: Vec<GithubContent> =
+        github.get(&RepoUrl::parse(repo)?.api_url(crates_root, git_ref)?)?;
+
+    let mut crates: Vec<String> = content
+        .into_iter()
+        .filter(|c| c.content_type == "dir")
+        .map(|c| c.name)
+        .collect();
+
+    crates.sort();
+    Ok(crates)
+}
+
+// Finds the single fork of bevyengine/bevy owned by a user or organization, for when
+// the fork isn't literally named `bevy`.
+fn find_bevy_fork(owner: &str, github: &Github) -> Result<String> {
+    find_bevy_fork_at("https://api.github.com", owner, github)
+}
+
+fn find_bevy_fork_at(api_root: &str, owner: &str, github: &Github) -> Result<String> {
+    let owner_info: GithubOwner = github.get(&format!("{api_root}/users/{owner}"))?;
+    let listing = if owner_info.owner_type == "Organization" {
+        format!("{api_root}/orgs/{owner}/repos")
+    } else {
+        format!("{api_root}/users/{owner}/repos")
+    };
+
+    // The listing has no parents, so forks are told apart by name rather than looked up one
+    // by one. Only the one picked is looked up, plugin forks like `bevy_rapier` are refused then.
+    let mut candidates = Vec::new();
+    for page in 1.. {
+        let repos: Vec<GithubRepo> = github.get(&format!("{listing}?per_page=100&page={page}"))?;
+        let last_page = repos.len() < 100;
+        candidates.extend(
+            repos
+                .into_iter()
+                .filter(|repo| {
+                    let name = repo.full_name.rsplit('/').next().unwrap_or_default();
+                    repo.fork && name.to_lowercase().contains("bevy")
+                })
+                .map(|repo| repo.full_name),
+        );
+        if last_page {
+            break;
+        }
+    }
+
+    let full_name = match candidates.len() {
+        0 => anyhow::bail!("{owner} has no fork of bevyengine/bevy"),
+        1 => candidates.remove(0),
+        _ => {
+            let choice = prompt::choose(
+                &format!("{owner} has multiple forks named like bevy:"),
+                &candidates,
+                "Pick one with --repo",
+            )?;
+            candidates.remove(choice)
+        }
+    };
+    let repo: GithubRepo = github.get(&format!("{api_root}/repos/{full_name}"))?;
+    if repo
+        .parent
+        .is_none_or(|parent| parent.full_name != "bevyengine/bevy")
+    {
+        anyhow::bail!("{full_name} isn't a fork of bevyengine/bevy, pass the fork with --repo");
+    }
+    Ok(repo.html_url)
+}
+
+// Whether a 404 came from the repository itself rather than e.g. a branch it doesn't have.
+fn repo_is_missing(repo: &str, github: &Github) -> Result<bool> {
+    match github.get::<GithubRepo>(&api_repo_url(repo)?) {
+        Ok(_) => Ok(false),
+        Err(err) if GithubError::is_not_found(&err) => Ok(true),
+        Err(err) => Err(err),
+    }
+}
+
+#[derive(Deserialize)]
+struct RepoSearch {
+    items: Vec<RepoSearchResult>,
+}
+
+#[derive(Deserialize)]
+struct RepoSearchResult {
+    full_name: String,
+    description: Option<String>,
+    stargazers_count: u64,
+}
+
+fn search_github_repos(query: &str, github: &Github) -> Result<Vec<RepoSearchResult>> {
+    let search: RepoSearch = github.get(&format!(
+        "https://api.github.com/search/repositories?q={}+language:Rust&per_page=5",
+        encode_query(query)
+    ))?;
+    Ok(search.items)
+}
+
+// Offers the closest matches for a repository that doesn't exist, non-interactive runs
+// get them listed in the error instead.
+fn find_similar_repo(repo: &str, github: &Github) -> Result<String> {
+    let query = repo
+        .trim_start_matches("https://")
+        .trim_start_matches("github.com/")
+        .trim_end_matches(".git");
+    let results = search_github_repos(query, github)?;
+    if results.is_empty() {
+        anyhow::bail!("{repo} doesn't exist and GitHub has no Rust repository like it");
+    }
+
+    let options: Vec<String> = results
+        .iter()
+        .map(|result| match &result.description {
+            Some(description) => format!(
+                "{} ({} stars) {description}",
+                result.full_name, result.stargazers_count
+            ),
+            None => format!("{} ({} stars)", result.full_name, result.stargazers_count),
+        })
+        .collect();
+    let choice = prompt::choose(
+        &format!("{repo} doesn't exist, did you mean:"),
+        &options,
+        "Pick one with --repo",
+    )?;
+    Ok(format!("https://github.com/{}", results[choice].full_name))
+}
+
+fn parse_pr_number(pr: &str) -> Result<u64, String> {
+    pr.trim_start_matches('#')
+        .parse()
+        .map_err(|_| format!("{pr:?} isn't a pull request number"))
+}
+
+// Resolves a pull request to its head commit and the repository that commit lives in,
+// which is the contributor's fork for most pull requests.
+fn resolve_pr_to_sha(
+    repo: &str,
+    pr_number: u64,
+    allow_closed: bool,
+    github: &Github,
+) -> Result<PullRequestHead> {
+    let pull: GithubPull = github.get(&format!("{}/pulls/{pr_number}", api_repo_url(repo)?))?;
+
+    if pull.state == "closed" && !allow_closed {
+        anyhow::bail!("#{pr_number} is closed, pass --allow-closed-pr to patch to it anyway");
+    }
+    if pull.draft {
+        warning!("#{pr_number} is still a draft");
+    }
+
+    // The head stays reachable through `refs/pull/<n>/head` of the base repository.
+    let Some(head_repo) = pull.head.repo else {
+        warning!(
+            "the repository #{pr_number} came from was deleted, using refs/pull/{pr_number}/head of {repo}"
+        );
+        return Ok(PullRequestHead {
+            sha: pull.head.sha,
+            repo: repo.to_owned(),
+        });
+    };
+
+    Ok(PullRequestHead {
+        sha: pull.head.sha,
+        repo: head_repo.html_url,
+    })
+}
+
+// `pull/12345/head` or `refs/pull/12345/merge` -> (12345, "merge")
+fn parse_pull_ref(git_ref: &str) -> Option<(u64, &str)> {
+    let git_ref = git_ref.strip_prefix("refs/").unwrap_or(git_ref);
+    let (number, kind) = git_ref.strip_prefix("pull/")?.split_once('/')?;
+    let number = number.parse().ok()?;
+    matches!(kind, "head" | "merge").then_some((number, kind))
+}
+
+// `*` matches any run of characters, `?` a single one.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let (pattern, text): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), text.chars().collect());
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn fetch_tags(repo: &str, github: &Github) -> Result<Vec<String>> {
+    let api_url = api_repo_url(repo)?;
+    let mut tags = Vec::new();
+    for page in 1.. {
+        let page: Vec<GithubTagName> =
+            github.get(&format!("{api_url}/tags?per_page=100&page={page}"))?;
+        let last_page = page.len() < 100;
+        tags.extend(page.into_iter().map(|tag| tag.name));
+        if last_page {
+            break;
+        }
+    }
+    Ok(tags)
+}
+
+fn resolve_latest_tag(repo: &str, pattern: Option<&str>, github: &Github) -> Result<String> {
+    let api_url = api_repo_url(repo)?;
+    let matches = |tag: &str| pattern.is_none_or(|pattern| glob_matches(pattern, tag));
+
+    match github.get::<GithubRelease>(&format!("{api_url}/releases/latest")) {
+        Ok(release) if matches(&release.tag_name) => return Ok(release.tag_name),
+        Ok(_) => {}
+        Err(err) if GithubError::is_not_found(&err) => {}
+        Err(err) => return Err(err),
+    }
+
+    // No release, or the latest one doesn't match the pattern: pick the highest semver tag.
+    fetch_tags(repo, github)?
+        .into_iter()
+        .filter(|tag| matches(tag))
+        .filter_map(|tag| {
+            let version = semver::Version::parse(tag.trim_start_matches('v')).ok()?;
+            Some((version, tag))
+        })
+        .max()
+        .map(|(_, tag)| tag)
+        .ok_or_else(|| match pattern {
+            Some(pattern) => anyhow::anyhow!("{repo} has no semver tag matching {pattern:?}"),
+            None => anyhow::anyhow!("{repo} has no releases or semver tags"),
+        })
+}
+
+// Cargo can't fetch `refs/pull/*` by name, so they are always pinned by SHA.
+fn resolve_pull_ref(repo: &str, git_ref: &str, github: &Github) -> Result<String> {
+    let (pr_number, kind) = parse_pull_ref(git_ref).ok_or_else(|| {
+        anyhow::anyhow!(
+            "--ref {git_ref:?} should be pull/<n>/head or pull/<n>/merge, use --branch, --tag or --rev otherwise"
+        )
+    })?;
+    if kind == "merge" {
+        warning!(
+            "pull/{pr_number}/merge is a merge preview GitHub recreates whenever #{pr_number} or its base moves"
+        );
+    }
+
+    let pull_ref: GithubRef = github
+        .get(&format!(
+            "{}/git/ref/pull/{pr_number}/{kind}",
+            api_repo_url(repo)?
+        ))
+        .with_context(|| format!("{repo} has no refs/pull/{pr_number}/{kind}"))?;
+    Ok(pull_ref.object.sha)
+}
+
+// 7 to 39 hex digits, git's shortest default abbreviation and up. Shorter ones can't be told
+// apart from names like `cafe` or `beef`.
+fn is_abbreviated_sha(rev: &str) -> bool {
+    (7..40).contains(&rev.len()) && rev.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn is_full_sha(rev: &str) -> bool {
+    rev.len() == 40 && rev.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RevKind {
+    FullSha,
+    AbbreviatedSha,
+    // `refs/tags/...`
+    Tag,
+    // Anything else, e.g. `main`, or too few hex digits to be told apart from a name.
+    BranchLike,
+}
+
+// What a `--rev` looks like, failing on values git wouldn't accept as a ref name at all.
+fn validate_rev(rev: &str) -> Result<RevKind> {
+    let invalid = rev.is_empty()
+        || rev.starts_with(['-', '/'])
+        || rev.ends_with(['/', '.'])
+        || rev.ends_with(".lock")
+        || rev.contains("..")
+        || rev.contains("@{")
+        || rev.contains("//")
+        || rev
+            .chars()
+            .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c));
+    if invalid {
+        anyhow::bail!("--rev {rev:?} isn't a valid commit SHA or ref name");
+    }
+
+    Ok(if is_full_sha(rev) {
+        RevKind::FullSha
+    } else if is_abbreviated_sha(rev) {
+        RevKind::AbbreviatedSha
+    } else if rev.starts_with("refs/tags/") {
+        RevKind::Tag
+    } else {
+        RevKind::BranchLike
+    })
+}
+
+// Resolves an abbreviated commit SHA, or an annotated tag's SHA, to the full commit SHA.
+fn expand_rev(repo: &str, rev: &str, github: &Github) -> Result<String> {
+    expand_rev_at(&api_repo_url(repo)?, rev, github)
+        .with_context(|| format!("rev {rev:?} is ambiguous or unknown on {repo}"))
+}
+
+fn expand_rev_at(api_url: &str, rev: &str, github: &Github) -> Result<String> {
+    match github.get::<GithubCommit>(&format!("{api_url}/commits/{}", encode_path(rev))) {
+        Ok(commit) => Ok(commit.sha),
+        Err(err) if is_full_sha(rev) => peel_tag(api_url, rev, github).map_err(|_| err),
+        // GitHub answers a prefix matching several commits with a 422 and no candidates.
+        Err(err) if GithubError::is_unprocessable(&err) => {
+            let candidates = match commits_with_prefix(api_url, rev, github) {
+                Ok(candidates) if candidates.len() > 1 => candidates,
+                _ => return Err(err),
+            };
+            anyhow::bail!(
+                "rev {rev:?} matches {} commits, pass more of the SHA:\n{}",
+                candidates.len(),
+                candidates.join("\n")
+            )
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// `<sha> <summary>` of the commits whose SHA starts with `prefix`, from the commit search API.
+fn commits_with_prefix(api_url: &str, prefix: &str, github: &Github) -> Result<Vec<String>> {
+    let (api_root, path) = api_url
+        .rsplit_once("/repos/")
+        .ok_or_else(|| anyhow::anyhow!("Unexpected API url {api_url}"))?;
+    let search: GithubCommitSearch = github.get(&format!(
+        "{api_root}/search/commits?q={}",
+        encode_query(&format!("repo:{path} hash:{prefix}"))
+    ))?;
+    Ok(search
+        .items
+        .into_iter()
+        .map(|item| {
+            let summary = item.commit.message.lines().next().unwrap_or_default();
+            format!("  {} {summary}", item.sha)
+        })
+        .collect())
+}
+
+fn peel_tag(api_url: &str, sha: &str, github: &Github) -> Result<String> {
+    let mut sha = sha.to_owned();
+    loop {
+        let tag: GithubTag = github.get(&format!("{api_url}/git/tags/{sha}"))?;
+        match tag.object.object_type.as_str() {
+            "commit" => return Ok(tag.object.sha),
+            "tag" => sha = tag.object.sha,
+            other => anyhow::bail!("tag {sha} points at a {other}, not a commit"),
+        }
+    }
+}
+
+fn fetch_file_from_github(
+    repo: &str,
+    file_path: &str,
+    git_ref: &str,
+    github: &Github,
+) -> Result<String> {
+    let url = format!(
+        "{}/contents/{}?ref={}",
+        api_repo_url(repo)?,
+        encode_path(file_path),
+        encode_query(git_ref)
+    );
+    let file: GithubFile = github.get(&url)?;
+
+    if file.encoding != "base64" {
+        anyhow::bail!("Unexpected encoding {:?} for {file_path}", file.encoding);
+    }
+
+    // GitHub wraps the base64 payload across lines.
+    let content: String = file.content.split_whitespace().collect();
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(content)
+        .with_context(|| format!("Failed to decode {file_path}"))?;
+    String::from_utf8(bytes).with_context(|| format!("{file_path} is not valid UTF-8"))
+}
+
+// `--extra-crate <subpath>[:<package-name>]`
+fn parse_extra_crate(spec: &str) -> (String, Option<String>) {
+    match spec.split_once(':') {
+        Some((subpath, name)) => (subpath.to_owned(), Some(name.to_owned())),
+        None => (spec.to_owned(), None),
+    }
+}
+
+fn extra_crate_entry(source: &PatchSource, spec: &str, github: &Github) -> Result<PatchEntry> {
+    let (subpath, name) = parse_extra_crate(spec);
+    let subpath = subpath.trim_matches('/').to_owned();
+
+    if let PatchSource::Path { path } = source {
+        let manifest = std::path::Path::new(path).join(&subpath).join("Cargo.toml");
+        if !manifest.is_file() {
+            anyhow::bail!("extra crate {subpath:?} has no Cargo.toml in {path}");
+        }
+    }
+
+    // Doubles as the existence check for remote sources.
+    let metadata = fetch_crate_metadata(source, &subpath, github)
+        .with_context(|| format!("extra crate {subpath:?} couldn't be found"))?;
+    Ok(PatchEntry::new(name.unwrap_or(metadata.name), subpath))
+}
+
+fn add_versions(source: &PatchSource, entries: &mut [PatchEntry], github: &Github) -> Result<()> {
+    for entry in entries {
+        let metadata = fetch_crate_metadata(source, &entry.subpath, github)?;
+        if metadata.name != entry.name {
+            warning!(
+                "{} contains package `{}`, cargo will not match it to `{}`",
+                entry.subpath,
+                metadata.name,
+                entry.name
+            );
+        }
+        entry.version = metadata.version;
+    }
+
+    Ok(())
+}
+
+fn parse_rename(spec: &str) -> Result<(String, String)> {
+    spec.split_once('=')
+        .map(|(name, fork_name)| (name.to_owned(), fork_name.to_owned()))
+        .ok_or_else(|| {
+            anyhow::anyhow!("--rename {spec:?} should look like <crates-io-name>=<fork-name>")
+        })
+}
+
+// Cargo matches patches by package name, so only the directory is allowed to differ.
+fn apply_rename(
+    source: &PatchSource,
+    entries: &mut Vec<PatchEntry>,
+    spec: &str,
+    crates_root: &str,
+    github: &Github,
+) -> Result<()> {
+    let (name, fork_name) = parse_rename(spec)?;
+    let subpath = crate_subpath(crates_root, &fork_name);
+    let metadata = fetch_crate_metadata(source, &subpath, github)
+        .with_context(|| format!("renamed crate {subpath:?} couldn't be found"))?;
+
+    if metadata.name != name {
+        anyhow::bail!(
+            "{subpath} contains package `{package}`, but cargo only patches `{name}` with a package named `{name}`.\n\
+             Either rename the package back to `{name}` in the fork, or stop patching and depend on the fork directly \
+             with `{name} = {{ package = \"{package}\", ... }}` in your Cargo.toml",
+            package = metadata.name
+        );
+    }
+
+    entries.retain(|entry| entry.subpath != subpath && entry.name != name);
+    entries.push(PatchEntry::new(name, subpath));
+    Ok(())
+}
+
+// Implementation details of bevy, never patched on their own by --skip-bevy-internal.
+const INTERNAL_CRATES: &[&str] = &["bevy_internal"];
+
+fn build_entries(
+    source: &PatchSource,
+    include_root: bool,
+    crates: Vec<String>,
+    cli: &Cli,
+    github: &Github,
+) -> Result<Vec<PatchEntry>> {
+    let mut entries = Vec::new();
+    if include_root {
+        entries.push(PatchEntry::root());
+    }
+    entries.extend(
+        crates
+            .iter()
+            .map(|c| PatchEntry::discovered(cli.crates_root(), c)),
+    );
+    // Before the filters, which apply to extra crates as well.
+    for spec in &cli.extra_crate {
+        entries.push(extra_crate_entry(source, spec, github)?);
+    }
+    if cli.skip_bevy_internal {
+        entries.retain(|entry| {
+            let internal = INTERNAL_CRATES
+                .iter()
+                .copied()
+                .chain(cli.internal_pattern.iter().map(String::as_str))
+                .any(|pattern| glob_matches(pattern, &entry.name));
+            if internal && cli.verbose {
+                eprintln!("debug: skipping internal crate {}", entry.name);
+            }
+            !internal
+        });
+    }
+    if cli.skip_unpublished {
+        let mut published = Vec::new();
+        for entry in entries {
+            if fetch_crate_metadata(source, &entry.subpath, github)?.publish {
+                published.push(entry);
+            } else if cli.verbose {
+                eprintln!("debug: skipping {}, it has publish = false", entry.name);
+            }
+        }
+        entries = published;
+    }
+
+    for spec in &cli.rename {
+        apply_rename(source, &mut entries, spec, cli.crates_root(), github)?;
+    }
+
+    if cli.add_version {
+        add_versions(source, &mut entries, github)?;
+    }
+
+    Ok(entries)
+}
+
+// `--source <crate>=<repo>#<ref>`, the ref defaults to `main`.
+fn parse_source_override(spec: &str) -> Result<(String, String, String)> {
+    let (crate_name, location) = spec.split_once('=').ok_or_else(|| {
+        anyhow::anyhow!("--source {spec:?} should look like <crate>=<repo>#<ref>")
+    })?;
+    let (repo, git_ref) = location.split_once('#').unwrap_or((location, "main"));
+    Ok((
+        crate_name.to_owned(),
+        user_friendly_repo(repo),
+        git_ref.to_owned(),
+    ))
+}
+
+// The override syntax doesn't say what kind of ref it is, so ask GitHub.
+fn resolve_reference(repo: &str, git_ref: &str, github: &Github) -> Result<GitReference> {
+    if is_abbreviated_sha(git_ref) || is_full_sha(git_ref) {
+        return Ok(GitReference::Rev(expand_rev(repo, git_ref, github)?));
+    }
+
+    let branch = github.get::<serde_json::Value>(&format!(
+        "{}/branches/{}",
+        api_repo_url(repo)?,
+        encode_path(git_ref)
+    ));
+    match branch {
+        Ok(_) => Ok(GitReference::Branch(git_ref.to_owned())),
+        Err(err) if GithubError::is_not_found(&err) => Ok(GitReference::Tag(git_ref.to_owned())),
+        Err(err) => Err(err),
+    }
+}
+
+// Lists every ref in parallel, each crate is taken from the first ref that has it.
+fn fetch_crates_from_multiple_refs(
+    repo: &str,
+    refs: &[&str],
+    crates_root: &str,
+    github: &Github,
+) -> Result<BTreeMap<String, (String, PatchEntry)>> {
+    let listings = std::thread::scope(|scope| {
+        let handles: Vec<_> = refs
+            .iter()
+            .map(|git_ref| {
+                scope.spawn(move || fetch_crates_from_github(repo, crates_root, git_ref, github))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("crate listing thread panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    let mut merged = BTreeMap::new();
+    for (git_ref, listing) in refs.iter().zip(listings) {
+        let crates = listing.with_context(|| format!("Repo url: {repo:?}, ref: {git_ref:?}"))?;
+        for name in crates {
+            merged.entry(name.clone()).or_insert_with(|| {
+                (
+                    git_ref.to_string(),
+                    PatchEntry::discovered(crates_root, &name),
+                )
+            });
+        }
+    }
+
+    Ok(merged)
+}
+
+// `--merge-branches`: moves each crate out of the first branch's group into its own branch's group.
+fn split_by_branch(
+    groups: &mut Vec<PatchGroup>,
+    branch_of: &BTreeMap<String, String>,
+    cli: &Cli,
+    github: &Github,
+) -> Result<()> {
+    let PatchSource::Git { repo, reference } = groups[0].source.clone() else {
+        return Ok(());
+    };
+
+    let first = groups.len();
+    let entries = std::mem::take(&mut groups[0].entries);
+    for entry in entries {
+        let branch = match branch_of.get(&entry.name) {
+            Some(branch) if branch != reference.as_str() => branch,
+            _ => {
+                groups[0].entries.push(entry);
+                continue;
+            }
+        };
+        let source = PatchSource::Git {
+            repo: repo.clone(),
+            reference: GitReference::Branch(branch.clone()),
+        };
+        match groups[first..]
+            .iter_mut()
+            .find(|group| group.source == source)
+        {
+            Some(group) => group.entries.push(entry),
+            None => groups.push(PatchGroup {
+                source,
+                entries: vec![entry],
+            }),
+        }
+    }
+
+    if cli.add_version {
+        for group in &mut groups[first..] {
+            add_versions(&group.source, &mut group.entries, github)?;
+        }
+    }
+    Ok(())
+}
+
+// Moves the crates named by `--source` out of the base group into one group per override source.
+fn apply_source_overrides(
+    mut base: PatchGroup,
+    specs: &[String],
+    cli: &Cli,
+    github: &Github,
+) -> Result<Vec<PatchGroup>> {
+    let mut sources: Vec<((String, String), Vec<String>)> = Vec::new();
+    for spec in specs {
+        let (crate_name, repo, git_ref) = parse_source_override(spec)?;
+        let key = (repo, git_ref);
+        match sources.iter_mut().find(|(existing, _)| *existing == key) {
+            Some((_, crates)) => crates.push(crate_name),
+            None => sources.push((key, vec![crate_name])),
+        }
+    }
+
+    let mut groups = Vec::new();
+    for ((repo, git_ref), crates) in sources {
+        if !is_github(&repo) {
+            anyhow::bail!(
+                "--source can only point at GitHub repositories, not {repo}, \
+                 pass --github-enterprise-host for a GitHub Enterprise server"
+            );
+        }
+        let reference = resolve_reference(&repo, &git_ref, github)
+            .context(format!("Github url: {repo:?}, ref: {git_ref:?}"))?;
+        let available =
+            fetch_crates_from_github(&repo, cli.crates_root(), reference.as_str(), github)
+                .context(format!("Github url: {repo:?}, ref: {git_ref:?}"))?;
+        let source = PatchSource::Git { repo, reference };
+
+        let mut entries = Vec::new();
+        for crate_name in crates {
+            if crate_name != "bevy" && !available.contains(&crate_name) {
+                anyhow::bail!("{} has no crate named {crate_name}", source.label());
+            }
+
+            base.entries.retain(|entry| entry.name != crate_name);
+            entries.push(if crate_name == "bevy" {
+                PatchEntry::root()
+            } else {
+                PatchEntry::discovered(cli.crates_root(), &crate_name)
+            });
+        }
+
+        if cli.add_version {
+            add_versions(&source, &mut entries, github)?;
+        }
+        groups.push(PatchGroup { source, entries });
+    }
+
+    groups.insert(0, base);
+    Ok(groups)
+}
+
+// The workspace manifest cargo would use from the current directory.
+fn cargo_project_manifest() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("CARGO_MANIFEST_DIR") {
+        return Some(PathBuf::from(dir).join("Cargo.toml"));
+    }
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let output = std::process::Command::new(cargo)
+        .args(["locate-project", "--workspace", "--message-format", "plain"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(PathBuf::from(String::from_utf8(output.stdout).ok()?.trim()))
+}
+
+// Gives every option without a variable of its own a `BEVY_PATCH_<LONG>` one, e.g.
+// `BEVY_PATCH_REPO` for `--repo`. Flags take explicit booleans: 1/0, true/false, yes/no, on/off.
+fn with_env_vars(command: clap::Command) -> clap::Command {
+    command
+        .mut_args(|arg| {
+            if arg.get_env().is_some()
+                || !matches!(
+                    arg.get_action(),
+                    ArgAction::Set | ArgAction::Append | ArgAction::SetTrue
+                )
+            {
+                return arg;
+            }
+            let Some(long) = arg.get_long() else {
+                return arg;
+            };
+            let name = format!("BEVY_PATCH_{}", long.to_ascii_uppercase().replace('-', "_"));
+            let arg = arg.env(name);
+            if matches!(arg.get_action(), ArgAction::SetTrue) {
+                arg.value_parser(BoolishValueParser::new())
+            } else {
+                arg
+            }
+        })
+        .mut_subcommands(with_env_vars)
+}
+
+// Flags set to false through their env var, clap would still count them as given for
+// `requires` and `conflicts_with`.
+fn disabled_flag_vars(command: &clap::Command) -> Vec<std::ffi::OsString> {
+    let mut vars: Vec<_> = command
+        .get_arguments()
+        .filter(|arg| matches!(arg.get_action(), ArgAction::SetTrue))
+        .filter_map(|arg| arg.get_env())
+        .filter(|name| {
+            std::env::var(name).is_ok_and(|value| {
+                matches!(
+                    value.trim().to_ascii_lowercase().as_str(),
+                    "0" | "false" | "no" | "off" | "n" | "f"
+                )
+            })
+        })
+        .map(|name| name.to_owned())
+        .collect();
+    for subcommand in command.get_subcommands() {
+        vars.extend(disabled_flag_vars(subcommand));
+    }
+    vars
+}
+
+// Command lines from profiles and imports, read with the same env vars as the real one.
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Cli> {
+    let matches = with_env_vars(Cli::command())
+        .try_get_matches_from(std::iter::once("bevy-patch".to_owned()).chain(args))?;
+    Ok(Cli::from_arg_matches(&matches)?)
+}
+
+fn main() -> ExitCode {
+    let mut args: Vec<_> = std::env::args_os().collect();
+    // `cargo bevy-patch <args>` runs us as `cargo-bevy-patch bevy-patch <args>`.
+    let cargo_subcommand = args.get(1).is_some_and(|arg| arg == "bevy-patch");
+    for name in disabled_flag_vars(&with_env_vars(Cli::command())) {
+        // SAFETY: no other thread is running yet.
+        unsafe { std::env::remove_var(name) };
+    }
+    // `Arg::env` reads the variable right away, so the command is built again without them.
+    let mut command = with_env_vars(Cli::command());
+    if cargo_subcommand {
+        args.remove(1);
+        command = command.bin_name("cargo bevy-patch");
+    }
+    // `--print-default-template` is the one way to run without a subcommand.
+    if args.iter().any(|arg| arg == "--print-default-template")
+        && command
+            .clone()
+            .subcommand_required(false)
+            .get_matches_from(args.clone())
+            .get_flag("print_default_template")
+    {
+        print!("{}", template::DEFAULT_TEMPLATE);
+        return ExitCode::SUCCESS;
+    }
+    let matches = command.get_matches_from(args);
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    if cargo_subcommand
+        && matches.value_source("cargo_toml") == Some(ValueSource::DefaultValue)
+        && let Some(manifest) = cargo_project_manifest()
+    {
+        cli.cargo_toml = manifest;
+    }
+    if matches.value_source("cargo_toml") == Some(ValueSource::DefaultValue)
+        && cli.command.writes_patch()
+        && let Some(root) = workspace::member_of(&cli.cargo_toml)
+    {
+        eprintln!("note: using the workspace root {}", root.display());
+        cli.cargo_toml = root;
+    }
+
+    report::set_error_format(cli.error_format);
+    prompt::set_assume_yes(cli.yes);
+    let ci = ci::CiEnvironment::detect();
+    ci::set_ci(ci);
+    // Nobody is there to answer, even when a terminal is attached.
+    prompt::set_interactive(ci.is_none());
+    cache::set_cache_dir(cli.cache_dir.clone());
+    repo_url::set_enterprise_hosts(&cli.github_enterprise_host);
+
+    let origin = |id, flag| match matches.value_source(id) {
+        Some(ValueSource::EnvVariable) => http::Origin::Env(
+            with_env_vars(Cli::command())
+                .get_arguments()
+                .find(|arg| arg.get_id() == id)
+                .and_then(|arg| arg.get_env())
+                .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+        ),
+        _ => http::Origin::Flag(flag),
+    };
+    let http_config = http::HttpConfig::resolve(
+        cli.proxy.clone().map(|value| http::Setting {
+            value,
+            origin: origin("proxy", "--proxy"),
+        }),
+        cli.ca_bundle.clone().map(|value| http::Setting {
+            value,
+            origin: origin("ca_bundle", "--ca-bundle"),
+        }),
+    );
+    if cli.show_http_config {
+        http_config.show();
+    }
+
+    match http::configure(&http_config, cli.verbose).and_then(|()| run(&cli)) {
+        Ok(code) => code,
+        Err(err) => {
+            if report::is_json() {
+                report::JsonReporter(&err).print();
+            } else {
+                ci::error(&format!("{err:?}"));
+            }
+            if err.is::<prompt::PromptRefused>() {
+                ExitCode::from(prompt::REFUSED_EXIT_CODE)
+            } else if matches!(cli.command, Command::Outdated { .. }) {
+                ExitCode::from(outdated::ERROR_EXIT_CODE)
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<ExitCode> {
+    if cli.command.reads_manifest() && !cli.cargo_toml.is_file() {
+        anyhow::bail!(
+            "{} doesn't exist or isn't a file, pass --cargo-toml <path>",
+            cli.cargo_toml.display()
+        );
+    }
+
+    if cli.command.writes_patch() {
+        workspace::refuse_member(&cli.cargo_toml)?;
+    }
+
+    // Built on first use, most commands never talk to GitHub.
+    let github = LazyCell::new(|| cli.github());
+    match &cli.command {
+        Command::Cache { command } => cache::run(command)?,
+        Command::Auth { command } => auth::run(command)?,
+        Command::Hook { command } => hook::run(command, &cli.cargo_toml)?,
+        Command::Apply { args } => {
+            // `--cargo-toml` given after `apply` still wins, the last occurrence does.
+            let manifest = [
+                "--cargo-toml".to_owned(),
+                cli.cargo_toml.display().to_string(),
+            ];
+            let mut apply_cli = parse_args(manifest.into_iter().chain(args.iter().cloned()))?;
+            if !matches!(
+                apply_cli.command,
+                Command::Path { .. } | Command::Git { .. } | Command::Github { .. }
+            ) {
+                anyhow::bail!("apply takes a path, git or github command line");
+            }
+            if apply_cli.expand || !apply_cli.extra_registry.is_empty() {
+                anyhow::bail!(
+                    "apply writes inline [patch.crates-io] entries, drop --expand and --extra-registry"
+                );
+            }
+            apply_cli.apply = true;
+            return generate(&apply_cli, &apply_cli.github());
+        }
+        Command::Remove => apply::remove(&cli.cargo_toml)?,
+        Command::Toggle { off, on } => {
+            apply::toggle(&cli.cargo_toml, (*off || *on).then_some(*on))?
+        }
+        Command::Status => println!("{}", apply::status(&cli.cargo_toml)?),
+        Command::Bisect {
+            repo,
+            good,
+            bad,
+            script,
+            test_command,
+            args,
+        } => {
+            if *script {
+                print!(
+                    "{}",
+                    bisect::script(&cli.cargo_toml, repo, good, bad, test_command, args)?
+                );
+            } else {
+                return bisect::run(&cli.cargo_toml, repo, good, bad, args, &github);
+            }
+        }
+        Command::Profile { command } => {
+            if let Some(args) = profile::run(command)? {
+                let profile_cli = parse_args(args)?;
+                if matches!(profile_cli.command, Command::Profile { .. }) {
+                    anyhow::bail!("A profile can't run another profile");
+                }
+                return run(&profile_cli);
+            }
+        }
+        Command::Init { defaults, force } => init::run(&cli.cargo_toml, *defaults, *force)?,
+        Command::Export { args } => share::export(args)?,
+        Command::Import { file, args } => {
+            let imported = share::import(file)?;
+            let import_cli = parse_args(imported.args.iter().chain(args).cloned())?;
+            return run(&import_cli);
+        }
+        Command::SelfManage { command } => update::run(command, &github)?,
+        Command::Convert { command, to, path } => match (command, to) {
+            (Some(command), _) => {
+                let groups = convert::run(command, &cli.cargo_toml, cli.crates_root(), &github)?;
+                println!("{}", cli.format_options().formatter().format(&groups)?);
+            }
+            (None, Some(to)) => {
+                convert::rewrite(
+                    &cli.cargo_toml,
+                    *to,
+                    path.as_deref(),
+                    cli.crates_root(),
+                    &github,
+                )?;
+            }
+            (None, None) => anyhow::bail!("convert needs --to <KIND> or a subcommand"),
+        },
+        Command::FromLockfile { lock_file, repo } => {
+            let lock_file = lock_file
+                .clone()
+                .unwrap_or_else(|| cli.cargo_toml.with_file_name("Cargo.lock"));
+            let groups = from_lockfile::run(&lock_file, repo, cli.crates_root(), cli.add_version)?;
+            println!("{}", cli.format_options().formatter().format(&groups)?);
+        }
+        Command::Outdated { branch } => {
+            return outdated::run(&cli.cargo_toml, branch, cli.format, &github);
+        }
+        Command::DiffCrates {
+            repo,
+            from,
+            to,
+            versions,
+        } => diff_crates::run(
+            repo,
+            from,
+            to,
+            *versions,
+            cli.crates_root(),
+            cli.format,
+            &github,
+        )?,
+        Command::Explain { crate_name } => explain::run(crate_name, &cli.cargo_toml, cli.format)?,
+        Command::CheckEffective { strict } => {
+            return explain::check_effective(&cli.cargo_toml, *strict, cli.format);
+        }
+        Command::CheckEcosystem { repo, branch } => {
+            ecosystem::run(&cli.cargo_toml, repo, branch, cli.format, &github)?
+        }
+        Command::Watch {
+            branch,
+            interval,
+            write,
+            on_update,
+            clean,
+            prefetch,
+            prefetch_locked,
+            once,
+        } => {
+            if *write {
+                cli.check_bevy_versions()?;
+            }
+            // Polling has to see the branch move, cached responses would hide that.
+            let mut github = cli.github();
+            github.cache.read = false;
+            watch::run(
+                &cli.cargo_toml,
+                branch,
+                *interval,
+                write
+                    .then_some(watch::Update {
+                        on_update: on_update.as_deref(),
+                        clean: *clean,
+                        prefetch: (*prefetch || *prefetch_locked).then_some(*prefetch_locked),
+                    })
+                    .as_ref(),
+                *once,
+                &github,
+            )?
+        }
+        _ => return generate(cli, &github),
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+// Discovers the crates and where each comes from, for the path, git and github commands.
+fn resolve_groups(cli: &Cli, github: &Github) -> Result<Vec<PatchGroup>> {
+    let crate_list = cli.crates.as_deref().map(read_crate_list).transpose()?;
+    // A curated list is closed, the root crate is only patched when it's listed.
+    let include_root = !cli.skip_root
+        && crate_list
+            .as_ref()
+            .is_none_or(|list| list.iter().any(|c| c == "bevy"));
+    let crate_list = if cli.root_only {
+        // Nothing to discover.
+        Some(Vec::new())
+    } else {
+        crate_list.map(|list| list.into_iter().filter(|c| c != "bevy").collect::<Vec<_>>())
+    };
+
+    let mut branch_of = BTreeMap::new();
+    let (source, crates) = match &cli.command {
+        Command::Path { path } => {
+            let crates = match crate_list {
+                Some(crates) => crates,
+                None => LocalFetcher {
+                    path,
+                    crates_root: cli.crates_root(),
+                }
+                .fetch()?,
+            };
+
+            (PatchSource::Path { path: path.clone() }, crates)
+        }
+        Command::Git {
+            repo,
+            merge_branches,
+            ..
+        } if !merge_branches.is_empty() => {
+            let repo = resolve_repo_redirect(&user_friendly_repo(repo), github)?;
+            let refs: Vec<&str> = merge_branches.iter().map(String::as_str).collect();
+            let merged = fetch_crates_from_multiple_refs(&repo, &refs, cli.crates_root(), github)?;
+            let crates = crate_list.unwrap_or_else(|| merged.keys().cloned().collect());
+            branch_of = merged
+                .into_iter()
+                .map(|(name, (branch, _))| (name, branch))
+                .collect();
+
+            let reference = GitReference::Branch(merge_branches[0].clone());
+            (PatchSource::Git { repo, reference }, crates)
+        }
+        Command::Git {
+            repo,
+            branch,
+            tag,
+            rev,
+            git_ref,
+            no_fork_search,
+            fuzzy_repo,
+            latest_tag,
+            tag_pattern,
+            since,
+            local,
+            from_manifest_version,
+            no_expand_rev,
+            ..
+        } => {
+            let reference = if let Some(tag) = tag {
+                GitReference::Tag(tag.clone())
+            } else if let Some(branch) = branch {
+                GitReference::Branch(branch.clone())
+            } else if let Some(rev) = rev {
+                if validate_rev(rev)? == RevKind::BranchLike {
+                    warning!(
+                        "--rev {rev} looks like a branch name, not a commit, pass --branch {rev} if that's what it is"
+                    );
+                }
+                GitReference::Rev(rev.clone())
+            } else {
+                GitReference::Branch("main".to_owned())
+            };
+
+            let owner = repo;
+            let mut repo = resolve_repo_redirect(&user_friendly_repo(owner), github)?;
+            if cli.refresh {
+                cache::remove_matching(&api_repo_url(&repo)?, &encode_query(reference.as_str()))?;
+            }
+            let mut reference = reference;
+            if let Some(git_ref) = git_ref {
+                reference = GitReference::Rev(resolve_pull_ref(&repo, git_ref, github)?);
+            }
+            if *latest_tag || tag_pattern.is_some() {
+                let tag = resolve_latest_tag(&repo, tag_pattern.as_deref(), github)?;
+                eprintln!("note: using the latest tag, reproduce with --tag {tag}");
+                reference = GitReference::Tag(tag);
+            }
+            if *from_manifest_version {
+                let requirement = manifest_version::bevy_requirement(&cli.cargo_toml)?;
+                let tag = manifest_version::resolve_tag(
+                    &requirement,
+                    &fetch_tags(&repo, github)?,
+                    &repo,
+                )?;
+                eprintln!(
+                    "note: using {tag} for bevy = \"{requirement}\", reproduce with --tag {tag}"
+                );
+                reference = GitReference::Tag(tag);
+            }
+            if let Some(since) = since {
+                let instant = since::parse_since(since, *local)?;
+                let (sha, date) = since::resolve(&repo, reference.as_str(), instant, github)?;
+                eprintln!("note: using {sha} committed {date}, reproduce with --rev {sha}");
+                reference = GitReference::Rev(sha);
+            }
+            let crates = match crate_list {
+                Some(crates) => Ok(crates),
+                None => fetcher::for_repo(&repo, &reference, cli, github)?.fetch(),
+            };
+            let search =
+                is_github(&repo) && (*fuzzy_repo || (!no_fork_search && !owner.contains('/')));
+            let repo_missing = match &crates {
+                Err(err) if search && GithubError::is_not_found(err) => {
+                    repo_is_missing(&repo, github)?
+                }
+                _ => false,
+            };
+            let crates = match crates {
+                Err(_) if *fuzzy_repo && repo_missing => {
+                    repo = find_similar_repo(owner, github)
+                        .context(format!("{repo} doesn't exist, searched for it instead"))?;
+                    eprintln!("note: using {repo}, reproduce with --repo {repo}");
+                    GithubFetcher {
+                        repo: &repo,
+                        crates_root: cli.crates_root(),
+                        git_ref: reference.as_str(),
+                        github,
+                    }
+                    .fetch()
+                }
+                // `--repo <owner>` guessed `<owner>/bevy`, the fork may be named differently.
+                Err(_) if repo_missing => {
+                    repo = find_bevy_fork(owner, github)
+                        .context(format!("{repo} doesn't exist, searched for forks instead"))?;
+                    eprintln!("note: using {repo}, the bevy fork owned by {owner}");
+                    GithubFetcher {
+                        repo: &repo,
+                        crates_root: cli.crates_root(),
+                        git_ref: reference.as_str(),
+                        github,
+                    }
+                    .fetch()
+                }
+                crates => crates,
+            }
+            .context(format!(
+                "Repo url: {:?}, ref: {:?}",
+                repo,
+                reference.as_str()
+            ))?;
+
+            // Cargo wants full SHAs, and tag objects need to be peeled to their commit.
+            if let GitReference::Rev(rev) = &reference
+                && !*no_expand_rev
+                && (is_abbreviated_sha(rev) || is_full_sha(rev))
+                && is_github(&repo)
+            {
+                let sha = expand_rev(&repo, rev, github)?;
+                if &sha != rev {
+                    eprintln!("note: expanded rev {rev} to {sha}");
+                }
+                reference = GitReference::Rev(sha);
+            }
+
+            (PatchSource::Git { repo, reference }, crates)
+        }
+        Command::Github {
+            repo,
+            pr,
+            allow_closed_pr,
+        } => {
+            let repo = resolve_repo_redirect(&user_friendly_repo(repo), github)?;
+            if cli.refresh {
+                cache::remove_matching(&api_repo_url(&repo)?, &format!("pulls/{pr}"))?;
+            }
+            let head = resolve_pr_to_sha(&repo, *pr, *allow_closed_pr, github)
+                .context(format!("Github url: {repo:?}, pull request: #{pr}"))?;
+            if head.repo != repo {
+                eprintln!("note: #{pr} comes from {}", head.repo);
+            }
+
+            let crates = match crate_list {
+                Some(crates) => crates,
+                None => GithubFetcher {
+                    repo: &head.repo,
+                    crates_root: cli.crates_root(),
+                    git_ref: &head.sha,
+                    github,
+                }
+                .fetch()
+                .context(format!("Repo url: {:?}, ref: {:?}", head.repo, head.sha))?,
+            };
+
+            let source = PatchSource::Git {
+                repo: head.repo,
+                reference: GitReference::Rev(head.sha),
+            };
+            (source, crates)
+        }
+        Command::Cache { .. }
+        | Command::Auth { .. }
+        | Command::Hook { .. }
+        | Command::Apply { .. }
+        | Command::Remove
+        | Command::Toggle { .. }
+        | Command::Status
+        | Command::Bisect { .. }
+        | Command::Profile { .. }
+        | Command::Init { .. }
+        | Command::Export { .. }
+        | Command::Import { .. }
+        | Command::SelfManage { .. }
+        | Command::Convert { .. }
+        | Command::FromLockfile { .. }
+        | Command::Outdated { .. }
+        | Command::DiffCrates { .. }
+        | Command::Explain { .. }
+        | Command::CheckEffective { .. }
+        | Command::CheckEcosystem { .. }
+        | Command::Watch { .. } => {
+            unreachable!("handled in main")
+        }
+    };
+
+    let entries = build_entries(&source, include_root, crates, cli, github)?;
+    let mut groups =
+        apply_source_overrides(PatchGroup { source, entries }, &cli.source, cli, github)?;
+    split_by_branch(&mut groups, &branch_of, cli, github)?;
+    if cli.deterministic {
+        normalize_separators(&mut groups);
+    }
+    Ok(groups)
+}
+
+// `..\bevy` -> `../bevy`, cargo reads both on Windows.
+fn normalize_separators(groups: &mut [PatchGroup]) {
+    for group in groups {
+        if let PatchSource::Path { path } = &mut group.source {
+            *path = path.replace('\\', "/");
+        }
+        for entry in &mut group.entries {
+            entry.subpath = entry.subpath.replace('\\', "/");
+        }
+    }
+}
+
+// The groups from bevy-patch.lock, or freshly resolved ones, pinned and locked with --lock.
+fn locked_groups(cli: &Cli, github: &Github) -> Result<Vec<PatchGroup>> {
+    let path = lock::lock_path(&cli.cargo_toml);
+    let uses_lock = !matches!(cli.command, Command::Path { .. });
+    let existing = if uses_lock && !cli.update_lock {
+        lock::read(&path)?
+    } else {
+        None
+    };
+    if let Some(lock) = existing {
+        lock.check(&share::Request::from_cli(cli)?, &path)?;
+        return Ok(lock.groups());
+    }
+    if cli.locked {
+        anyhow::bail!("--locked needs {}, create it with --lock", path.display());
+    }
+
+    let mut groups = resolve_groups(cli, github)?;
+    if !cli.lock && !cli.update_lock {
+        return Ok(groups);
+    }
+    if !uses_lock {
+        anyhow::bail!("A local path has no commit to lock");
+    }
+    for group in &mut groups {
+        if let PatchSource::Git { repo, reference } = &mut group.source {
+            if !is_github(repo) {
+                anyhow::bail!("Only GitHub sources can be locked, not {repo}");
+            }
+            *reference = GitReference::Rev(expand_rev(repo, reference.as_str(), github)?);
+        }
+    }
+    let lock = lock::Lock::new(share::Request::from_cli(cli)?, &groups)?;
+    lock.write(&path)?;
+    eprintln!("note: pinned the entries in {}", path.display());
+    // Read back so this run prints exactly what later runs will.
+    Ok(lock.groups())
+}
+
+fn generate(cli: &Cli, github: &Github) -> Result<ExitCode> {
+    if cli.apply {
+        cli.check_bevy_versions()?;
+    }
+    let started = Instant::now();
+    let mut groups = locked_groups(cli, github)?;
+    let elapsed = started.elapsed();
+
+    for group in &groups {
+        msrv::check(
+            &group.source,
+            &cli.cargo_toml,
+            cli.deny.contains(&Deny::Msrv),
+            github,
+        )?;
+    }
+
+    // A curated crate list says nothing about what the ref has.
+    if cli.crates.is_none() && !cli.root_only {
+        missing_crates::check(
+            &groups,
+            &cli.cargo_toml,
+            cli.skip_root,
+            cli.deny.contains(&Deny::MissingCrates),
+            cli.verbose,
+        )?;
+    }
+
+    if cli.check_circular {
+        let mut crates = Vec::new();
+        for group in &groups {
+            for entry in &group.entries {
+                crates.push(fetch_crate_metadata(&group.source, &entry.subpath, github)?);
+            }
+        }
+        for cycle in graph::find_cycles(&graph::build_dep_graph(&crates)) {
+            warning!(
+                "patched crates depend on each other in a cycle: {}",
+                cycle.join(" -> ")
+            );
+        }
+    }
+
+    if cli.check_deprecations {
+        for message in deprecations::DeprecationChecker::default().check(&groups) {
+            warning!("{message}");
+        }
+    }
+
+    // Split branches give several groups of one repository, each is only looked up once, and
+    // only when the note could be printed.
+    let mut git_repos: Vec<&str> = groups
+        .iter()
+        .filter_map(|group| match &group.source {
+            PatchSource::Git { repo, .. } => Some(repo.as_str()),
+            PatchSource::Path { .. } => None,
+        })
+        .collect();
+    git_repos.sort();
+    git_repos.dedup();
+    let needs_git_cli = || {
+        matches!(&cli.command, Command::Git { repo, .. } if is_ssh_url(repo))
+            || (!git_repos.is_empty() && cli.git_url_style == GitUrlStyle::Ssh)
+            || git_repos.iter().any(|repo| is_private_repo(repo, github))
+    };
+    if cli.setup_git_cli {
+        let path = cargo_config::enable_git_fetch_with_cli(std::path::Path::new("."))?;
+        eprintln!("note: enabled net.git-fetch-with-cli in {}", path.display());
+    } else if needs_git_cli() {
+        eprintln!(
+            "note: cargo's built-in git can't use your SSH agent or credential helper, \
+             pass --setup-git-cli to set net.git-fetch-with-cli = true"
+        );
+    }
+
+    if cli.git_url_style == GitUrlStyle::Ssh {
+        for group in &mut groups {
+            if let PatchSource::Git { repo, .. } = &mut group.source {
+                *repo = ssh_repo_url(repo);
+            }
+        }
+    }
+
+    if cli.frozen || cli.apply {
+        convert::paths_relative_to_manifest(&mut groups, &cli.cargo_toml)?;
+    }
+    let mut format_options = cli.format_options();
+    if !cli.no_stats {
+        let sources: Vec<String> = groups
+            .iter()
+            .map(|group| {
+                let label = group.source.label();
+                match label.split_once("://") {
+                    Some((_, rest)) => rest.to_owned(),
+                    None => label,
+                }
+            })
+            .collect();
+        format_options.stats = Some(format::Stats {
+            crate_count: groups.iter().map(|group| group.entries.len()).sum(),
+            source: sources.join(", "),
+            // A timing would make every `apply` rewrite the manifest.
+            elapsed: (cli.timings && !cli.apply).then_some(elapsed),
+        });
+    }
+    if cli.format == Format::Markdown
+        || cli.share.is_some()
+        || cli.gha
+        || cli.require_signed.is_some()
+        || cli.provenance.is_some()
+        || cli.template.is_some()
+    {
+        for group in &groups {
+            if let PatchSource::Git { repo, reference } = &group.source
+                && is_github(repo)
+            {
+                let sha = expand_rev(repo, reference.as_str(), github)?;
+                format_options.commits.insert(group.source.clone(), sha);
+            }
+        }
+    }
+
+    if let Some(level) = cli.require_signed {
+        let sources: Vec<&PatchSource> = groups.iter().map(|group| &group.source).collect();
+        format_options.verifications =
+            signed::check(&format_options.commits, &sources, level, github)?;
+    }
+
+    if cli.frozen {
+        format_options.format = Format::Toml;
+        let generated = format_options.formatter().format(&groups)?;
+        frozen::check(&cli.cargo_toml, &generated)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if cli.impact {
+        impact::report(&groups, &cli.cargo_toml, cli.format)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if cli.apply {
+        format_options.format = Format::Toml;
+        let generated = format_options.formatter().format(&groups)?;
+        apply::apply(&cli.cargo_toml, &generated)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let output = match &cli.template {
+        Some(template) => template::render(
+            template,
+            &groups,
+            &format_options.commits,
+            format_options.stats.as_ref(),
+            !cli.no_validate,
+        )?,
+        None => format_options.formatter().format(&groups)?,
+    };
+    println!("{output}");
+    if let Some(path) = &cli.provenance {
+        provenance::Provenance::new(&groups, &format_options.commits)?.write(path)?;
+        eprintln!("note: wrote the provenance to {}", path.display());
+    }
+
+    if cli.share == Some(ShareTarget::Gist) {
+        format_options.format = Format::Toml;
+        let toml = format_options.formatter().format(&groups)?;
+        format_options.format = Format::Markdown;
+        let markdown = format_options.formatter().format(&groups)?;
+        let url = gist::create(&toml, &markdown, cli.public, github)?;
+        eprintln!("note: shared at {url}");
+    }
+    if cli.gha {
+        gha::report(&groups, &format_options.commits)?;
+    }
+    if let Some(path) = &cli.compare_to
+        && compare::report(path, &groups)?
+        && cli.fail_on_change
+    {
+        return Ok(ExitCode::from(compare::CHANGED_EXIT_CODE));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Formatter;
+
+    const REPO: &str = "https://github.com/bevyengine/bevy";
+
+    fn git_line(branch: &str) -> String {
+        let source = PatchSource::Git {
+            repo: REPO.to_owned(),
+            reference: GitReference::Branch(branch.to_owned()),
+        };
+        PatchEntry::discovered("crates", "bevy_ecs").line(&source)
+    }
+
+    // Parses the generated line back and returns its `branch`.
+    fn parsed_branch(line: &str) -> String {
+        let table: toml::Table = toml::from_str(line).unwrap();
+        table["bevy_ecs"]["branch"].as_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn branch_with_slash() {
+        assert_eq!(
+            RepoUrl::parse(REPO)
+                .unwrap()
+                .api_url("crates", "feature/foo")
+                .unwrap(),
+            "https://api.github.com/repos/bevyengine/bevy/contents/crates?ref=feature%2Ffoo"
+        );
+        assert_eq!(encode_path("feature/foo"), "feature/foo");
+        assert_eq!(parsed_branch(&git_line("feature/foo")), "feature/foo");
+    }
+
+    #[test]
+    fn branch_with_hash() {
+        assert_eq!(
+            RepoUrl::parse(REPO)
+                .unwrap()
+                .api_url("crates", "fix#123")
+                .unwrap(),
+            "https://api.github.com/repos/bevyengine/bevy/contents/crates?ref=fix%23123"
+        );
+        assert_eq!(parsed_branch(&git_line("fix#123")), "fix#123");
+    }
+
+    #[test]
+    fn branch_with_plus_and_space() {
+        assert_eq!(
+            RepoUrl::parse(REPO)
+                .unwrap()
+                .api_url("crates", "a+b c")
+                .unwrap(),
+            "https://api.github.com/repos/bevyengine/bevy/contents/crates?ref=a%2Bb%20c"
+        );
+        assert_eq!(encode_path("a+b c/d"), "a%2Bb%20c/d");
+        assert_eq!(parsed_branch(&git_line("a+b c")), "a+b c");
+    }
+
+    #[test]
+    fn branch_with_non_ascii() {
+        assert_eq!(
+            RepoUrl::parse(REPO)
+                .unwrap()
+                .api_url("crates", "größe")
+                .unwrap(),
+            "https://api.github.com/repos/bevyengine/bevy/contents/crates?ref=gr%C3%B6%C3%9Fe"
+        );
+        assert_eq!(parsed_branch(&git_line("größe")), "größe");
+    }
+
+    #[test]
+    fn branch_with_quotes_and_backslashes() {
+        let branch = "say \"hi\"\\now";
+        assert_eq!(
+            git_line(branch),
+            r#"bevy_ecs = { git = "https://github.com/bevyengine/bevy", branch = "say \"hi\"\\now" }"#
+        );
+        assert_eq!(parsed_branch(&git_line(branch)), branch);
+    }
+
+    #[test]
+    fn deterministic_paths_use_forward_slashes() {
+        let mut groups = vec![PatchGroup {
+            source: PatchSource::Path {
+                path: r"..\bevy".to_owned(),
+            },
+            entries: vec![PatchEntry::new("bevy_ecs", r"crates\bevy_ecs")],
+        }];
+        normalize_separators(&mut groups);
+        let output = TomlFormatter::default().format(&groups).unwrap();
+        assert!(output.contains(r#"bevy_ecs = { path = "../bevy/crates/bevy_ecs" }"#));
+    }
+
+    #[test]
+    fn secondary_rate_limit_is_told_apart_from_auth_failures() {
+        let limited = Github::parse_error(
+            reqwest::StatusCode::FORBIDDEN,
+            r#"{"message": "You have exceeded a secondary rate limit. Please wait a few minutes before you try again."}"#,
+        );
+        assert!(GithubError::is_secondary_rate_limit(&limited));
+        let denied = Github::parse_error(
+            reqwest::StatusCode::FORBIDDEN,
+            r#"{"message": "Resource not accessible by integration"}"#,
+        );
+        assert!(!GithubError::is_secondary_rate_limit(&denied));
+    }
+
+    // Answers each request with the first response whose path prefix matches, until dropped.
+    fn mock_github(responses: &'static [(&'static str, u16, &'static str)]) -> String {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let path = request_line.split(' ').nth(1).unwrap_or_default();
+                let (status, body) = responses
+                    .iter()
+                    .find(|(prefix, ..)| path.starts_with(prefix))
+                    .map_or(
+                        (404, r#"{"message": "Not Found"}"#),
+                        |&(_, status, body)| (status, body),
+                    );
+                write!(
+                    stream,
+                    "HTTP/1.1 {status} Mock\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+            }
+        });
+        format!("http://{address}")
+    }
+
+    fn mock_client() -> Github {
+        Github {
+            client: http::client(),
+            token: None,
+            cache: CachePolicy {
+                read: false,
+                write: false,
+            },
+            wait_on_rate_limit: false,
+            max_rate_limit_wait: Duration::ZERO,
+            rate_limit_backoff: Duration::ZERO,
+            timeout: Duration::from_secs(5),
+            api_version: GITHUB_API_VERSION.to_owned(),
+            preflight: false,
+        }
+    }
+
+    #[test]
+    fn revs_are_told_apart() {
+        let sha = "0123456789abcdef0123456789abcdef01234567";
+        assert_eq!(validate_rev(sha).unwrap(), RevKind::FullSha);
+        assert_eq!(validate_rev("abc1234").unwrap(), RevKind::AbbreviatedSha);
+        assert_eq!(validate_rev("refs/tags/v0.15.0").unwrap(), RevKind::Tag);
+        assert_eq!(validate_rev("main").unwrap(), RevKind::BranchLike);
+        assert_eq!(validate_rev("cafe").unwrap(), RevKind::BranchLike);
+        assert!(!is_abbreviated_sha("beef") && !is_abbreviated_sha("dead"));
+        assert!(is_abbreviated_sha("abc1234") && !is_abbreviated_sha(sha));
+        assert!(validate_rev("main..dev").is_err());
+        assert!(validate_rev("HEAD~1").is_err());
+        assert!(validate_rev("").is_err());
+    }
+
+    #[test]
+    fn abbreviated_revs_expand_to_full_shas() {
+        let root = mock_github(&[(
+            "/repos/bevyengine/bevy/commits/abc1234",
+            200,
+            r#"{"sha": "abc1234def5678abc1234def5678abc1234def56"}"#,
+        )]);
+        let sha = expand_rev_at(
+            &format!("{root}/repos/bevyengine/bevy"),
+            "abc1234",
+            &mock_client(),
+        )
+        .unwrap();
+        assert_eq!(sha, "abc1234def5678abc1234def5678abc1234def56");
+    }
+
+    #[test]
+    fn ambiguous_revs_list_the_candidates() {
+        let root = mock_github(&[
+            (
+                "/repos/bevyengine/bevy/commits/abc1",
+                422,
+                r#"{"message": "No commit found for SHA: abc1"}"#,
+            ),
+            (
+                "/search/commits",
+                200,
+                r#"{"items": [
+                    {"sha": "abc1000000000000000000000000000000000000", "commit": {"message": "First"}},
+                    {"sha": "abc1ffffffffffffffffffffffffffffffffffff", "commit": {"message": "Second\n\nbody"}}
+                ]}"#,
+            ),
+        ]);
+        let err = expand_rev_at(
+            &format!("{root}/repos/bevyengine/bevy"),
+            "abc1",
+            &mock_client(),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("matches 2 commits"), "{err}");
+        assert!(err.contains("abc1000000000000000000000000000000000000 First"));
+        assert!(err.contains("abc1ffffffffffffffffffffffffffffffffffff Second"));
+    }
+
+    #[test]
+    fn preflight_is_skipped_through_a_proxy() {
+        let url = "https://github.example.com/api/v3/repos/bevyengine/bevy";
+        assert_eq!(
+            preflight_host(url, false).as_deref(),
+            Some("github.example.com")
+        );
+        assert_eq!(preflight_host(url, true), None);
+    }
+
+    #[test]
+    fn secondary_rate_limits_are_retried_three_times() {
+        let api = mock_github(&[(
+            "/repos/",
+            403,
+            r#"{"message": "You have exceeded a secondary rate limit."}"#,
+        )]);
+        let err = mock_client()
+            .send(&format!("{api}/repos/bevyengine/bevy"))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "GitHub secondary rate limit hit repeatedly. Try using --token for authenticated access."
+        );
+    }
+
+    #[test]
+    fn too_many_requests_fail_without_waiting() {
+        let api = mock_github(&[("/repos/", 429, r#"{"message": "Too many requests"}"#)]);
+        let started = std::time::Instant::now();
+        let err = mock_client()
+            .send(&format!("{api}/repos/bevyengine/bevy"))
+            .unwrap_err();
+        assert!(err.to_string().contains("--wait-on-rate-limit"), "{err:#}");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn forks_are_told_apart_by_name_and_only_the_pick_is_looked_up() {
+        let api = mock_github(&[
+            (
+                "/users/alice/repos",
+                200,
+                r#"[
+                    {"full_name": "alice/engine", "html_url": "https://github.com/alice/engine", "fork": true},
+                    {"full_name": "alice/bevy-game", "html_url": "https://github.com/alice/bevy-game", "fork": false},
+                    {"full_name": "alice/my-bevy", "html_url": "https://github.com/alice/my-bevy", "fork": true}
+                ]"#,
+            ),
+            ("/users/alice", 200, r#"{"type": "User"}"#),
+            (
+                "/repos/alice/my-bevy",
+                200,
+                r#"{"full_name": "alice/my-bevy", "html_url": "https://github.com/alice/my-bevy",
+                    "fork": true, "parent": {"full_name": "bevyengine/bevy", "html_url": ""}}"#,
+            ),
+            (
+                "/users/bob/repos",
+                200,
+                r#"[{"full_name": "bob/bevy_rapier", "html_url": "https://github.com/bob/bevy_rapier", "fork": true}]"#,
+            ),
+            ("/users/bob", 200, r#"{"type": "User"}"#),
+            (
+                "/repos/bob/bevy_rapier",
+                200,
+                r#"{"full_name": "bob/bevy_rapier", "html_url": "https://github.com/bob/bevy_rapier",
+                    "fork": true, "parent": {"full_name": "dimforge/bevy_rapier", "html_url": ""}}"#,
+            ),
+        ]);
+        let github = mock_client();
+        assert_eq!(
+            find_bevy_fork_at(&api, "alice", &github).unwrap(),
+            "https://github.com/alice/my-bevy"
+        );
+        let err = find_bevy_fork_at(&api, "bob", &github).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("bob/bevy_rapier isn't a fork of bevyengine/bevy"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn the_library_renders_like_the_binary() {
+        let section = bevy_patch::PatchBuilder::new()
+            .git_source("https://github.com/bevyengine/bevy")
+            .tag("v0.15.0")
+            .include_only(&["bevy", "bevy_ecs"])
+            .build()
+            .unwrap();
+        let cli = parse_args(["path", "."].map(str::to_owned)).unwrap();
+        let mut format_options = cli.format_options();
+        let groups = std::slice::from_ref(section.group());
+        assert_eq!(
+            format_options.formatter().format(groups).unwrap(),
+            section.to_string()
+        );
+        format_options.format = Format::Json;
+        let json: serde_json::Value =
+            serde_json::from_str(&format_options.formatter().format(groups).unwrap()).unwrap();
+        assert_eq!(json, serde_json::to_value(&section).unwrap());
+    }
+
+    #[test]
+    fn the_last_of_expand_rev_and_no_expand_rev_wins() {
+        let no_expand_rev = |args: &[&str]| {
+            let args = ["git", "--rev", "abc1234"].iter().chain(args);
+            match parse_args(args.map(|arg| arg.to_string())).unwrap().command {
+                Command::Git { no_expand_rev, .. } => no_expand_rev,
+                _ => unreachable!(),
+            }
+        };
+        assert!(!no_expand_rev(&[]));
+        assert!(!no_expand_rev(&["--expand-rev"]));
+        assert!(no_expand_rev(&["--expand-rev", "--no-expand-rev"]));
+        assert!(!no_expand_rev(&["--no-expand-rev", "--expand-rev"]));
+    }
+
+    // A project in `sub/` next to a bevy checkout, under a relative root so the commands get
+    // `--cargo-toml <root>/sub/Cargo.toml` the way users pass it.
+    fn project(name: &str) -> PathBuf {
+        let root = PathBuf::from(format!(
+            "target/bevy-patch-tests/{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        for (dir, package) in [
+            ("sub", "game"),
+            ("bevy", "bevy"),
+            ("bevy/crates/bevy_ecs", "bevy_ecs"),
+        ] {
+            std::fs::create_dir_all(root.join(dir).join("src")).unwrap();
+            std::fs::write(root.join(dir).join("src/lib.rs"), "").unwrap();
+            std::fs::write(
+                root.join(dir).join("Cargo.toml"),
+                format!(
+                    "[package]\nname = \"{package}\"\nversion = \"0.1.0\"\nedition = \"2021\"\n"
+                ),
+            )
+            .unwrap();
+        }
+        root
+    }
+
+    fn run_args(args: &[&str]) -> Result<ExitCode> {
+        run(&parse_args(args.iter().map(|arg| (*arg).to_owned()))?)
+    }
+
+    #[test]
+    fn subcommands_use_the_manifest_given_with_cargo_toml() {
+        let root = project("cargo-toml");
+        let cargo_toml = root.join("sub/Cargo.toml");
+        let manifest = cargo_toml.to_str().unwrap();
+        let bevy = root.join("bevy");
+        let bevy = bevy.to_str().unwrap();
+
+        run_args(&["--cargo-toml", manifest, "apply", "path", bevy]).unwrap();
+        let applied = std::fs::read_to_string(&cargo_toml).unwrap();
+        assert!(
+            applied.contains("bevy = { path = \"../bevy\" }\n"),
+            "{applied}"
+        );
+        assert!(applied.contains("bevy_ecs = { path = \"../bevy/crates/bevy_ecs\" }\n"));
+
+        // The drift check compares what apply wrote, not paths relative to the working directory.
+        let frozen = run_args(&["--cargo-toml", manifest, "--frozen", "path", bevy]).unwrap();
+        assert_eq!(frozen, ExitCode::SUCCESS);
+        run_args(&["--cargo-toml", manifest, "check-effective"]).unwrap();
+        run_args(&["--cargo-toml", manifest, "explain", "bevy_ecs"]).unwrap();
+
+        run_args(&["--cargo-toml", manifest, "init", "--defaults"]).unwrap();
+        assert!(root.join("sub/bevy-patch.toml").is_file());
+        assert_eq!(
+            lock::lock_path(&cargo_toml),
+            root.join("sub/bevy-patch.lock")
+        );
+
+        run_args(&["--cargo-toml", manifest, "remove"]).unwrap();
+        let removed = std::fs::read_to_string(&cargo_toml).unwrap();
+        assert!(!removed.contains(apply::BEGIN) && !removed.contains("bevy_ecs"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn extra_crates_are_filtered_too() {
+        let root = project("extra-filtered");
+        for (dir, manifest) in [
+            ("bevy/tools/bevy_internal", "name = \"bevy_internal\"\n"),
+            ("bevy/tools/helper", "name = \"helper\"\npublish = false\n"),
+            ("bevy/tools/kept", "name = \"kept\"\n"),
+        ] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+            std::fs::write(
+                root.join(dir).join("Cargo.toml"),
+                format!("[package]\n{manifest}version = \"0.1.0\"\n"),
+            )
+            .unwrap();
+        }
+        let bevy = root.join("bevy").to_str().unwrap().to_owned();
+        let cli = parse_args(
+            [
+                "--skip-bevy-internal",
+                "--skip-unpublished",
+                "--extra-crate",
+                "tools/bevy_internal",
+                "--extra-crate",
+                "tools/helper",
+                "--extra-crate",
+                "tools/kept",
+                "path",
+                &bevy,
+            ]
+            .map(str::to_owned),
+        )
+        .unwrap();
+        let source = PatchSource::Path { path: bevy };
+        let entries = build_entries(&source, false, Vec::new(), &cli, &mock_client()).unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["kept"]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn apply_refuses_workspace_members() {
+        let root = project("apply-member");
+        std::fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"sub\"]\nexclude = [\"bevy\"]\n",
+        )
+        .unwrap();
+        let member = root.join("sub/Cargo.toml");
+        let bevy = root.join("bevy");
+
+        let err = run_args(&[
+            "--cargo-toml",
+            member.to_str().unwrap(),
+            "apply",
+            "path",
+            bevy.to_str().unwrap(),
+        ])
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains("cargo ignores [patch] outside the workspace root"),
+            "{err}"
+        );
+        assert!(
+            !std::fs::read_to_string(&member)
+                .unwrap()
+                .contains(apply::BEGIN)
+        );
+
+        let workspace_root = root.join("Cargo.toml");
+        run_args(&[
+            "--cargo-toml",
+            workspace_root.to_str().unwrap(),
+            "apply",
+            "path",
+            bevy.to_str().unwrap(),
+        ])
+        .unwrap();
+        assert!(
+            std::fs::read_to_string(&workspace_root)
+                .unwrap()
+                .contains(apply::BEGIN)
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn convert_writes_paths_relative_to_cargo_toml() {
+        let root = project("convert");
+        let cargo_toml = root.join("sub/Cargo.toml");
+        let manifest = cargo_toml.to_str().unwrap();
+        let bevy = root.join("bevy");
+        let bevy = bevy.to_str().unwrap();
+        for args in [
+            &["init", "--quiet"][..],
+            &[
+                "remote",
+                "add",
+                "origin",
+                "https://github.com/bevyengine/bevy",
+            ],
+            &["add", "."],
+            &[
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@example.com",
+                "commit",
+                "--quiet",
+                "-m",
+                "bevy",
+            ],
+        ] {
+            let status = std::process::Command::new("git")
+                .args(args)
+                .current_dir(bevy)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+
+        run_args(&["--cargo-toml", manifest, "apply", "path", bevy]).unwrap();
+        let applied = std::fs::read_to_string(&cargo_toml).unwrap();
+        run_args(&["--cargo-toml", manifest, "convert", "--to", "git"]).unwrap();
+        let converted = std::fs::read_to_string(&cargo_toml).unwrap();
+        assert!(converted.contains("git = \"https://github.com/bevyengine/bevy\", rev = \""));
+        run_args(&[
+            "--cargo-toml",
+            manifest,
+            "convert",
+            "--to",
+            "path",
+            "--path",
+            bevy,
+        ])
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&cargo_toml).unwrap(), applied);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
 }
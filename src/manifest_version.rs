@@ -0,0 +1,221 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use semver::{Version, VersionReq};
+
+use crate::workspace::{WorkspaceMembers, read, workspace_root};
+
+// The `bevy` requirement in a dependency table, also when it's renamed with `package`.
+fn requirement_in(table: Option<&toml::Value>) -> Option<toml::Value> {
+    table?
+        .as_table()?
+        .iter()
+        .find(|(key, dependency)| {
+            dependency
+                .get("package")
+                .and_then(|package| package.as_str())
+                .unwrap_or(key)
+                == "bevy"
+        })
+        .map(|(_, dependency)| dependency.clone())
+}
+
+// `bevy = "0.15"` from `[dependencies]`, or from `[workspace.dependencies]` for
+// `bevy = { workspace = true }` and workspace roots. A workspace root without either
+// requires what all of its members do.
+pub fn bevy_requirement(manifest_path: &Path) -> Result<VersionReq> {
+    let manifest = read(manifest_path)?;
+    if manifest.contains_key("workspace")
+        && requirement_in(manifest.get("dependencies")).is_none()
+        && requirement_in(
+            manifest
+                .get("workspace")
+                .and_then(|workspace| workspace.get("dependencies")),
+        )
+        .is_none()
+    {
+        return members_requirement(manifest_path);
+    }
+    member_requirement(manifest_path)
+}
+
+fn members_requirement(root: &Path) -> Result<VersionReq> {
+    let mut comparators = Vec::new();
+    for member in WorkspaceMembers::from_cargo_toml(root)? {
+        if member == root || requirement_in(read(&member)?.get("dependencies")).is_none() {
+            continue;
+        }
+        comparators.extend(member_requirement(&member)?.comparators);
+    }
+    if comparators.is_empty() {
+        anyhow::bail!(
+            "No member of the workspace at {} depends on bevy",
+            root.display()
+        );
+    }
+    comparators.dedup();
+    Ok(VersionReq { comparators })
+}
+
+fn member_requirement(manifest_path: &Path) -> Result<VersionReq> {
+    let manifest = read(manifest_path)?;
+    let dependency = requirement_in(manifest.get("dependencies"));
+    let inherited = dependency
+        .as_ref()
+        .and_then(|dependency| dependency.get("workspace"))
+        .and_then(|workspace| workspace.as_bool())
+        == Some(true);
+
+    let dependency = match dependency {
+        Some(dependency) if !inherited => dependency,
+        _ => {
+            let root = if manifest.contains_key("workspace") {
+                manifest
+            } else {
+                match workspace_root(manifest_path) {
+                    Some(root) => read(&root)?,
+                    None if inherited => {
+                        anyhow::bail!("bevy is inherited from a workspace that wasn't found")
+                    }
+                    None => toml::Table::new(),
+                }
+            };
+            requirement_in(root.get("workspace").and_then(|w| w.get("dependencies"))).ok_or_else(
+                || {
+                    anyhow::anyhow!(
+                        "{} doesn't depend on bevy in [dependencies] or [workspace.dependencies]",
+                        manifest_path.display()
+                    )
+                },
+            )?
+        }
+    };
+
+    let requirement = match &dependency {
+        toml::Value::String(requirement) => Some(requirement.as_str()),
+        dependency => dependency
+            .get("version")
+            .and_then(|version| version.as_str()),
+    }
+    .ok_or_else(|| anyhow::anyhow!("The bevy dependency has no version requirement"))?;
+    VersionReq::parse(requirement)
+        .with_context(|| format!("bevy = {requirement:?} isn't a semver requirement"))
+}
+
+// The newest `vX.Y.Z` tag matching `requirement`, or the tags closest to it in the error.
+pub fn resolve_tag(requirement: &VersionReq, tags: &[String], repo: &str) -> Result<String> {
+    let mut versions: Vec<(Version, &String)> = tags
+        .iter()
+        .filter_map(|tag| Some((Version::parse(tag.strip_prefix('v')?).ok()?, tag)))
+        .collect();
+    versions.sort();
+
+    let nearest = |versions: &[(Version, &String)]| {
+        // Around the lowest version the requirement names, `*` has none and lists the newest.
+        let lowest = requirement.comparators.first().map(|comparator| {
+            Version::new(
+                comparator.major,
+                comparator.minor.unwrap_or(0),
+                comparator.patch.unwrap_or(0),
+            )
+        });
+        let at = lowest.map_or(versions.len(), |lowest| {
+            versions.partition_point(|(version, _)| *version < lowest)
+        });
+        let nearby = &versions[at.saturating_sub(3)..(at + 3).min(versions.len())];
+        nearby
+            .iter()
+            .map(|(_, tag)| tag.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    if requirement.comparators.is_empty() {
+        anyhow::bail!(
+            "bevy = \"*\" doesn't pin a release, require a version such as one of {}",
+            nearest(&versions)
+        );
+    }
+    versions
+        .iter()
+        .rev()
+        .find(|(version, _)| requirement.matches(version))
+        .map(|(_, tag)| (*tag).clone())
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "No tag of {repo} matches bevy = \"{requirement}\", the nearest are {}",
+                nearest(&versions)
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags() -> Vec<String> {
+        [
+            "v0.14.0",
+            "v0.14.2",
+            "v0.15.0-rc.1",
+            "v0.15.0",
+            "v0.15.1",
+            "latest",
+        ]
+        .iter()
+        .map(|tag| (*tag).to_owned())
+        .collect()
+    }
+
+    fn resolve(requirement: &str) -> Result<String> {
+        resolve_tag(&VersionReq::parse(requirement).unwrap(), &tags(), "bevy")
+    }
+
+    #[test]
+    fn requirements() {
+        assert_eq!(resolve("0.15").unwrap(), "v0.15.1");
+        assert_eq!(resolve("~0.14.1").unwrap(), "v0.14.2");
+        assert_eq!(resolve("=0.15.0").unwrap(), "v0.15.0");
+        assert_eq!(resolve("=0.15.0-rc.1").unwrap(), "v0.15.0-rc.1");
+    }
+
+    #[test]
+    fn virtual_workspaces_require_what_their_members_do() {
+        let root = Path::new("target/bevy-patch-tests")
+            .join(format!("virtual-workspace-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for (dir, manifest) in [
+            (
+                "",
+                "[workspace]\nmembers = [\"crates/*\", \"game\"]\nexclude = [\"crates/old\"]\n",
+            ),
+            ("crates/render", "[dependencies]\nbevy = \"0.15\"\n"),
+            ("crates/util", "[dependencies]\nserde = \"1\"\n"),
+            ("crates/old", "[dependencies]\nbevy = \"0.13\"\n"),
+            ("game", "[dependencies]\nbevy = { version = \"0.15.1\" }\n"),
+        ] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+            std::fs::write(root.join(dir).join("Cargo.toml"), manifest).unwrap();
+        }
+        let cargo_toml = root.join("Cargo.toml");
+
+        let members = WorkspaceMembers::from_cargo_toml(&cargo_toml).unwrap();
+        assert_eq!(
+            members,
+            ["crates/render", "crates/util", "game"].map(|dir| root.join(dir).join("Cargo.toml"))
+        );
+        let requirement = bevy_requirement(&cargo_toml).unwrap();
+        assert_eq!(requirement.to_string(), "^0.15, ^0.15.1");
+        assert_eq!(
+            resolve_tag(&requirement, &tags(), "bevy").unwrap(),
+            "v0.15.1"
+        );
+    }
+
+    #[test]
+    fn unmatched_requirements_list_candidates() {
+        let err = resolve("0.16").unwrap_err().to_string();
+        assert!(err.contains("v0.15.1"), "{err}");
+        assert!(resolve("*").is_err());
+    }
+}
@@ -0,0 +1,74 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::PatchGroup;
+use crate::explain::{cargo_metadata, str_field};
+
+// Every bevy crate in the project's resolved graph, transitive ones included, so crates the
+// patched bevy pulls in are covered once the project builds against it.
+fn project_bevy_crates(cargo_toml: &Path) -> Result<BTreeSet<String>> {
+    let metadata = cargo_metadata(cargo_toml)?;
+    Ok(metadata["packages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|package| str_field(package, "name"))
+        .filter(|name| *name == "bevy" || name.starts_with("bevy_"))
+        .map(str::to_owned)
+        .collect())
+}
+
+// Crates the project needs that the target ref lacks keep resolving from crates.io, which
+// cargo reports as a cryptic "failed to select a version".
+pub fn check(
+    groups: &[PatchGroup],
+    cargo_toml: &Path,
+    skip_root: bool,
+    deny: bool,
+    verbose: bool,
+) -> Result<()> {
+    if !cargo_toml.is_file() {
+        return Ok(());
+    }
+    let needed = match project_bevy_crates(cargo_toml) {
+        Ok(needed) => needed,
+        Err(err) => {
+            if verbose {
+                eprintln!("debug: skipped the missing crates check: {err:#}");
+            }
+            return Ok(());
+        }
+    };
+    let patched: BTreeSet<&str> = groups
+        .iter()
+        .flat_map(|group| &group.entries)
+        .map(|entry| entry.name.as_str())
+        .collect();
+    let missing: Vec<&str> = needed
+        .iter()
+        .map(String::as_str)
+        .filter(|name| !patched.contains(name) && (!skip_root || *name != "bevy"))
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let target = groups
+        .first()
+        .map_or_else(String::new, |group| group.source.label());
+    if deny {
+        anyhow::bail!(
+            "{target} has no {}, which the project uses",
+            missing.join(", ")
+        );
+    }
+    for name in missing {
+        warning!(
+            "{target} has no {name}, the project keeps getting it from crates.io \
+             and will likely fail to select a version, pass --deny missing-crates to make this an error"
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use anyhow::Result;
+use semver::Version;
+use serde::Deserialize;
+
+use crate::{Github, PatchSource};
+
+#[derive(Deserialize)]
+struct RootManifest {
+    package: Option<RustVersionTable>,
+    workspace: Option<RootWorkspace>,
+}
+
+#[derive(Deserialize)]
+struct RootWorkspace {
+    package: Option<RustVersionTable>,
+}
+
+#[derive(Deserialize)]
+struct RustVersionTable {
+    // Either a plain string or `{ workspace = true }`.
+    #[serde(rename = "rust-version")]
+    rust_version: Option<toml::Value>,
+}
+
+#[derive(Deserialize)]
+struct ToolchainFile {
+    toolchain: ToolchainTable,
+}
+
+#[derive(Deserialize)]
+struct ToolchainTable {
+    channel: Option<String>,
+}
+
+// `1.85` -> 1.85.0, `1.86.0-nightly` -> 1.86.0, channels like `stable` aren't versions.
+fn parse_rust_version(version: &str) -> Option<Version> {
+    let version = version.trim();
+    let version = version.split(['-', ' ']).next()?;
+    let mut parts = version.split('.').map(|part| part.parse::<u64>().ok());
+    let major = parts.next()??;
+    let minor = parts.next().unwrap_or(Some(0))?;
+    let patch = parts.next().unwrap_or(Some(0))?;
+    Some(Version::new(major, minor, patch))
+}
+
+fn target_rust_version(source: &PatchSource, github: &Github) -> Result<Option<Version>> {
+    let manifest: RootManifest = toml::from_str(&crate::read_manifest(source, "", github)?)?;
+    let workspace = manifest
+        .workspace
+        .and_then(|workspace| workspace.package)
+        .and_then(|package| package.rust_version);
+    let rust_version = match manifest.package.and_then(|package| package.rust_version) {
+        Some(toml::Value::String(version)) => Some(version),
+        _ => workspace.and_then(|version| version.as_str().map(str::to_owned)),
+    };
+    Ok(rust_version.as_deref().and_then(parse_rust_version))
+}
+
+// A pinned rust-toolchain file wins, otherwise whatever `rustc` runs in the project.
+fn toolchain_version(project_dir: &Path) -> Option<Version> {
+    let pinned =
+        if let Ok(content) = std::fs::read_to_string(project_dir.join("rust-toolchain.toml")) {
+            toml::from_str::<ToolchainFile>(&content)
+                .ok()
+                .and_then(|file| file.toolchain.channel)
+        } else {
+            std::fs::read_to_string(project_dir.join("rust-toolchain")).ok()
+        };
+    if let Some(version) = pinned.as_deref().and_then(parse_rust_version) {
+        return Some(version);
+    }
+
+    let output = std::process::Command::new("rustc")
+        .arg("--version")
+        .current_dir(project_dir)
+        .output()
+        .ok()?;
+    // `rustc 1.85.0 (4d91de4e4 2025-02-17)`
+    String::from_utf8(output.stdout)
+        .ok()?
+        .strip_prefix("rustc ")
+        .and_then(parse_rust_version)
+}
+
+// Unreadable manifests, e.g. when offline, and sources without a rust-version are skipped.
+pub fn check(source: &PatchSource, cargo_toml: &Path, deny: bool, github: &Github) -> Result<()> {
+    let Ok(Some(required)) = target_rust_version(source, github) else {
+        return Ok(());
+    };
+    let project_dir = match cargo_toml.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Some(toolchain) = toolchain_version(project_dir) else {
+        return Ok(());
+    };
+
+    if toolchain < required {
+        let message = format!(
+            "{} needs rust {required}, your toolchain is {toolchain}",
+            source.label()
+        );
+        if deny {
+            anyhow::bail!("{message}");
+        }
+        warning!("{message}, pass --deny msrv to make this an error");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use std::path::PathBuf;
+
+// Where curl and git look, `_netrc` is the traditional Windows name.
+fn netrc_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(home) = std::env::var_os("HOME") {
+        paths.push(PathBuf::from(home).join(".netrc"));
+    }
+    if cfg!(windows)
+        && let Some(profile) = std::env::var_os("USERPROFILE")
+    {
+        let profile = PathBuf::from(profile);
+        paths.push(profile.join(".netrc"));
+        paths.push(profile.join("_netrc"));
+    }
+    paths
+}
+
+fn find_machine(content: &str, host: &str) -> Option<(String, String)> {
+    let mut tokens = content.split_whitespace();
+    let mut in_machine = false;
+    let (mut login, mut password) = (None, None);
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" | "default" => {
+                if in_machine {
+                    break;
+                }
+                in_machine = token == "default" || tokens.next() == Some(host);
+            }
+            "login" => login = tokens.next().filter(|_| in_machine).or(login),
+            "password" => password = tokens.next().filter(|_| in_machine).or(password),
+            _ => {}
+        }
+    }
+
+    Some((login?.to_owned(), password?.to_owned()))
+}
+
+// The `login` and `password` of `machine <host>` in the user's netrc file.
+pub fn parse_netrc(host: &str) -> Option<(String, String)> {
+    netrc_paths().into_iter().find_map(|path| {
+        let content = std::fs::read_to_string(path).ok()?;
+        find_machine(&content, host)
+    })
+}
@@ -0,0 +1,148 @@
+use std::path::Path;
+use std::process::ExitCode;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::format::Format;
+use crate::{Github, api_repo_url, encode_path};
+
+// Exit codes CI can tell apart, everything else failing is reported as an error.
+pub const BEHIND_EXIT_CODE: u8 = 1;
+pub const ERROR_EXIT_CODE: u8 = 2;
+
+#[derive(Deserialize)]
+pub struct BranchCommit {
+    pub sha: String,
+    commit: BranchCommitDetails,
+}
+
+#[derive(Deserialize)]
+struct BranchCommitDetails {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct GithubCompare {
+    ahead_by: u64,
+}
+
+#[derive(Serialize)]
+struct PinStatus {
+    repo: String,
+    branch: String,
+    pinned: String,
+    head: String,
+    ahead_by: u64,
+    latest_summary: String,
+}
+
+// Every distinct `git` + `rev` pair in [patch.crates-io].
+pub fn read_pins(manifest_path: &Path) -> Result<Vec<(String, String)>> {
+    let content = std::fs::read_to_string(manifest_path)
+        .with_context(|| format!("Failed to read {}", manifest_path.display()))?;
+    let manifest: toml::Table = toml::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", manifest_path.display()))?;
+
+    let mut pins = Vec::new();
+    let patches = manifest
+        .get("patch")
+        .and_then(|patch| patch.get("crates-io"))
+        .and_then(|patches| patches.as_table());
+    for patch in patches.into_iter().flat_map(|patches| patches.values()) {
+        let git = patch.get("git").and_then(|git| git.as_str());
+        let rev = patch.get("rev").and_then(|rev| rev.as_str());
+        if let (Some(git), Some(rev)) = (git, rev) {
+            let pin = (git.to_owned(), rev.to_owned());
+            if !pins.contains(&pin) {
+                pins.push(pin);
+            }
+        }
+    }
+
+    if pins.is_empty() {
+        anyhow::bail!(
+            "{} has no git patches pinned with `rev`",
+            manifest_path.display()
+        );
+    }
+    Ok(pins)
+}
+
+pub fn branch_head(repo: &str, branch: &str, github: &Github) -> Result<BranchCommit> {
+    github
+        .get(&format!(
+            "{}/commits/{}",
+            api_repo_url(repo)?,
+            encode_path(branch)
+        ))
+        .with_context(|| format!("Failed to look up {branch} on {repo}"))
+}
+
+fn pin_status(repo: &str, pinned: &str, branch: &str, github: &Github) -> Result<PinStatus> {
+    let api_url = api_repo_url(repo)?;
+    let head = branch_head(repo, branch, github)?;
+
+    let ahead_by = if head.sha.starts_with(pinned) {
+        0
+    } else {
+        github
+            .get::<GithubCompare>(&format!("{api_url}/compare/{pinned}...{}", head.sha))
+            .with_context(|| format!("Failed to compare {pinned} with {branch} on {repo}"))?
+            .ahead_by
+    };
+
+    Ok(PinStatus {
+        repo: repo.to_owned(),
+        branch: branch.to_owned(),
+        pinned: pinned.to_owned(),
+        head: head.sha,
+        ahead_by,
+        latest_summary: head
+            .commit
+            .message
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_owned(),
+    })
+}
+
+pub fn run(
+    manifest_path: &Path,
+    branch: &str,
+    format: Format,
+    github: &Github,
+) -> Result<ExitCode> {
+    let statuses = read_pins(manifest_path)?
+        .iter()
+        .map(|(repo, pinned)| pin_status(repo, pinned, branch, github))
+        .collect::<Result<Vec<_>>>()?;
+    let outdated = statuses.iter().any(|status| status.ahead_by > 0);
+
+    if format == Format::Json {
+        let output = serde_json::json!({ "outdated": outdated, "pins": statuses });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+    } else {
+        for status in &statuses {
+            if status.ahead_by == 0 {
+                println!("{} is up to date with {}", status.repo, status.branch);
+            } else {
+                println!(
+                    "{} is {} commits behind {}, latest is {}: {}",
+                    status.repo,
+                    status.ahead_by,
+                    status.branch,
+                    &status.head[..7],
+                    status.latest_summary
+                );
+            }
+        }
+    }
+
+    Ok(if outdated {
+        ExitCode::from(BEHIND_EXIT_CODE)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
@@ -0,0 +1,138 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Subcommand;
+
+#[derive(Subcommand)]
+pub enum ProfileCommand {
+    /// Save a command line under a name, e.g. `profile save my-fork git --repo me/bevy --branch feature`
+    Save {
+        name: String,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        args: Vec<String>,
+    },
+    /// Run a saved command line, arguments after `--` are appended to it
+    Run {
+        name: String,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Print the saved profiles
+    List,
+    /// Remove a saved profile
+    Delete { name: String },
+}
+
+// Flags tied to one project or with side effects, they're given again on each run instead.
+const UNSAVED_FLAGS: &[&str] = &["--setup-git-cli", "--refresh", "--yes", "-y"];
+const UNSAVED_OPTIONS: &[&str] = &["--cargo-toml", "--manifest-path"];
+
+fn config_path() -> Result<PathBuf> {
+    let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".config"))
+            .ok_or_else(|| anyhow::anyhow!("Can't find the config directory, set HOME"))?,
+    };
+    Ok(dir.join("bevy-patch").join("config.toml"))
+}
+
+fn read_config() -> Result<(PathBuf, toml_edit::DocumentMut)> {
+    let path = config_path()?;
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let config = content
+        .parse()
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok((path, config))
+}
+
+fn write_config(path: &Path, config: &toml_edit::DocumentMut) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    crate::atomic::write(path, config.to_string())
+}
+
+fn saved_args(args: &[String]) -> Vec<String> {
+    let mut saved = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if UNSAVED_OPTIONS.contains(&arg.as_str()) {
+            args.next();
+        } else if !UNSAVED_FLAGS.contains(&arg.as_str())
+            && !UNSAVED_OPTIONS
+                .iter()
+                .any(|option| arg.starts_with(&format!("{option}=")))
+        {
+            saved.push(arg.clone());
+        }
+    }
+    saved
+}
+
+fn profile_args(config: &toml_edit::DocumentMut, name: &str) -> Option<Vec<String>> {
+    let args = config.get("profiles")?.get(name)?.get("args")?.as_array()?;
+    Some(
+        args.iter()
+            .filter_map(|arg| arg.as_str().map(str::to_owned))
+            .collect(),
+    )
+}
+
+// Returns the command line to run for `profile run`, the other commands are handled here.
+pub fn run(command: &ProfileCommand) -> Result<Option<Vec<String>>> {
+    let _lock = crate::atomic::lock(&config_path()?)?;
+    let (path, mut config) = read_config()?;
+    match command {
+        ProfileCommand::Save { name, args } => {
+            let args = saved_args(args);
+            let profiles = config
+                .entry("profiles")
+                .or_insert_with(toml_edit::table)
+                .as_table_mut()
+                .ok_or_else(|| anyhow::anyhow!("`profiles` in {} isn't a table", path.display()))?;
+            profiles.set_implicit(true);
+            let mut profile = toml_edit::Table::new();
+            profile.insert(
+                "args",
+                toml_edit::value(args.iter().collect::<toml_edit::Array>()),
+            );
+            profiles.insert(name, toml_edit::Item::Table(profile));
+            write_config(&path, &config)?;
+            eprintln!("note: saved {name} as `{}`", args.join(" "));
+        }
+        ProfileCommand::Run { name, args } => {
+            let mut saved = profile_args(&config, name)
+                .ok_or_else(|| anyhow::anyhow!("No profile named {name} in {}", path.display()))?;
+            saved.extend(args.iter().cloned());
+            return Ok(Some(saved));
+        }
+        ProfileCommand::List => {
+            let names = config
+                .get("profiles")
+                .and_then(|profiles| profiles.as_table())
+                .map(|profiles| profiles.iter().map(|(name, _)| name.to_owned()).collect())
+                .unwrap_or_else(Vec::new);
+            for name in names {
+                let args = profile_args(&config, &name).unwrap_or_default();
+                println!("{name}: {}", args.join(" "));
+            }
+        }
+        ProfileCommand::Delete { name } => {
+            let removed = config
+                .get_mut("profiles")
+                .and_then(|profiles| profiles.as_table_mut())
+                .and_then(|profiles| profiles.remove(name));
+            if removed.is_none() {
+                anyhow::bail!("No profile named {name} in {}", path.display());
+            }
+            write_config(&path, &config)?;
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,131 @@
+use std::io::{BufRead, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Result;
+
+// Exit code used when a prompt was needed but couldn't be shown.
+pub const REFUSED_EXIT_CODE: u8 = 3;
+
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+static INTERACTIVE: AtomicBool = AtomicBool::new(true);
+
+pub fn set_assume_yes(yes: bool) {
+    ASSUME_YES.store(yes, Ordering::Relaxed);
+}
+
+pub fn set_interactive(interactive: bool) {
+    INTERACTIVE.store(interactive, Ordering::Relaxed);
+}
+
+#[derive(Debug)]
+pub struct PromptRefused {
+    question: String,
+    hint: String,
+    // `--yes` was passed but the question is a choice it can't make.
+    assume_yes: bool,
+}
+
+impl std::error::Error for PromptRefused {}
+
+impl std::fmt::Display for PromptRefused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = if self.assume_yes {
+            "--yes only answers confirmations, not choices"
+        } else {
+            "can't prompt, stdin isn't a terminal or this is CI"
+        };
+        write!(f, "{} ({reason})\n{}", self.question, self.hint)
+    }
+}
+
+fn ask(question: &str, hint: &str) -> Result<String> {
+    if !INTERACTIVE.load(Ordering::Relaxed) || !std::io::stdin().is_terminal() {
+        return Err(PromptRefused {
+            question: question.to_owned(),
+            hint: hint.to_owned(),
+            assume_yes: false,
+        }
+        .into());
+    }
+
+    eprint!("{question} ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(answer.trim().to_owned())
+}
+
+// Yes/no question, answered with yes by `--yes`.
+pub fn confirm(question: &str) -> Result<bool> {
+    if ASSUME_YES.load(Ordering::Relaxed) {
+        return Ok(true);
+    }
+
+    let answer = ask(&format!("{question} [y/N]"), "Pass --yes to confirm")?;
+    Ok(matches!(answer.to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+// Free text answer, empty answers and `--yes` take the default.
+pub fn input(question: &str, default: &str) -> Result<String> {
+    if ASSUME_YES.load(Ordering::Relaxed) {
+        return Ok(default.to_owned());
+    }
+
+    let answer = ask(
+        &format!("{question} [{default}]"),
+        "Pass --yes to take the defaults",
+    )?;
+    Ok(if answer.is_empty() {
+        default.to_owned()
+    } else {
+        answer
+    })
+}
+
+// Picks one of `options`, `--yes` can't make this choice so `hint` explains the alternative.
+pub fn choose(question: &str, options: &[String], hint: &str) -> Result<usize> {
+    let mut listing = String::from(question);
+    for (i, option) in options.iter().enumerate() {
+        listing.push_str(&format!("\n  {}) {option}", i + 1));
+    }
+
+    if ASSUME_YES.load(Ordering::Relaxed) {
+        return Err(PromptRefused {
+            question: listing,
+            hint: hint.to_owned(),
+            assume_yes: true,
+        }
+        .into());
+    }
+
+    loop {
+        let answer = ask(&format!("{listing}\n>"), hint)?;
+        match answer.parse::<usize>() {
+            Ok(choice) if (1..=options.len()).contains(&choice) => return Ok(choice - 1),
+            _ => eprintln!("Enter a number between 1 and {}", options.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refusals_under_yes_name_it() {
+        let refused = |assume_yes| {
+            PromptRefused {
+                question: "alice has multiple forks of bevyengine/bevy:".to_owned(),
+                hint: "Pick one with --repo".to_owned(),
+                assume_yes,
+            }
+            .to_string()
+        };
+        assert_eq!(
+            refused(true),
+            "alice has multiple forks of bevyengine/bevy: (--yes only answers confirmations, \
+             not choices)\nPick one with --repo"
+        );
+        assert!(refused(false).contains("stdin isn't a terminal"));
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{PatchGroup, PatchSource, git_output, is_bare_git_repo};
+
+// Bumped whenever a field changes meaning or goes away, new optional fields keep the version.
+const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+pub struct Provenance {
+    schema_version: u32,
+    generator: String,
+    // RFC 3339, UTC.
+    retrieved_at: String,
+    sources: Vec<Source>,
+}
+
+#[derive(Serialize)]
+struct Source {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repo: Option<String>,
+    #[serde(rename = "ref", skip_serializing_if = "Option::is_none")]
+    reference: Option<Reference>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    // Null when it couldn't be resolved, e.g. a non-GitHub repository or a path outside git.
+    commit: Option<String>,
+    crates: Vec<Crate>,
+}
+
+#[derive(Serialize)]
+struct Reference {
+    // `branch`, `tag` or `rev`.
+    kind: &'static str,
+    name: String,
+}
+
+#[derive(Serialize)]
+struct Crate {
+    name: String,
+    subpath: String,
+    version: Option<String>,
+    // Over the crate's files, only for local checkouts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+}
+
+impl Provenance {
+    // `commits` maps git sources to the commit they resolved to.
+    pub fn new(groups: &[PatchGroup], commits: &HashMap<PatchSource, String>) -> Result<Self> {
+        let mut sources = Vec::new();
+        for group in groups {
+            let mut source = match &group.source {
+                PatchSource::Git { repo, reference } => Source {
+                    repo: Some(repo.clone()),
+                    reference: Some(Reference {
+                        kind: reference.key(),
+                        name: reference.as_str().to_owned(),
+                    }),
+                    path: None,
+                    commit: commits.get(&group.source).cloned(),
+                    crates: Vec::new(),
+                },
+                PatchSource::Path { path } => Source {
+                    repo: None,
+                    reference: None,
+                    path: Some(path.clone()),
+                    commit: git_output(path, &["rev-parse", "HEAD"])
+                        .ok()
+                        .map(|sha| sha.trim().to_owned()),
+                    crates: Vec::new(),
+                },
+            };
+
+            for entry in &group.entries {
+                let sha256 = match &group.source {
+                    // Bare clones have no files to hash, the commit pins them.
+                    PatchSource::Path { path } if !is_bare_git_repo(path) => {
+                        Some(content_digest(&Path::new(path).join(&entry.subpath))?)
+                    }
+                    PatchSource::Path { .. } | PatchSource::Git { .. } => None,
+                };
+                source.crates.push(Crate {
+                    name: entry.name.clone(),
+                    subpath: entry.subpath.clone(),
+                    version: entry.version.clone(),
+                    sha256,
+                });
+            }
+            sources.push(source);
+        }
+
+        Ok(Provenance {
+            schema_version: SCHEMA_VERSION,
+            generator: format!("bevy-patch {}", env!("CARGO_PKG_VERSION")),
+            retrieved_at: rfc3339(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default(),
+            ),
+            sources,
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)? + "\n";
+        crate::atomic::write(path, &content)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+// Files of the crate in `dir`, skipping `target`, hidden entries and nested packages.
+fn crate_files(dir: &Path, root: bool, files: &mut Vec<std::path::PathBuf>) -> Result<()> {
+    if !root && dir.join("Cargo.toml").is_file() {
+        return Ok(());
+    }
+    let read_dir =
+        std::fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))?;
+    for file in read_dir {
+        let file = file?;
+        let name = file.file_name();
+        if name == "target" || name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if file.file_type()?.is_dir() {
+            crate_files(&file.path(), false, files)?;
+        } else {
+            files.push(file.path());
+        }
+    }
+    Ok(())
+}
+
+// sha256 over each file's `/`-separated relative path and contents, in path order.
+fn content_digest(dir: &Path) -> Result<String> {
+    let mut files = Vec::new();
+    crate_files(dir, true, &mut files)?;
+    let mut files: Vec<(String, std::path::PathBuf)> = files
+        .into_iter()
+        .map(|file| {
+            let relative = file.strip_prefix(dir).unwrap_or(&file);
+            let relative: Vec<_> = relative
+                .components()
+                .map(|part| part.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            (relative.join("/"), file)
+        })
+        .collect();
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    for (relative, file) in &files {
+        let content =
+            std::fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+        hasher.update(relative.as_bytes());
+        hasher.update([0]);
+        hasher.update((content.len() as u64).to_le_bytes());
+        hasher.update(&content);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+// Seconds since the epoch -> `2024-01-31T12:00:00Z`, from Howard Hinnant's `civil_from_days`.
+fn rfc3339(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(1_706_702_399), "2024-01-31T11:59:59Z");
+    }
+}
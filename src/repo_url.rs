@@ -0,0 +1,334 @@
+use std::fmt;
+use std::sync::OnceLock;
+
+use anyhow::Result;
+
+use crate::{azure, encode_path, encode_query};
+
+// Hosts of GitHub Enterprise servers, from `--github-enterprise-host`.
+static ENTERPRISE_HOSTS: OnceLock<Vec<String>> = OnceLock::new();
+
+pub fn set_enterprise_hosts(hosts: &[String]) {
+    let hosts = hosts.iter().map(|host| host.to_ascii_lowercase()).collect();
+    let _ = ENTERPRISE_HOSTS.set(hosts);
+}
+
+fn is_enterprise_host(host: &str) -> bool {
+    ENTERPRISE_HOSTS
+        .get()
+        .is_some_and(|hosts| hosts.iter().any(|known| known.eq_ignore_ascii_case(host)))
+}
+
+// Whether a request to `url` may carry the GitHub token: api.github.com or a configured
+// GitHub Enterprise host, compared exactly.
+pub fn is_github_api_url(url: &str) -> bool {
+    let Some(rest) = url.strip_prefix("https://") else {
+        return false;
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    host.eq_ignore_ascii_case("api.github.com") || is_enterprise_host(host)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Https,
+    // `ssh://` and scp-like `git@host:path` input.
+    Ssh,
+}
+
+// A repository location, normalized from whatever form was passed to `--repo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoUrl {
+    pub scheme: Scheme,
+    pub host: String,
+    // Everything between the host and the repository name, e.g. `group/subgroup` on GitLab
+    // or `org/project/_git` on Azure DevOps.
+    pub owner: String,
+    // Keeps a `.git` suffix given over https, cargo fetches the URL as written.
+    pub repo: String,
+}
+
+impl RepoUrl {
+    pub fn parse(input: &str) -> Result<RepoUrl> {
+        let input = input.trim();
+        if input.is_empty() {
+            anyhow::bail!("Empty repository");
+        }
+
+        let (scheme, rest) = if let Some(rest) = input.strip_prefix("ssh://") {
+            (Scheme::Ssh, rest.to_owned())
+        } else if let Some(rest) = input
+            .strip_prefix("https://")
+            .or_else(|| input.strip_prefix("http://"))
+        {
+            (Scheme::Https, rest.to_owned())
+        } else if let Some(rest) = input.strip_prefix("git@") {
+            // git@github.com:aceeri/bevy.git -> github.com/aceeri/bevy.git
+            (Scheme::Ssh, rest.replacen(':', "/", 1))
+        } else {
+            (Scheme::Https, input.to_owned())
+        };
+
+        // git@host, or https://org@dev.azure.com/org/project/_git/bevy.
+        let rest = match rest.split_once('@') {
+            Some((user, rest)) if !user.contains('/') => rest.to_owned(),
+            _ => rest,
+        };
+        let rest = rest.trim_end_matches('/');
+
+        let mut segments: Vec<&str> = rest.split('/').collect();
+        // aceeri/bevy -> github.com/aceeri/bevy, aceeri -> github.com/aceeri/bevy
+        let host = if segments[0].contains('.') {
+            segments.remove(0).to_owned()
+        } else {
+            "github.com".to_owned()
+        };
+        if segments.len() == 1 && host == "github.com" && !rest.contains('/') {
+            segments.push("bevy");
+        }
+        // The SSH port means nothing over https.
+        let host = match scheme {
+            Scheme::Ssh => host.split(':').next().unwrap_or(&host).to_owned(),
+            Scheme::Https => host,
+        };
+
+        let path = segments.join("/");
+        // org.visualstudio.com/project/_git/bevy -> dev.azure.com/org/project/_git/bevy
+        let (host, path) = match azure::modern_azure_url(&format!("{host}/{path}")) {
+            Some(modern) => {
+                let (host, path) = modern.split_once('/').unwrap_or((&modern, ""));
+                (host.to_owned(), path.to_owned())
+            }
+            None => (host, path),
+        };
+
+        let (owner, repo) = path.rsplit_once('/').unwrap_or(("", &path));
+        let repo = match scheme {
+            Scheme::Ssh => repo.trim_end_matches(".git"),
+            Scheme::Https => repo,
+        };
+
+        Ok(RepoUrl {
+            scheme,
+            host,
+            owner: owner.to_owned(),
+            repo: repo.to_owned(),
+        })
+    }
+
+    fn path(&self) -> String {
+        if self.owner.is_empty() {
+            self.repo.clone()
+        } else {
+            format!("{}/{}", self.owner, self.repo)
+        }
+    }
+
+    pub fn ssh_url(&self) -> String {
+        let path = self.path();
+        if path.ends_with(".git") {
+            format!("ssh://git@{}/{path}", self.host)
+        } else {
+            format!("ssh://git@{}/{path}.git", self.host)
+        }
+    }
+
+    // github.com itself or a configured GitHub Enterprise host, nothing that only looks alike.
+    pub fn is_github(&self) -> bool {
+        self.host.eq_ignore_ascii_case("github.com") || is_enterprise_host(&self.host)
+    }
+
+    // GitHub Enterprise serves the API under `/api/v3` of its own host. Other hosts have no
+    // GitHub API to guess at.
+    pub fn api_repo_url(&self) -> Result<String> {
+        let path = self.path();
+        let path = path.strip_suffix(".git").unwrap_or(&path);
+        if self.host.eq_ignore_ascii_case("github.com") {
+            Ok(format!("https://api.github.com/repos/{path}"))
+        } else if is_enterprise_host(&self.host) {
+            Ok(format!("https://{}/api/v3/repos/{path}", self.host))
+        } else {
+            anyhow::bail!(
+                "{self} isn't on GitHub, pass --github-enterprise-host {} if it's a GitHub Enterprise server",
+                self.host
+            )
+        }
+    }
+
+    // The contents API listing of `crates_root` at `git_ref`.
+    pub fn api_url(&self, crates_root: &str, git_ref: &str) -> Result<String> {
+        Ok(format!(
+            "{}/contents/{}?ref={}",
+            self.api_repo_url()?,
+            encode_path(crates_root),
+            encode_query(git_ref)
+        ))
+    }
+}
+
+// Always the https form, the one API calls and default entries use.
+impl fmt::Display for RepoUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "https://{}/{}", self.host, self.path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalized(input: &str) -> String {
+        RepoUrl::parse(input).unwrap().to_string()
+    }
+
+    #[test]
+    fn bare_username() {
+        assert_eq!(normalized("aceeri"), "https://github.com/aceeri/bevy");
+    }
+
+    #[test]
+    fn owner_and_repo() {
+        assert_eq!(normalized("aceeri/bevy"), "https://github.com/aceeri/bevy");
+    }
+
+    #[test]
+    fn host_without_scheme() {
+        assert_eq!(
+            normalized("github.com/aceeri/bevy"),
+            "https://github.com/aceeri/bevy"
+        );
+    }
+
+    #[test]
+    fn https() {
+        let url = RepoUrl::parse("https://github.com/aceeri/bevy").unwrap();
+        assert_eq!(url.scheme, Scheme::Https);
+        assert_eq!(url.host, "github.com");
+        assert_eq!(url.owner, "aceeri");
+        assert_eq!(url.repo, "bevy");
+    }
+
+    #[test]
+    fn http_is_upgraded() {
+        assert_eq!(
+            normalized("http://github.com/aceeri/bevy"),
+            "https://github.com/aceeri/bevy"
+        );
+    }
+
+    #[test]
+    fn https_keeps_git_suffix() {
+        assert_eq!(
+            normalized("https://github.com/aceeri/bevy.git"),
+            "https://github.com/aceeri/bevy.git"
+        );
+    }
+
+    #[test]
+    fn scp_like_ssh() {
+        let url = RepoUrl::parse("git@github.com:aceeri/bevy.git").unwrap();
+        assert_eq!(url.scheme, Scheme::Ssh);
+        assert_eq!(url.to_string(), "https://github.com/aceeri/bevy");
+    }
+
+    #[test]
+    fn ssh_scheme() {
+        assert_eq!(
+            normalized("ssh://git@github.com/aceeri/bevy.git"),
+            "https://github.com/aceeri/bevy"
+        );
+    }
+
+    #[test]
+    fn ssh_with_port() {
+        assert_eq!(
+            normalized("ssh://git@github.com:2222/aceeri/bevy.git"),
+            "https://github.com/aceeri/bevy"
+        );
+    }
+
+    #[test]
+    fn azure_user_is_dropped() {
+        assert_eq!(
+            normalized("https://org@dev.azure.com/org/project/_git/bevy"),
+            "https://dev.azure.com/org/project/_git/bevy"
+        );
+    }
+
+    #[test]
+    fn legacy_visualstudio_host() {
+        let url = RepoUrl::parse("org.visualstudio.com/project/_git/bevy").unwrap();
+        assert_eq!(url.host, "dev.azure.com");
+        assert_eq!(url.owner, "org/project/_git");
+        assert_eq!(url.repo, "bevy");
+    }
+
+    #[test]
+    fn gitlab_subgroups() {
+        let url = RepoUrl::parse("git@gitlab.com:group/sub/bevy.git").unwrap();
+        assert_eq!(url.owner, "group/sub");
+        assert_eq!(url.to_string(), "https://gitlab.com/group/sub/bevy");
+    }
+
+    #[test]
+    fn trailing_slash() {
+        assert_eq!(
+            normalized("https://github.com/aceeri/bevy/"),
+            "https://github.com/aceeri/bevy"
+        );
+    }
+
+    #[test]
+    fn ssh_url() {
+        let url = RepoUrl::parse("https://github.com/aceeri/bevy").unwrap();
+        assert_eq!(url.ssh_url(), "ssh://git@github.com/aceeri/bevy.git");
+    }
+
+    #[test]
+    fn api_urls() {
+        let url = RepoUrl::parse("https://github.com/aceeri/bevy.git").unwrap();
+        assert_eq!(
+            url.api_url("crates", "main").unwrap(),
+            "https://api.github.com/repos/aceeri/bevy/contents/crates?ref=main"
+        );
+        set_enterprise_hosts(&["github.example.com".to_owned()]);
+        let url = RepoUrl::parse("github.example.com/team/bevy").unwrap();
+        assert_eq!(
+            url.api_repo_url().unwrap(),
+            "https://github.example.com/api/v3/repos/team/bevy"
+        );
+        // Never guessed for hosts that weren't configured.
+        let url = RepoUrl::parse("gitlab.com/team/bevy").unwrap();
+        assert!(url.api_repo_url().is_err());
+    }
+
+    #[test]
+    fn github_hosts_match_exactly() {
+        assert!(
+            RepoUrl::parse("https://github.com/a/b")
+                .unwrap()
+                .is_github()
+        );
+        assert!(
+            !RepoUrl::parse("https://evilgithub.com/x/y")
+                .unwrap()
+                .is_github()
+        );
+        assert!(
+            !RepoUrl::parse("https://github.com.evil.io/x/y")
+                .unwrap()
+                .is_github()
+        );
+        assert!(is_github_api_url("https://api.github.com/repos/a/b"));
+        assert!(!is_github_api_url(
+            "https://api.github.com.evil.io/repos/a/b"
+        ));
+        assert!(!is_github_api_url("https://gitlab.com/api/v3/repos/a/b"));
+        assert!(!is_github_api_url("http://api.github.com/repos/a/b"));
+    }
+
+    #[test]
+    fn empty_is_an_error() {
+        assert!(RepoUrl::parse("  ").is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::GithubError;
+use crate::prompt::PromptRefused;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ErrorFormat {
+    /// `warning:` and `Error:` lines
+    Human,
+    /// One JSON object per line on stderr, like `cargo --message-format json`
+    Json,
+}
+
+static ERROR_FORMAT: OnceLock<ErrorFormat> = OnceLock::new();
+
+pub fn set_error_format(format: ErrorFormat) {
+    let _ = ERROR_FORMAT.set(format);
+}
+
+pub fn is_json() -> bool {
+    ERROR_FORMAT.get() == Some(&ErrorFormat::Json)
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    level: &'a str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'a str>,
+}
+
+// One line whatever the message holds, serde escapes its line breaks.
+fn line(message: &Message) -> String {
+    serde_json::to_string(message)
+        .unwrap_or_else(|_| format!("{}: {}", message.level, message.message))
+}
+
+fn print(message: &Message) {
+    eprintln!("{}", line(message));
+}
+
+pub fn warning(message: &str) {
+    print(&Message {
+        level: "warning",
+        message,
+        code: None,
+    });
+}
+
+// Prints an error with a stable code scripts can match on, picked from its cause chain.
+pub struct JsonReporter<'a>(pub &'a anyhow::Error);
+
+impl JsonReporter<'_> {
+    fn code(&self) -> &'static str {
+        for cause in self.0.chain() {
+            if cause.is::<GithubError>() {
+                return "GITHUB_API_ERROR";
+            }
+            if cause.is::<PromptRefused>() {
+                return "PROMPT_REFUSED";
+            }
+            if cause.is::<reqwest::Error>() {
+                return "NETWORK_ERROR";
+            }
+            if cause.is::<toml::de::Error>() || cause.is::<serde_json::Error>() {
+                return "PARSE_ERROR";
+            }
+            if cause.is::<std::io::Error>() {
+                return "IO_ERROR";
+            }
+        }
+        "ERROR"
+    }
+
+    pub fn print(&self) {
+        print(&Message {
+            level: "error",
+            message: &format!("{:#}", self.0),
+            code: Some(self.code()),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_stay_on_one_line() {
+        let message = "100% of \"bevy\"\r\nfailed\n";
+        let line = line(&Message {
+            level: "error",
+            message,
+            code: Some("ERROR"),
+        });
+        assert!(!line.contains(['\r', '\n']), "{line}");
+        let parsed: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            parsed,
+            serde_json::json!({ "level": "error", "message": message, "code": "ERROR" })
+        );
+    }
+
+    #[test]
+    fn codes_come_from_the_cause_chain() {
+        let err = anyhow::Error::new(std::io::Error::other("disk full"))
+            .context("Failed to write Cargo.toml");
+        assert_eq!(JsonReporter(&err).code(), "IO_ERROR");
+        assert_eq!(JsonReporter(&anyhow::anyhow!("plain")).code(), "ERROR");
+    }
+}
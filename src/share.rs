@@ -0,0 +1,496 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{Cli, Command};
+
+// Everything `import` needs to run the exported command line again.
+#[derive(Serialize, Deserialize)]
+pub struct PatchConfig {
+    // The generating command first, then the `--source` overrides.
+    sources: Vec<SourceConfig>,
+    #[serde(default)]
+    filter: FilterConfig,
+    #[serde(default)]
+    options: OutputOptions,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum SourceConfig {
+    Path {
+        path: String,
+    },
+    Git {
+        repo: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        branch: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tag: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rev: Option<String>,
+        #[serde(default, rename = "ref", skip_serializing_if = "Option::is_none")]
+        git_ref: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        merge_branches: Vec<String>,
+        #[serde(default)]
+        latest_tag: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tag_pattern: Option<String>,
+        #[serde(default)]
+        no_fork_search: bool,
+        #[serde(default)]
+        fuzzy_repo: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        since: Option<String>,
+        #[serde(default)]
+        local: bool,
+        #[serde(default)]
+        from_manifest_version: bool,
+        #[serde(default)]
+        no_expand_rev: bool,
+    },
+    Github {
+        repo: String,
+        pr: u64,
+        #[serde(default)]
+        allow_closed_pr: bool,
+    },
+    Override {
+        #[serde(rename = "crate")]
+        crate_name: String,
+        repo: String,
+        #[serde(rename = "ref")]
+        git_ref: String,
+    },
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+struct FilterConfig {
+    // Embedded so the config doesn't depend on the `--crates` file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crates: Option<Vec<String>>,
+    extra_crates: Vec<String>,
+    renames: Vec<String>,
+    crates_root: String,
+    skip_root: bool,
+    root_only: bool,
+    check_circular: bool,
+    check_deprecations: bool,
+    skip_bevy_internal: bool,
+    internal_patterns: Vec<String>,
+    skip_unpublished: bool,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            crates: None,
+            extra_crates: Vec::new(),
+            renames: Vec::new(),
+            crates_root: "crates".to_owned(),
+            skip_root: false,
+            root_only: false,
+            check_circular: false,
+            check_deprecations: false,
+            skip_bevy_internal: false,
+            internal_patterns: Vec::new(),
+            skip_unpublished: false,
+        }
+    }
+}
+
+// Enum options are kept as their command line values.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct OutputOptions {
+    format: String,
+    add_version: bool,
+    align: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group_by: Option<String>,
+    expand: bool,
+    // `<url>:<crate>,<crate>`, as given to `--extra-registry`.
+    extra_registries: Vec<String>,
+    git_url_style: String,
+    nix_fetcher: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nix_sha256: Option<String>,
+    shell: String,
+    deny: Vec<String>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            format: "toml".to_owned(),
+            add_version: false,
+            align: false,
+            group_by: None,
+            expand: false,
+            extra_registries: Vec::new(),
+            git_url_style: "https".to_owned(),
+            nix_fetcher: "fetchFromGitHub".to_owned(),
+            nix_sha256: None,
+            shell: "sh".to_owned(),
+            deny: Vec::new(),
+        }
+    }
+}
+
+// The part of a config deciding which crates are patched from where, recorded in
+// bevy-patch.lock to tell whether the pins still match the command line.
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub struct Request {
+    sources: Vec<SourceConfig>,
+    #[serde(default)]
+    filter: FilterConfig,
+}
+
+impl Request {
+    pub fn from_cli(cli: &Cli) -> Result<Self> {
+        let config = PatchConfig::from_cli(cli)?;
+        Ok(Self {
+            sources: config.sources,
+            filter: config.filter,
+        })
+    }
+
+    // The command line the request came from, output options aside.
+    pub fn to_args(&self) -> Result<Vec<String>> {
+        if self.filter.crates.is_some() {
+            anyhow::bail!("It was made with a --crates list, which only lives in a file");
+        }
+        PatchConfig {
+            sources: self.sources.clone(),
+            filter: self.filter.clone(),
+            options: OutputOptions::default(),
+        }
+        .to_args(None)
+    }
+}
+
+fn value_name(value: &impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_owned())
+        .unwrap_or_default()
+}
+
+impl PatchConfig {
+    fn from_cli(cli: &Cli) -> Result<Self> {
+        let mut sources = vec![match &cli.command {
+            Command::Path { path } => SourceConfig::Path { path: path.clone() },
+            Command::Git {
+                repo,
+                branch,
+                tag,
+                rev,
+                git_ref,
+                no_fork_search,
+                fuzzy_repo,
+                merge_branches,
+                latest_tag,
+                tag_pattern,
+                since,
+                local,
+                from_manifest_version,
+                no_expand_rev,
+                expand_rev: _,
+            } => SourceConfig::Git {
+                repo: repo.clone(),
+                branch: branch.clone(),
+                tag: tag.clone(),
+                rev: rev.clone(),
+                git_ref: git_ref.clone(),
+                merge_branches: merge_branches.clone(),
+                latest_tag: *latest_tag,
+                tag_pattern: tag_pattern.clone(),
+                no_fork_search: *no_fork_search,
+                fuzzy_repo: *fuzzy_repo,
+                since: since.clone(),
+                local: *local,
+                from_manifest_version: *from_manifest_version,
+                no_expand_rev: *no_expand_rev,
+            },
+            Command::Github {
+                repo,
+                pr,
+                allow_closed_pr,
+            } => SourceConfig::Github {
+                repo: repo.clone(),
+                pr: *pr,
+                allow_closed_pr: *allow_closed_pr,
+            },
+            _ => anyhow::bail!("Only the path, git and github commands can be exported"),
+        }];
+        for spec in &cli.source {
+            let (crate_name, repo, git_ref) = crate::parse_source_override(spec)?;
+            sources.push(SourceConfig::Override {
+                crate_name,
+                repo,
+                git_ref,
+            });
+        }
+
+        Ok(Self {
+            sources,
+            filter: FilterConfig {
+                crates: cli
+                    .crates
+                    .as_deref()
+                    .map(crate::read_crate_list)
+                    .transpose()?,
+                extra_crates: cli.extra_crate.clone(),
+                renames: cli.rename.clone(),
+                crates_root: cli.crates_root.clone(),
+                skip_root: cli.skip_root,
+                root_only: cli.root_only,
+                check_circular: cli.check_circular,
+                check_deprecations: cli.check_deprecations,
+                skip_bevy_internal: cli.skip_bevy_internal,
+                internal_patterns: cli.internal_pattern.clone(),
+                skip_unpublished: cli.skip_unpublished,
+            },
+            options: OutputOptions {
+                format: value_name(&cli.format),
+                add_version: cli.add_version,
+                align: cli.align,
+                group_by: cli.group_by().as_ref().map(value_name),
+                expand: cli.expand,
+                extra_registries: cli
+                    .extra_registry
+                    .iter()
+                    .map(|(url, crates)| format!("{url}:{}", crates.join(",")))
+                    .collect(),
+                git_url_style: value_name(&cli.git_url_style),
+                nix_fetcher: value_name(&cli.nix_fetcher),
+                nix_sha256: cli.nix_sha256.clone(),
+                shell: value_name(&cli.shell),
+                deny: cli.deny.iter().map(value_name).collect(),
+            },
+        })
+    }
+
+    fn to_args(&self, crate_list: Option<&Path>) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+        let mut push = |flag: &str, value: &str| {
+            args.push(flag.to_owned());
+            args.push(value.to_owned());
+        };
+
+        let (command, overrides) = self
+            .sources
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("The config has no sources"))?;
+        for source in overrides {
+            let SourceConfig::Override {
+                crate_name,
+                repo,
+                git_ref,
+            } = source
+            else {
+                anyhow::bail!("Only the first source can be a path, git or github source");
+            };
+            push("--source", &format!("{crate_name}={repo}#{git_ref}"));
+        }
+
+        let filter = &self.filter;
+        if let Some(path) = crate_list {
+            push("--crates", &path.to_string_lossy());
+        }
+        for extra_crate in &filter.extra_crates {
+            push("--extra-crate", extra_crate);
+        }
+        for rename in &filter.renames {
+            push("--rename", rename);
+        }
+        push("--crates-root", &filter.crates_root);
+        for pattern in &filter.internal_patterns {
+            push("--internal-pattern", pattern);
+        }
+
+        let options = &self.options;
+        push("--format", &options.format);
+        push("--git-url-style", &options.git_url_style);
+        push("--nix-fetcher", &options.nix_fetcher);
+        if let Some(sha256) = &options.nix_sha256 {
+            push("--nix-sha256", sha256);
+        }
+        push("--shell", &options.shell);
+        for registry in &options.extra_registries {
+            push("--extra-registry", registry);
+        }
+        if let Some(group_by) = &options.group_by {
+            push("--group-by", group_by);
+        }
+        for deny in &options.deny {
+            push("--deny", deny);
+        }
+
+        let flags = [
+            ("--skip-root", filter.skip_root),
+            ("--root-only", filter.root_only),
+            ("--check-circular", filter.check_circular),
+            ("--check-deprecations", filter.check_deprecations),
+            ("--skip-bevy-internal", filter.skip_bevy_internal),
+            ("--skip-unpublished", filter.skip_unpublished),
+            ("--add-version", options.add_version),
+            ("--align", options.align),
+            ("--expand", options.expand),
+        ];
+        args.extend(
+            flags
+                .into_iter()
+                .filter(|(_, set)| *set)
+                .map(|(flag, _)| flag.to_owned()),
+        );
+
+        match command {
+            SourceConfig::Path { path } => args.extend(["path".to_owned(), path.clone()]),
+            SourceConfig::Git {
+                repo,
+                branch,
+                tag,
+                rev,
+                git_ref,
+                merge_branches,
+                latest_tag,
+                tag_pattern,
+                no_fork_search,
+                fuzzy_repo,
+                since,
+                local,
+                from_manifest_version,
+                no_expand_rev,
+            } => {
+                args.extend(["git".to_owned(), "--repo".to_owned(), repo.clone()]);
+                let specifiers = [
+                    ("--branch", branch),
+                    ("--tag", tag),
+                    ("--rev", rev),
+                    ("--ref", git_ref),
+                    ("--tag-pattern", tag_pattern),
+                    ("--since", since),
+                ];
+                for (flag, value) in specifiers {
+                    if let Some(value) = value {
+                        args.extend([flag.to_owned(), value.clone()]);
+                    }
+                }
+                if !merge_branches.is_empty() {
+                    args.push("--merge-branches".to_owned());
+                    args.extend(merge_branches.iter().cloned());
+                }
+                if *latest_tag {
+                    args.push("--latest-tag".to_owned());
+                }
+                if *no_fork_search {
+                    args.push("--no-fork-search".to_owned());
+                }
+                if *fuzzy_repo {
+                    args.push("--fuzzy-repo".to_owned());
+                }
+                if *local {
+                    args.push("--local".to_owned());
+                }
+                if *from_manifest_version {
+                    args.push("--from-manifest-version".to_owned());
+                }
+                if *no_expand_rev {
+                    args.push("--no-expand-rev".to_owned());
+                }
+            }
+            SourceConfig::Github {
+                repo,
+                pr,
+                allow_closed_pr,
+            } => {
+                args.extend([
+                    "github".to_owned(),
+                    "--repo".to_owned(),
+                    repo.clone(),
+                    "--pr".to_owned(),
+                    pr.to_string(),
+                ]);
+                if *allow_closed_pr {
+                    args.push("--allow-closed-pr".to_owned());
+                }
+            }
+            SourceConfig::Override { .. } => {
+                anyhow::bail!("The first source has to be a path, git or github source")
+            }
+        }
+
+        Ok(args)
+    }
+}
+
+// The command line of an imported config, its crate list lives in a temporary file until dropped.
+pub struct Imported {
+    pub args: Vec<String>,
+    crate_list: Option<PathBuf>,
+}
+
+impl Drop for Imported {
+    fn drop(&mut self) {
+        if let Some(path) = &self.crate_list {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// Prints the config reproducing `bevy-patch <args>`.
+pub fn export(args: &[String]) -> Result<()> {
+    let cli = <Cli as clap::Parser>::try_parse_from(
+        std::iter::once("bevy-patch".to_owned()).chain(args.iter().cloned()),
+    )?;
+    let config = PatchConfig::from_cli(&cli)?;
+    println!("{}", serde_json::to_string_pretty(&config)?);
+    Ok(())
+}
+
+// Checks that a TOML config parses and turns into a command line bevy-patch accepts.
+pub fn check_toml(content: &str) -> Result<()> {
+    let config: PatchConfig = toml::from_str(content)?;
+    let crate_list = config
+        .filter
+        .crates
+        .as_ref()
+        .map(|_| Path::new("crates.txt"));
+    let args = config.to_args(crate_list)?;
+    <Cli as clap::Parser>::try_parse_from(std::iter::once("bevy-patch".to_owned()).chain(args))?;
+    Ok(())
+}
+
+// Reads a config written by `export`, `.toml` files are accepted too.
+pub fn import(path: &Path) -> Result<Imported> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let config: PatchConfig = if path.extension().is_some_and(|ext| ext == "toml") {
+        toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))?
+    } else {
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?
+    };
+
+    let crate_list = match &config.filter.crates {
+        Some(crates) => {
+            let path =
+                std::env::temp_dir().join(format!("bevy-patch-import-{}.txt", std::process::id()));
+            std::fs::write(&path, crates.join("\n"))
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            Some(path)
+        }
+        None => None,
+    };
+    let mut imported = Imported {
+        args: Vec::new(),
+        crate_list,
+    };
+    imported.args = config.to_args(imported.crate_list.as_deref())?;
+    Ok(imported)
+}
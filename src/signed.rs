@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{Github, PatchSource, api_repo_url, encode_path};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RequireSigned {
+    /// Fail when a commit isn't signed or its signature doesn't verify
+    Error,
+    /// Only warn about it
+    Warn,
+}
+
+// GitHub's view of a commit signature, as reported by the commits API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Verification {
+    pub verified: bool,
+    // e.g. `valid`, `unsigned`, `bad_email`, `unknown_key`.
+    pub reason: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signer: Option<String>,
+}
+
+impl Verification {
+    pub fn describe(&self) -> String {
+        match (&self.signer, self.verified) {
+            (Some(signer), true) => format!("verified, signed by {signer}"),
+            (None, true) => "verified".to_owned(),
+            (_, false) => format!("not verified ({})", self.reason),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct GithubCommit {
+    commit: CommitDetails,
+    committer: Option<GithubUser>,
+}
+
+#[derive(Deserialize)]
+struct CommitDetails {
+    committer: GitCommitter,
+    verification: GithubVerification,
+}
+
+#[derive(Deserialize)]
+struct GitCommitter {
+    name: String,
+    email: String,
+}
+
+#[derive(Deserialize)]
+struct GithubVerification {
+    verified: bool,
+    reason: String,
+}
+
+#[derive(Deserialize)]
+struct GithubUser {
+    login: String,
+}
+
+// GitHub checks the signature against the committer's keys, so the committer is the signer.
+fn fetch(repo: &str, sha: &str, github: &Github) -> Result<Verification> {
+    let commit: GithubCommit = github
+        .get(&format!(
+            "{}/commits/{}",
+            api_repo_url(repo)?,
+            encode_path(sha)
+        ))
+        .with_context(|| format!("Failed to fetch the signature of {sha} on {repo}"))?;
+    let verification = commit.commit.verification;
+    let signer = verification.verified.then(|| match commit.committer {
+        Some(user) => user.login,
+        None => format!(
+            "{} <{}>",
+            commit.commit.committer.name, commit.commit.committer.email
+        ),
+    });
+    Ok(Verification {
+        verified: verification.verified,
+        reason: verification.reason,
+        signer,
+    })
+}
+
+// Verifies the commit each git source resolved to, `commits` maps the sources to their SHAs.
+pub fn check(
+    commits: &HashMap<PatchSource, String>,
+    sources: &[&PatchSource],
+    level: RequireSigned,
+    github: &Github,
+) -> Result<HashMap<PatchSource, Verification>> {
+    let mut verifications = HashMap::new();
+    for &source in sources {
+        let PatchSource::Git { repo, .. } = source else {
+            continue;
+        };
+        let Some(sha) = commits.get(source) else {
+            let message = format!("can't check commit signatures of {repo}, only GitHub's");
+            match level {
+                RequireSigned::Error => anyhow::bail!("{message}"),
+                RequireSigned::Warn => warning!("{message}"),
+            }
+            continue;
+        };
+
+        let verification = fetch(repo, sha, github)?;
+        if verification.verified {
+            eprintln!("note: {sha} on {repo} is {}", verification.describe());
+        } else {
+            let message = format!(
+                "commit {sha} on {repo} has no valid signature ({})",
+                verification.reason
+            );
+            match level {
+                RequireSigned::Error => anyhow::bail!("{message}"),
+                RequireSigned::Warn => warning!("{message}"),
+            }
+        }
+        verifications.insert(source.clone(), verification);
+    }
+
+    Ok(verifications)
+}
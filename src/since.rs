@@ -0,0 +1,97 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use serde::Deserialize;
+
+use crate::{Github, api_repo_url, encode_query};
+
+#[derive(Deserialize)]
+struct GithubCommit {
+    sha: String,
+    commit: CommitDetails,
+}
+
+#[derive(Deserialize)]
+struct CommitDetails {
+    committer: CommitDate,
+}
+
+#[derive(Deserialize)]
+struct CommitDate {
+    date: String,
+}
+
+// RFC 3339, or a date or datetime without an offset taken as UTC, or local time with `local`.
+// A bare date means the end of that day, so its commits are included.
+pub fn parse_since(value: &str, local: bool) -> Result<DateTime<Utc>> {
+    if let Ok(instant) = DateTime::parse_from_rfc3339(value) {
+        return Ok(instant.with_timezone(&Utc));
+    }
+    let naive = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_time(NaiveTime::from_hms_opt(23, 59, 59).unwrap()))
+        })
+        .ok_or_else(|| {
+            anyhow::anyhow!("--since {value:?} should be RFC 3339 or look like 2024-01-31")
+        })?;
+
+    if local {
+        let instant = Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .ok_or_else(|| anyhow::anyhow!("--since {value:?} doesn't exist in local time"))?;
+        Ok(instant.with_timezone(&Utc))
+    } else {
+        Ok(naive.and_utc())
+    }
+}
+
+// The newest commit on `branch` at or before `since`, and its commit date.
+pub fn resolve(
+    repo: &str,
+    branch: &str,
+    since: DateTime<Utc>,
+    github: &Github,
+) -> Result<(String, String)> {
+    if since > Utc::now() {
+        anyhow::bail!("--since {} is in the future", since.to_rfc3339());
+    }
+
+    let until = since.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    let commits: Vec<GithubCommit> = github
+        .get(&format!(
+            "{}/commits?sha={}&until={}&per_page=1",
+            api_repo_url(repo)?,
+            encode_query(branch),
+            encode_query(&until)
+        ))
+        .with_context(|| format!("Failed to list the commits of {branch} on {repo}"))?;
+    let commit = commits.into_iter().next().ok_or_else(|| {
+        anyhow::anyhow!(
+            "{branch} on {repo} has no commits at or before {until}, it didn't exist yet"
+        )
+    })?;
+
+    Ok((commit.sha, commit.commit.committer.date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let utc = |value| parse_since(value, false).unwrap().to_rfc3339();
+        assert_eq!(
+            utc("2024-01-31T12:00:00+02:00"),
+            "2024-01-31T10:00:00+00:00"
+        );
+        assert_eq!(utc("2024-01-31T12:00:00Z"), "2024-01-31T12:00:00+00:00");
+        assert_eq!(utc("2024-01-31 12:00:00"), "2024-01-31T12:00:00+00:00");
+        assert_eq!(utc("2024-01-31"), "2024-01-31T23:59:59+00:00");
+        assert!(parse_since("last friday", false).is_err());
+    }
+}
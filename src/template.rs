@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::format::Stats;
+use crate::{PatchGroup, PatchSource, toml_string};
+
+// Renders like `--format toml` with its defaults, a starting point for `--template`.
+pub const DEFAULT_TEMPLATE: &str = r#"[patch.crates-io]
+# Bevy Patch
+{% if summary %}{{ summary }}
+{% endif %}{% for source in sources %}{% if sources | length > 1 %}# {{ source.label }}
+{% endif %}{% for entry in source.entries %}{{ entry.name }} = { {{ entry.fields }} }
+{% endfor %}{% endfor %}"#;
+
+#[derive(Serialize)]
+struct TemplateData {
+    // The `--stats` comment, e.g. `# 47 crates patched from ...`.
+    summary: Option<String>,
+    sources: Vec<Source>,
+    // Every entry of every source, in order.
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+struct Source {
+    label: String,
+    repo: Option<String>,
+    path: Option<String>,
+    // `branch`, `tag` or `rev`.
+    ref_kind: Option<&'static str>,
+    #[serde(rename = "ref")]
+    reference: Option<String>,
+    sha: Option<String>,
+    entries: Vec<Entry>,
+}
+
+#[derive(Clone, Serialize)]
+struct Entry {
+    name: String,
+    subpath: String,
+    version: Option<String>,
+    source: String,
+    // The inside of the inline table, `git = "...", branch = "main"`.
+    fields: String,
+}
+
+fn data(
+    groups: &[PatchGroup],
+    commits: &HashMap<PatchSource, String>,
+    stats: Option<&Stats>,
+) -> TemplateData {
+    let sources: Vec<Source> = groups
+        .iter()
+        .map(|group| {
+            let (repo, path, reference) = match &group.source {
+                PatchSource::Git { repo, reference } => (Some(repo.clone()), None, Some(reference)),
+                PatchSource::Path { path } => (None, Some(path.clone()), None),
+            };
+            let entries = group
+                .entries
+                .iter()
+                .map(|entry| Entry {
+                    name: entry.name.clone(),
+                    subpath: entry.subpath.clone(),
+                    version: entry.version.clone(),
+                    source: group.source.label(),
+                    fields: entry
+                        .fields(&group.source)
+                        .into_iter()
+                        .map(|(key, value)| format!("{key} = {value}"))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                })
+                .collect();
+            Source {
+                label: group.source.label(),
+                repo,
+                path,
+                ref_kind: reference.map(|reference| reference.key()),
+                reference: reference.map(|reference| reference.as_str().to_owned()),
+                sha: commits.get(&group.source).cloned(),
+                entries,
+            }
+        })
+        .collect();
+
+    TemplateData {
+        summary: stats.map(Stats::comment),
+        entries: sources
+            .iter()
+            .flat_map(|source| source.entries.clone())
+            .collect(),
+        sources,
+    }
+}
+
+fn location(name: &str, err: &minijinja::Error) -> String {
+    match err.line() {
+        Some(line) => format!("{name}:{line}"),
+        None => name.to_owned(),
+    }
+}
+
+// Renders `path` with the patch data, the output has to parse as TOML unless `validate` is off.
+pub fn render(
+    path: &Path,
+    groups: &[PatchGroup],
+    commits: &HashMap<PatchSource, String>,
+    stats: Option<&Stats>,
+    validate: bool,
+) -> Result<String> {
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    render_str(
+        &path.display().to_string(),
+        &source,
+        groups,
+        commits,
+        stats,
+        validate,
+    )
+}
+
+fn render_str(
+    name: &str,
+    source: &str,
+    groups: &[PatchGroup],
+    commits: &HashMap<PatchSource, String>,
+    stats: Option<&Stats>,
+    validate: bool,
+) -> Result<String> {
+    let mut env = minijinja::Environment::new();
+    // Quotes a value for TOML, e.g. `{{ entry.version | toml_string }}`.
+    env.add_filter("toml_string", |value: &str| toml_string(value));
+    let rendered = env
+        .template_from_str(source)
+        .and_then(|template| template.render(minijinja::value::Serde(data(groups, commits, stats))))
+        .map_err(|err| {
+            anyhow::anyhow!(
+                "{}: {}",
+                location(name, &err),
+                err.detail().unwrap_or(&err.to_string())
+            )
+        })?;
+
+    if validate && let Err(err) = rendered.parse::<toml::Table>() {
+        anyhow::bail!(
+            "{name} didn't render valid TOML, pass --no-validate if that's intended: {err}"
+        );
+    }
+    Ok(rendered.trim_end().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{Formatter, TomlFormatter};
+    use crate::{GitReference, PatchEntry};
+
+    #[test]
+    fn default_template_matches_toml_output() {
+        let groups = vec![
+            PatchGroup {
+                source: PatchSource::Git {
+                    repo: "https://github.com/bevyengine/bevy".to_owned(),
+                    reference: GitReference::Branch("main".to_owned()),
+                },
+                entries: vec![PatchEntry::new("bevy_ecs", "crates/bevy_ecs")],
+            },
+            PatchGroup {
+                source: PatchSource::Path {
+                    path: "../bevy".to_owned(),
+                },
+                entries: vec![PatchEntry::new("bevy_app", "crates/bevy_app")],
+            },
+        ];
+        let rendered = render_str(
+            "default",
+            DEFAULT_TEMPLATE,
+            &groups,
+            &HashMap::new(),
+            None,
+            true,
+        )
+        .unwrap();
+        assert_eq!(rendered, TomlFormatter::default().format(&groups).unwrap());
+    }
+
+    #[test]
+    fn errors_point_at_the_line() {
+        let err = render_str(
+            "custom.j2",
+            "[patch.crates-io]\n{{ foo(\n",
+            &[],
+            &HashMap::new(),
+            None,
+            true,
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("custom.j2:2:"), "{err}");
+    }
+}
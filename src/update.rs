@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{Github, prompt};
+
+const RELEASES_URL: &str = "https://api.github.com/repos/aceeri/bevy-patch/releases/latest";
+const TARGET: &str = env!("BEVY_PATCH_TARGET");
+
+#[derive(Subcommand)]
+pub enum SelfCommand {
+    /// Replace this executable with the latest release
+    Update {
+        /// Only report whether a newer release exists
+        #[arg(long)]
+        check: bool,
+    },
+}
+
+#[derive(Deserialize)]
+struct GithubRelease {
+    tag_name: String,
+    assets: Vec<GithubAsset>,
+}
+
+#[derive(Deserialize)]
+struct GithubAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl GithubRelease {
+    fn asset(&self, name: &str) -> Result<&GithubAsset> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .ok_or_else(|| anyhow::anyhow!("{} has no {name} artifact", self.tag_name))
+    }
+}
+
+pub fn run(command: &SelfCommand, github: &Github) -> Result<()> {
+    let SelfCommand::Update { check } = command;
+
+    let current = semver::Version::parse(env!("CARGO_PKG_VERSION"))?;
+    let release: GithubRelease = github.get(RELEASES_URL)?;
+    let latest = semver::Version::parse(release.tag_name.trim_start_matches('v'))
+        .with_context(|| format!("Release tag {:?} isn't a version", release.tag_name))?;
+
+    if latest <= current {
+        println!("bevy-patch {current} is up to date");
+        return Ok(());
+    }
+
+    println!("bevy-patch {latest} is available (installed: {current})");
+    if *check {
+        return Ok(());
+    }
+
+    let exe = std::env::current_exe().context("Couldn't locate the running executable")?;
+    if installed_by_cargo(&exe) {
+        println!("Installed with cargo, update with `cargo install bevy-patch --force` instead");
+        return Ok(());
+    }
+
+    if !prompt::confirm(&format!("Replace {} with {latest}?", exe.display()))? {
+        return Ok(());
+    }
+
+    let artifact = format!("bevy-patch-{TARGET}{}", std::env::consts::EXE_SUFFIX);
+    let binary = download(&release.asset(&artifact)?.browser_download_url)?;
+    let sums = download(&release.asset("SHA256SUMS")?.browser_download_url)?;
+    verify_checksum(&artifact, &binary, &String::from_utf8_lossy(&sums))?;
+
+    replace_executable(&exe, &binary)?;
+    println!("Updated to bevy-patch {latest}");
+    Ok(())
+}
+
+fn installed_by_cargo(exe: &Path) -> bool {
+    let cargo_home = std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cargo")));
+    cargo_home.is_some_and(|cargo_home| exe.starts_with(cargo_home.join("bin")))
+}
+
+fn download(url: &str) -> Result<Vec<u8>> {
+    let response = crate::http::client()
+        .get(url)
+        .timeout(Duration::from_secs(120))
+        .header("User-Agent", "bevy-patch")
+        .send()
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("Failed to download {url}"))?;
+    Ok(response.bytes()?.to_vec())
+}
+
+fn verify_checksum(artifact: &str, binary: &[u8], sums: &str) -> Result<()> {
+    // `sha256sum` format: `<hex>  <file name>`, binary mode prefixes the name with `*`.
+    let expected = sums
+        .lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(_, name)| name.trim().trim_start_matches('*') == artifact)
+        .map(|(hash, _)| hash.to_ascii_lowercase())
+        .ok_or_else(|| anyhow::anyhow!("SHA256SUMS has no entry for {artifact}"))?;
+
+    let actual: String = Sha256::digest(binary)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    if actual != expected {
+        anyhow::bail!("Checksum mismatch for {artifact}: expected {expected}, got {actual}");
+    }
+
+    Ok(())
+}
+
+// Windows won't let the running executable be overwritten, but it can be renamed,
+// so move it aside and put the new one in its place.
+fn replace_executable(exe: &Path, binary: &[u8]) -> Result<()> {
+    let new = exe.with_extension("new");
+    let old = exe.with_extension("old");
+
+    std::fs::write(&new, binary).with_context(|| format!("Failed to write {}", new.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&new, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    let _ = std::fs::remove_file(&old);
+    std::fs::rename(exe, &old).with_context(|| format!("Failed to move {}", exe.display()))?;
+    if let Err(err) = std::fs::rename(&new, exe) {
+        let _ = std::fs::rename(&old, exe);
+        return Err(err).with_context(|| format!("Failed to replace {}", exe.display()));
+    }
+    // Still locked on Windows, it gets cleaned up by the next update.
+    let _ = std::fs::remove_file(&old);
+
+    Ok(())
+}
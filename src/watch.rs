@@ -0,0 +1,364 @@
+use std::io::BufRead;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+
+use crate::cargo_config::cargo_home;
+use crate::outdated::{branch_head, read_pins};
+use crate::{Github, atomic};
+
+// `30s`, `15m`, `1h`, plain numbers are seconds.
+pub fn parse_interval(interval: &str) -> Result<Duration, String> {
+    let (number, unit) = match interval.find(|c: char| !c.is_ascii_digit()) {
+        Some(split) => interval.split_at(split),
+        None => (interval, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("{interval:?} should look like 30s, 15m or 1h"))?;
+    let seconds = match unit {
+        "s" => number,
+        "m" => number * 60,
+        "h" => number * 60 * 60,
+        _ => return Err(format!("{interval:?} should look like 30s, 15m or 1h")),
+    };
+    Ok(Duration::from_secs(seconds))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Clean {
+    /// `cargo clean -p` every patched crate after rewriting pins
+    Always,
+    /// Only the crates whose pin was rewritten
+    Changed,
+    Never,
+}
+
+// What `--write` does once a branch moved.
+pub struct Update<'a> {
+    pub on_update: Option<&'a str>,
+    pub clean: Clean,
+    // `cargo fetch`, `Some(true)` passes `--locked`.
+    pub prefetch: Option<bool>,
+}
+
+// The package behind each patch, `package = "..."` renames the key.
+fn package_name(key: &str, patch: &dyn toml_edit::TableLike) -> String {
+    patch
+        .get("package")
+        .and_then(|package| package.as_str())
+        .unwrap_or(key)
+        .to_owned()
+}
+
+// Replaces the `rev = <old>` strings of the patches of `repo` in place, every other byte of the
+// manifest is kept, so are its line endings and final newline. Also returns the packages moved.
+fn replace_revs(content: &str, repo: &str, old: &str, new: &str) -> Result<(String, Vec<String>)> {
+    let manifest = toml_edit::Document::parse(content)?;
+    let patches = manifest
+        .get("patch")
+        .and_then(|patch| patch.get("crates-io"))
+        .and_then(|patches| patches.as_table_like())
+        .ok_or_else(|| anyhow::anyhow!("no [patch.crates-io]"))?;
+
+    let mut spans = Vec::new();
+    let mut moved = Vec::new();
+    for (key, patch) in patches.iter() {
+        let Some(patch) = patch.as_table_like() else {
+            continue;
+        };
+        let pinned = patch.get("git").and_then(|git| git.as_str()) == Some(repo)
+            && patch.get("rev").and_then(|rev| rev.as_str()) == Some(old);
+        if pinned && let Some(span) = patch.get("rev").and_then(|rev| rev.span()) {
+            spans.push(span);
+            moved.push(package_name(key, patch));
+        }
+    }
+
+    // Back to front, so the earlier spans stay valid.
+    spans.sort_by_key(|span| span.start);
+    let mut content = content.to_owned();
+    for span in spans.into_iter().rev() {
+        content.replace_range(span, &toml_edit::Value::from(new).to_string());
+    }
+    Ok((content, moved))
+}
+
+fn patched_crates(manifest_path: &Path) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(manifest_path)
+        .with_context(|| format!("Failed to read {}", manifest_path.display()))?;
+    let manifest = toml_edit::Document::parse(content)
+        .with_context(|| format!("Failed to parse {}", manifest_path.display()))?;
+    let patches = manifest
+        .get("patch")
+        .and_then(|patch| patch.get("crates-io"))
+        .and_then(|patches| patches.as_table_like());
+    Ok(patches
+        .into_iter()
+        .flat_map(|patches| patches.iter())
+        .filter_map(|(key, patch)| Some(package_name(key, patch.as_table_like()?)))
+        .collect())
+}
+
+fn rewrite_revs(manifest_path: &Path, repo: &str, old: &str, new: &str) -> Result<Vec<String>> {
+    let mut moved = Vec::new();
+    atomic::edit(manifest_path, |content| {
+        let (content, replaced) = replace_revs(content, repo, old, new)?;
+        moved = replaced;
+        Ok(content)
+    })?;
+    Ok(moved)
+}
+
+// Streams cargo's output and adds up its `Removed <n> files` lines.
+fn cargo_clean(manifest_path: &Path, crates: &[String]) -> Result<()> {
+    let mut command = std::process::Command::new("cargo");
+    command
+        .arg("clean")
+        .arg("--manifest-path")
+        .arg(manifest_path);
+    for name in crates {
+        command.args(["-p", name]);
+    }
+    let mut child = command
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .context("Failed to run cargo clean")?;
+
+    let mut removed = 0;
+    let stderr = child.stderr.take().expect("stderr is piped");
+    for line in std::io::BufReader::new(stderr).lines() {
+        let line = line?;
+        eprintln!("{line}");
+        if let Some(files) = line
+            .trim()
+            .strip_prefix("Removed ")
+            .and_then(|rest| rest.split_whitespace().next())
+            .and_then(|count| count.parse::<u64>().ok())
+        {
+            removed += files;
+        }
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        warning!("cargo clean exited with {status}");
+    }
+    eprintln!(
+        "note: removed {removed} files of {} crates: {}",
+        crates.len(),
+        crates.join(", ")
+    );
+    Ok(())
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let Ok(read_dir) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    read_dir
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(ty) if ty.is_dir() => dir_size(&entry.path()),
+            _ => entry.metadata().map(|m| m.len()).unwrap_or_default(),
+        })
+        .sum()
+}
+
+// What cargo keeps of fetched sources, git databases and checkouts and downloaded crates.
+fn fetched_size() -> u64 {
+    let Some(home) = cargo_home() else {
+        return 0;
+    };
+    ["git", "registry/cache"]
+        .iter()
+        .map(|dir| dir_size(&home.join(dir)))
+        .sum()
+}
+
+// Downloads the patched sources while the network is still there, for `cargo build --offline`.
+fn cargo_fetch(manifest_path: &Path, locked: bool) -> Result<()> {
+    let before = fetched_size();
+    let mut command = std::process::Command::new("cargo");
+    command
+        .arg("fetch")
+        .arg("--manifest-path")
+        .arg(manifest_path);
+    if locked {
+        command.arg("--locked");
+    }
+    let mut child = command
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .context("Failed to run cargo fetch")?;
+
+    let mut auth_failed = false;
+    let stderr = child.stderr.take().expect("stderr is piped");
+    for line in std::io::BufReader::new(stderr).lines() {
+        let line = line?;
+        eprintln!("{line}");
+        let lower = line.to_ascii_lowercase();
+        auth_failed |= lower.contains("authenticat") || lower.contains("permission denied");
+    }
+
+    let status = child.wait()?;
+    if !status.success() {
+        if auth_failed {
+            anyhow::bail!(
+                "cargo fetch couldn't authenticate, private forks need git credentials \
+                 or net.git-fetch-with-cli (see --setup-git-cli). The manifest was still updated"
+            );
+        }
+        anyhow::bail!("cargo fetch exited with {status}, the manifest was still updated");
+    }
+    eprintln!(
+        "note: fetched {} KB into {}",
+        fetched_size().saturating_sub(before).div_ceil(1024),
+        cargo_home().unwrap_or_default().display()
+    );
+    Ok(())
+}
+
+fn run_command(command: &str) -> Result<()> {
+    let status = if cfg!(windows) {
+        std::process::Command::new("cmd")
+            .args(["/C", command])
+            .status()
+    } else {
+        std::process::Command::new("sh")
+            .args(["-c", command])
+            .status()
+    }
+    .with_context(|| format!("Failed to run {command:?}"))?;
+
+    if !status.success() {
+        warning!("{command:?} exited with {status}");
+    }
+    Ok(())
+}
+
+// Checks every pin once, returns whether any of them moved. Pins are only rewritten with `update`.
+fn poll(
+    manifest_path: &Path,
+    branch: &str,
+    update: Option<&Update>,
+    github: &Github,
+) -> Result<bool> {
+    let mut moved = false;
+    let mut changed = Vec::new();
+    for (repo, pinned) in read_pins(manifest_path)? {
+        let head = branch_head(&repo, branch, github)?.sha;
+        if head.starts_with(&pinned) {
+            continue;
+        }
+
+        moved = true;
+        if update.is_some() {
+            changed.extend(rewrite_revs(manifest_path, &repo, &pinned, &head)?);
+            eprintln!("note: moved {repo} from {pinned} to {head}");
+        } else {
+            eprintln!("note: {branch} of {repo} moved to {head}, pass --write to update the pin");
+        }
+    }
+
+    let Some(update) = update.filter(|_| moved) else {
+        return Ok(moved);
+    };
+    if let Some(locked) = update.prefetch {
+        cargo_fetch(manifest_path, locked)?;
+    }
+    let clean = match update.clean {
+        Clean::Always => patched_crates(manifest_path)?,
+        Clean::Changed => changed,
+        Clean::Never => Vec::new(),
+    };
+    if !clean.is_empty() {
+        cargo_clean(manifest_path, &clean)?;
+    }
+    if let Some(command) = update.on_update {
+        run_command(command)?;
+    }
+    Ok(moved)
+}
+
+pub fn run(
+    manifest_path: &Path,
+    branch: &str,
+    interval: Duration,
+    update: Option<&Update>,
+    once: bool,
+    github: &Github,
+) -> Result<()> {
+    if once {
+        poll(manifest_path, branch, update, github)?;
+        return Ok(());
+    }
+
+    loop {
+        // A network blip shouldn't end the watch, the next poll tries again.
+        if let Err(err) = poll(manifest_path, branch, update, github) {
+            warning!("{err:#}");
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPO: &str = "https://github.com/bevyengine/bevy";
+
+    #[test]
+    fn crlf_is_kept() {
+        let content = "[package]\r\nname = \"game\"\r\n\r\n[patch.crates-io]\r\n\
+            bevy = { git = \"https://github.com/bevyengine/bevy\", rev = \"aaa\" }\r\n\
+            bevy_ecs = { git = \"https://github.com/bevyengine/bevy\", rev = \"aaa\" }\r\n";
+        assert_eq!(
+            replace_revs(content, REPO, "aaa", "bbb").unwrap().0,
+            content.replace("\"aaa\"", "\"bbb\"")
+        );
+    }
+
+    #[test]
+    fn missing_final_newline_is_kept() {
+        let content = "[patch.crates-io]\nbevy = { git = \"https://github.com/bevyengine/bevy\", rev = \"aaa\" }";
+        assert_eq!(
+            replace_revs(content, REPO, "aaa", "bbb").unwrap().0,
+            "[patch.crates-io]\nbevy = { git = \"https://github.com/bevyengine/bevy\", rev = \"bbb\" }"
+        );
+    }
+
+    #[test]
+    fn mixed_line_endings_are_kept() {
+        let content = "[package]\r\nname = \"game\"\n# comment\r\n\n[patch.crates-io.bevy]\r\n\
+            git = \"https://github.com/bevyengine/bevy\"\nrev = \"aaa\"\r\n\r\n";
+        let replaced = replace_revs(content, REPO, "aaa", "bbb").unwrap().0;
+        let at = content.find("\"aaa\"").unwrap();
+        assert_eq!(replaced.as_bytes()[..at], content.as_bytes()[..at]);
+        assert_eq!(&replaced[at..at + 5], "\"bbb\"");
+        assert_eq!(replaced.as_bytes()[at + 5..], content.as_bytes()[at + 5..]);
+    }
+
+    #[test]
+    fn comments_are_kept() {
+        let content = "[patch.crates-io]\n\
+            # TODO: remove after bevy 0.16\n\
+            bevy = { git = \"https://github.com/bevyengine/bevy\", rev = \"aaa\" } # pinned for CI\n\
+            bevy_ecs = { git = \"https://github.com/bevyengine/bevy\", rev = \"aaa\" }\n";
+        let replaced = replace_revs(content, REPO, "aaa", "bbb").unwrap().0;
+        assert!(replaced.contains("# TODO: remove after bevy 0.16\n"));
+        assert!(replaced.contains("rev = \"bbb\" } # pinned for CI\n"));
+    }
+
+    #[test]
+    fn other_repositories_are_untouched() {
+        let content = "[patch.crates-io]\nbevy = { git = \"https://github.com/aceeri/bevy\", rev = \"aaa\" }\n";
+        assert_eq!(
+            replace_revs(content, REPO, "aaa", "bbb").unwrap().0,
+            content
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+pub fn read(path: &Path) -> Result<toml::Table> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+// The closest manifest with a `[workspace]` table, this one or one further up.
+pub fn workspace_root(manifest_path: &Path) -> Option<PathBuf> {
+    let dir = std::path::absolute(manifest_path).ok()?;
+    dir.ancestors()
+        .skip(1)
+        .map(|dir| dir.join("Cargo.toml"))
+        .find(|manifest| read(manifest).is_ok_and(|manifest| manifest.contains_key("workspace")))
+}
+
+pub struct WorkspaceMembers;
+
+impl WorkspaceMembers {
+    // The manifests of the workspace's packages: the root's own `[package]` and every
+    // directory the `members` globs match, less the `exclude`d ones.
+    pub fn from_cargo_toml(root: &Path) -> Result<Vec<PathBuf>> {
+        let manifest = read(root)?;
+        let dir = root.parent().unwrap_or(Path::new(""));
+        let workspace = manifest
+            .get("workspace")
+            .ok_or_else(|| anyhow::anyhow!("{} has no [workspace] table", root.display()))?;
+        let patterns = |key| {
+            workspace
+                .get(key)
+                .and_then(|patterns| patterns.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|pattern| pattern.as_str())
+        };
+        let excluded: Vec<PathBuf> = patterns("exclude").map(|path| dir.join(path)).collect();
+
+        let mut members = Vec::new();
+        if manifest.contains_key("package") {
+            members.push(root.to_owned());
+        }
+        for pattern in patterns("members") {
+            let pattern = dir.join(pattern);
+            let matches = glob::glob(&pattern.to_string_lossy())
+                .with_context(|| format!("{} isn't a valid members glob", pattern.display()))?;
+            for member in matches {
+                let member = member?;
+                let manifest = member.join("Cargo.toml");
+                if manifest.is_file()
+                    && !excluded.iter().any(|excluded| member.starts_with(excluded))
+                {
+                    members.push(manifest);
+                }
+            }
+        }
+        members.sort();
+        members.dedup();
+        Ok(members)
+    }
+}
+
+// The root of the workspace `manifest_path` is a member of, when it isn't the root itself.
+pub fn member_of(manifest_path: &Path) -> Option<PathBuf> {
+    let manifest = read(manifest_path).ok()?;
+    if manifest.contains_key("workspace") {
+        return None;
+    }
+    let root = workspace_root(manifest_path)?;
+    let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_owned());
+    let manifest_path = canonical(manifest_path);
+    WorkspaceMembers::from_cargo_toml(&root)
+        .ok()?
+        .iter()
+        .any(|member| canonical(member) == manifest_path)
+        .then_some(root)
+}
+
+// cargo only reads `[patch]` from the workspace root, entries written to a member do nothing.
+pub fn refuse_member(manifest_path: &Path) -> Result<()> {
+    match member_of(manifest_path) {
+        Some(root) => anyhow::bail!(
+            "{} is a member of the workspace at {}, cargo ignores [patch] outside the \
+             workspace root, pass --cargo-toml {}",
+            manifest_path.display(),
+            root.display(),
+            root.display()
+        ),
+        None => Ok(()),
+    }
+}